@@ -0,0 +1,117 @@
+//! Timing in the style of criterion: warm up, pick a number of iterations
+//! per sample that fits the time budget, and report the spread of the
+//! samples along with the change from a saved baseline.
+
+use std::{
+    collections::HashMap,
+    fmt, fs, hint, io,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+const SAMPLES: u32 = 10;
+
+pub(crate) struct Harness {
+    filter: Option<String>,
+    time: Duration,
+    baseline: HashMap<String, f64>,
+    /// Median nanoseconds per iteration of each benchmark run so far.
+    results: Vec<(String, f64)>,
+}
+
+impl Harness {
+    pub(crate) fn new(filter: Option<String>, time: Duration) -> Harness {
+        Harness { filter, time, baseline: HashMap::new(), results: Vec::new() }
+    }
+
+    /// Whether the benchmark passes the filter, so that expensive setup can
+    /// be skipped otherwise.
+    pub(crate) fn wants(&self, name: &str) -> bool {
+        self.filter.as_ref().is_none_or(|it| name.contains(it.as_str()))
+    }
+
+    pub(crate) fn bench<T>(&mut self, name: &str, mut f: impl FnMut() -> T) {
+        if !self.wants(name) {
+            return;
+        }
+
+        let warm_up = self.time / 10;
+        let start = Instant::now();
+        let mut iters = 0u64;
+        while iters == 0 || start.elapsed() < warm_up {
+            hint::black_box(f());
+            iters += 1;
+        }
+        let per_iter = start.elapsed().as_secs_f64() / iters as f64;
+        let per_sample = self.time.as_secs_f64() / SAMPLES as f64;
+        let iters = ((per_sample / per_iter) as u64).max(1);
+        // Slow benchmarks take fewer samples rather than blow the budget.
+        let n_samples = if iters == 1 {
+            ((self.time.as_secs_f64() / per_iter) as u32).clamp(3, SAMPLES)
+        } else {
+            SAMPLES
+        };
+
+        let mut samples: Vec<f64> = (0..n_samples)
+            .map(|_| {
+                let start = Instant::now();
+                for _ in 0..iters {
+                    hint::black_box(f());
+                }
+                start.elapsed().as_nanos() as f64 / iters as f64
+            })
+            .collect();
+        samples.sort_by(f64::total_cmp);
+        let median = samples[samples.len() / 2];
+
+        let mut line = format!(
+            "{name:<28}{:>12}  [{} .. {}]",
+            Nanos(median).to_string(),
+            Nanos(samples[0]),
+            Nanos(samples[samples.len() - 1]),
+        );
+        if let Some(&base) = self.baseline.get(name) {
+            line.push_str(&format!("  {:+.1}%", (median / base - 1.0) * 100.0));
+        }
+        println!("{line}");
+        self.results.push((name.to_string(), median));
+    }
+
+    /// Loads medians written by [`Harness::save`] to compare against.
+    pub(crate) fn load_baseline(&mut self, path: &Path) -> io::Result<()> {
+        let text = fs::read_to_string(path)?;
+        for line in text.lines() {
+            let parsed =
+                line.split_once(' ').and_then(|(name, nanos)| Some((name, nanos.parse().ok()?)));
+            let Some((name, nanos)) = parsed else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid line `{line}`"),
+                ));
+            };
+            self.baseline.insert(name.to_string(), nanos);
+        }
+        Ok(())
+    }
+
+    /// Writes the medians, one `name nanoseconds` line per benchmark.
+    pub(crate) fn save(&self, path: &Path) -> io::Result<()> {
+        let text: String =
+            self.results.iter().map(|(name, nanos)| format!("{name} {nanos}\n")).collect();
+        fs::write(path, text)
+    }
+}
+
+struct Nanos(f64);
+
+impl fmt::Display for Nanos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (value, unit) = match self.0 {
+            it if it < 1e3 => (it, "ns"),
+            it if it < 1e6 => (it / 1e3, "µs"),
+            it if it < 1e9 => (it / 1e6, "ms"),
+            it => (it / 1e9, "s"),
+        };
+        write!(f, "{value:.2}{unit}")
+    }
+}
@@ -0,0 +1,165 @@
+//! The benchmark suite, from vector math to full renders, to measure
+//! performance work against a stable baseline:
+//!
+//! ```text
+//! $ cargo run -r -p bench -- --save before.txt
+//! $ cargo run -r -p bench -- --baseline before.txt bvh/
+//! ```
+//!
+//! This is a binary rather than a `benches/` target because `cargo bench`
+//! always builds with `panic = "unwind"`, and [`mem::Mem`] requires
+//! `panic = "abort"` to allocate arrays.
+
+mod harness;
+mod scenes;
+
+use std::{hint, io, path::PathBuf, time::Duration};
+
+use bvh::{BoundingBox, Bvh};
+use geom::{cross, dot, v64, Ray};
+use mem::Mem;
+use render::{
+    backend::{Backend, Builtin, Intersect, RayKind},
+    rgb,
+};
+use scene::Scene;
+
+use crate::harness::Harness;
+
+/// Runs the benchmarks whose names contain the filter, all by default.
+#[derive(argh::FromArgs)]
+struct Args {
+    /// only run benchmarks with names containing this, like `bvh/` or `1m`
+    #[argh(positional)]
+    filter: Option<String>,
+
+    /// seconds to spend measuring each benchmark, 2 by default
+    #[argh(option, default = "2.0")]
+    time: f64,
+
+    /// file with medians saved by an earlier run, to print the changes
+    #[argh(option)]
+    baseline: Option<PathBuf>,
+
+    /// save the medians to this file, for a later --baseline
+    #[argh(option)]
+    save: Option<PathBuf>,
+}
+
+fn main() -> io::Result<()> {
+    let args: Args = argh::from_env();
+    let mut h = Harness::new(args.filter, Duration::from_secs_f64(args.time));
+    if let Some(path) = &args.baseline {
+        h.load_baseline(path)?;
+    }
+
+    vector(&mut h);
+    triangle(&mut h);
+    for (label, n_triangles) in [("10k", 10_000), ("100k", 100_000), ("1m", 1_000_000)] {
+        terrain(&mut h, label, n_triangles);
+    }
+    render(&mut h, "render/spheres", include_str!("../../../scenes/spheres.crt"));
+    render(&mut h, "render/utah-small", include_str!("../../../scenes/utah-small.crt"));
+    if h.wants("render/terrain-100k") {
+        render(&mut h, "render/terrain-100k", &scenes::terrain(100_000));
+    }
+
+    if let Some(path) = &args.save {
+        h.save(path)?;
+    }
+    Ok(())
+}
+
+/// Operations over 1024 vectors at a time.
+fn vector(h: &mut Harness) {
+    let vs: Vec<v64> = (0..1024)
+        .map(|i| i as f64)
+        .map(|i| v64((i * 0.37).sin(), (i * 0.11).cos() + 1.5, i / 1024.0 - 0.5))
+        .collect();
+    let vs = hint::black_box(&vs);
+    h.bench("vector/dot", || vs.windows(2).map(|it| dot(it[0], it[1])).sum::<f64>());
+    h.bench("vector/cross", || vs.windows(2).fold(v64::ZERO, |acc, it| acc + cross(it[0], it[1])));
+    h.bench("vector/to_unit", || vs.iter().fold(v64::ZERO, |acc, &it| acc + it.to_unit()));
+}
+
+/// A single ray against a one-triangle mesh, so mostly the intersection
+/// test itself. The miss is inside the bounding box.
+fn triangle(h: &mut Harness) {
+    if !(h.wants("triangle/hit") || h.wants("triangle/miss")) {
+        return;
+    }
+    let crt = scenes::triangle();
+    let mut mem = vec![0; mem_size(&crt)];
+    Mem::with(&mut mem, |mem| {
+        let scene = Scene::parse(mem, &crt).unwrap_or_else(|err| panic!("{err}"));
+        let backend = Builtin::build(mem, &scene).unwrap();
+        let hit = Ray::from_to(v64(0.0, 0.0, -10.0), v64(0.0, 0.0, 0.0));
+        let miss = Ray::from_to(v64(0.0, 0.0, -10.0), v64(4.0, 4.0, 0.0));
+        for (name, ray) in [("triangle/hit", hit), ("triangle/miss", miss)] {
+            h.bench(name, || {
+                backend
+                    .closest_hit(&scene, hint::black_box(&ray), f64::INFINITY, RayKind::Camera)
+                    .is_some()
+            });
+        }
+    });
+}
+
+/// Building a BVH over a mesh of `n_triangles`, and tracing a grid of 1024
+/// rays through it.
+fn terrain(h: &mut Harness, label: &str, n_triangles: usize) {
+    let [build, traverse] = ["build", "traverse"].map(|it| format!("bvh/{it}/{label}"));
+    if !(h.wants(&build) || h.wants(&traverse)) {
+        return;
+    }
+    let crt = scenes::terrain(n_triangles);
+    let mut mem = vec![0; mem_size(&crt)];
+    Mem::with(&mut mem, |mem| {
+        let scene = Scene::parse(mem, &crt).unwrap_or_else(|err| panic!("{err}"));
+        let mesh = &scene.meshes[0];
+        h.bench(&build, || {
+            let free = mem.free();
+            mem.with_scratch(free, |_, scratch| {
+                let mut bbs = mesh.iter().map(|it| BoundingBox::from_points(&it.v));
+                Bvh::build(scratch, &mut bbs).map(drop)
+            })
+        });
+
+        let backend = Builtin::build(mem, &scene).unwrap();
+        let size = scenes::TERRAIN_SIZE * 0.9;
+        let rays: Vec<Ray> = (0..32 * 32)
+            .map(|i| {
+                let x = size * ((i % 32) as f64 / 31.0 - 0.5);
+                let z = size * ((i / 32) as f64 / 31.0 - 0.5);
+                Ray::from_to(v64(x / 2.0, 60.0, z / 2.0 - 90.0), v64(x, 0.0, z))
+            })
+            .collect();
+        h.bench(&traverse, || {
+            let hits = rays
+                .iter()
+                .map(|ray| backend.closest_hit(&scene, ray, f64::INFINITY, RayKind::Camera));
+            hits.filter(Option::is_some).count()
+        });
+    });
+}
+
+/// The whole of `render::render`, parsing included, at 160x120 on one
+/// thread.
+fn render(h: &mut Harness, name: &str, crt: &str) {
+    if !h.wants(name) {
+        return;
+    }
+    let mut mem = vec![0; mem_size(crt)];
+    let mut pixels = vec![rgb::Color::default(); 160 * 120];
+    let opts = render::Options::default();
+    h.bench(name, || {
+        let mut buf = rgb::Buf::new([160, 120], &mut pixels);
+        render::render(crt, &mut mem, &opts, &render::serial, &mut buf).unwrap()
+    });
+}
+
+/// Same headroom as `crt --mem auto`.
+fn mem_size(crt: &str) -> usize {
+    let estimate = render::mem_estimate(crt);
+    estimate + estimate / 8 + 1024
+}
@@ -0,0 +1,64 @@
+//! Procedural scenes for the benchmarks, sized by the number of triangles.
+
+use std::fmt::Write;
+
+use geom::v64;
+
+/// Side of the square [`terrain`] covers, centered at the origin.
+pub(crate) const TERRAIN_SIZE: f64 = 100.0;
+
+/// A rippled height field of about `n_triangles` triangles with smooth
+/// normals, seen from above at an angle.
+pub(crate) fn terrain(n_triangles: usize) -> String {
+    let n = ((n_triangles / 2) as f64).sqrt().round().max(1.0) as u32;
+    let height = |x: f64, z: f64| 4.0 * (x * 0.2).sin() * (z * 0.2).cos();
+
+    let mut res = header(v64(0.0, 60.0, -90.0));
+    res.push_str("mesh {\n");
+    res.push_str("    material {\n        color #cccccc\n        diffuse 1\n    }\n");
+    res.push_str("    data {\n");
+    let mut normals = String::new();
+    for i in 0..=n {
+        for j in 0..=n {
+            let x = TERRAIN_SIZE * (i as f64 / n as f64 - 0.5);
+            let z = TERRAIN_SIZE * (j as f64 / n as f64 - 0.5);
+            let dx = 0.8 * (x * 0.2).cos() * (z * 0.2).cos();
+            let dz = -0.8 * (x * 0.2).sin() * (z * 0.2).sin();
+            writeln!(res, "        v {}", v64(x, height(x, z), z)).unwrap();
+            writeln!(normals, "        vn {}", v64(-dx, 1.0, -dz).to_unit()).unwrap();
+        }
+    }
+    res.push_str(&normals);
+    let idx = |i: u32, j: u32| i * (n + 1) + j + 1;
+    for i in 0..n {
+        for j in 0..n {
+            let [a, b, c, d] = [idx(i, j), idx(i + 1, j), idx(i + 1, j + 1), idx(i, j + 1)];
+            writeln!(res, "        f {a}/{a} {b}/{b} {c}/{c}").unwrap();
+            writeln!(res, "        f {a}/{a} {c}/{c} {d}/{d}").unwrap();
+        }
+    }
+    res.push_str("    }\n}\n");
+    res
+}
+
+/// A single triangle facing the camera, covering the middle of the image.
+pub(crate) fn triangle() -> String {
+    let mut res = header(v64(0.0, 0.0, -10.0));
+    res.push_str("mesh {\n");
+    res.push_str("    material {\n        color #cccccc\n        diffuse 1\n    }\n");
+    res.push_str("    data {\n");
+    res.push_str("        v -5,-5,0\n        v 5,-5,0\n        v 0,5,0\n");
+    res.push_str("        vn 0,0,-1\n");
+    res.push_str("        f 1/1 2/1 3/1\n");
+    res.push_str("    }\n}\n");
+    res
+}
+
+fn header(camera: v64) -> String {
+    let mut res = String::new();
+    res.push_str("background #000000\n\n");
+    writeln!(res, "camera {{\n    pos {camera}\n    look_at 0,0,0").unwrap();
+    res.push_str("    up 0,-1,0\n    focus 750\n    dim 800x600\n}\n\n");
+    res.push_str("light {\n    pos -20,30,-20\n    color #dddddd\n}\n\n");
+    res
+}
@@ -1,26 +1,34 @@
 #![no_std]
+use core::fmt;
+
 use geom::{v64, Ray};
 use mem::{Mem, Oom};
 
 #[derive(Default)]
 pub struct Bvh<'m> {
+    /// The box around all faces, the only one no split holds.
+    root: BoundingBox,
     splits: &'m mut [BvhSplit],
-    leaves: &'m mut [BvhLeaf],
+    /// The face of each leaf. Leaves have no boxes of their own, their
+    /// parents hold them.
+    leaves: &'m mut [u32],
 }
 
+/// Holds the boxes of both children rather than its own, axis by axis, so
+/// that a visit tests both children against the ray at once, reading memory
+/// which is already in the cache. Two boxes don't fit into one cache line,
+/// the alignment keeps them within two adjacent ones.
 #[derive(Default)]
+#[repr(C, align(64))]
 struct BvhSplit {
+    /// `lo[axis][child]`
+    lo: [[f64; 2]; 3],
+    /// `hi[axis][child]`
+    hi: [[f64; 2]; 3],
     children: [u32; 2],
-    bb: BoundingBox,
     axis: u8,
 }
 
-#[derive(Default)]
-struct BvhLeaf {
-    face: u32,
-    bb: BoundingBox,
-}
-
 #[derive(Default, Clone, Copy)]
 pub struct BoundingBox {
     lo: v64,
@@ -30,65 +38,260 @@ pub struct BoundingBox {
 impl<'m> Bvh<'m> {
     pub fn build(
         mem: &mut Mem<'m>,
-        input: &mut (dyn ExactSizeIterator<Item = BoundingBox>),
+        input: &mut dyn ExactSizeIterator<Item = BoundingBox>,
     ) -> Result<Bvh<'m>, Oom> {
         let free_mem = mem.free();
         mem.with_scratch(free_mem / 2, |mem, scratch| {
             let bbs: &mut [BoundingBox] =
-                scratch.alloc_array(input.len(), |_| input.next().unwrap())?;
+                scratch.alloc_array(input.len(), |_| input.next().unwrap().or_empty())?;
             let faces = scratch.alloc_array(bbs.len(), |i| i as u32)?;
             let mut res = Bvh::default();
             if !faces.is_empty() {
-                let (root, n_splits, n_leaves) = bvh_recur(scratch, faces, bbs)?;
-                res.splits = mem.alloc_array_default(n_splits as usize)?;
-                res.leaves = mem.alloc_array_default(n_leaves as usize)?;
-                fill(&mut res, &mut (0, 0), root);
+                res.splits = mem.alloc_array_default(faces.len() - 1)?;
+                res.leaves = mem.alloc_array_default(faces.len())?;
+                fill(&mut res, faces, bbs);
             }
             Ok(res)
         })
     }
 
-    pub fn intersect(&self, ray: &Ray, max_t: &mut f64, intersect: &mut dyn FnMut(u32, &mut f64)) {
-        let mut work = [0u32; 64];
+    /// Updates bounding boxes after faces have moved, keeping the structure of
+    /// the tree. Much cheaper than rebuilding, and as good as long as the faces
+    /// move together.
+    pub fn refit(&mut self, face_bb: &dyn Fn(u32) -> BoundingBox) {
+        let node_bb = |splits: &[BvhSplit], idx: u32| {
+            if idx & LEAF_BIT == LEAF_BIT {
+                face_bb(self.leaves[(idx & !LEAF_BIT) as usize]).or_empty()
+            } else {
+                splits[idx as usize].bounds()
+            }
+        };
+        // Splits are stored in pre-order, so children come after parents.
+        for i in (0..self.splits.len()).rev() {
+            for (child, idx) in self.splits[i].children.into_iter().enumerate() {
+                let bb = node_bb(self.splits, idx);
+                self.splits[i].set_child_bb(child, bb);
+            }
+        }
+        if !self.leaves.is_empty() {
+            let root = if self.splits.is_empty() { LEAF_BIT } else { 0 };
+            self.root = node_bb(self.splits, root);
+        }
+    }
+
+    /// The box around all faces, `None` if there are none.
+    pub fn bounds(&self) -> Option<BoundingBox> {
+        if self.leaves.is_empty() {
+            return None;
+        }
+        Some(self.root)
+    }
+
+    /// Memory needed to build a BVH over `n_faces` faces: the size of the
+    /// result and the size of the temporary scratch space.
+    pub fn mem_estimate(n_faces: usize) -> (usize, usize) {
+        if n_faces == 0 {
+            return (0, 0);
+        }
+        let res = array_size::<BvhSplit>(n_faces - 1) + array_size::<u32>(n_faces);
+        let scratch = array_size::<BoundingBox>(n_faces) + array_size::<u32>(n_faces);
+        (res, scratch)
+    }
+
+    /// Calls `intersect` for the faces whose boxes `ray` hits closer than
+    /// `max_t`, which the callback lowers as it finds hits. Returns the number
+    /// of nodes visited, a measure of how well the tree fits the ray.
+    pub fn intersect(
+        &self,
+        ray: &Ray,
+        max_t: &mut f64,
+        intersect: &mut dyn FnMut(u32, &mut f64),
+    ) -> u32 {
+        // Nodes whose boxes the ray hits, with the distance to the box, as
+        // `max_t` can drop below it by the time the node is popped.
+        let mut work = [(0u32, 0.0); STACK_SIZE];
         let mut w = 0;
+        let mut visited = 0;
         if self.leaves.is_empty() {
-            return;
-        } else if self.splits.is_empty() {
-            work[w] = LEAF_BIT;
-            w += 1;
-        } else {
-            work[w] = 0;
-            w += 1;
+            return visited;
         }
+        visited += 1;
+        let Some(t) = self.root.intersection(ray, *max_t) else { return visited };
+        work[w] = (if self.splits.is_empty() { LEAF_BIT } else { 0 }, t);
+        w += 1;
         while w > 0 {
             w -= 1;
-            let idx = work[w];
-            let is_leaf = idx & LEAF_BIT == LEAF_BIT;
-            let idx = (idx & !LEAF_BIT) as usize;
-
-            let bb = if is_leaf { &self.leaves[idx].bb } else { &self.splits[idx].bb };
-            if !bb.is_intersected(ray, *max_t) {
+            let (idx, t) = work[w];
+            if t > *max_t {
                 continue;
             }
-            if is_leaf {
-                let leaf = &self.leaves[idx];
-                intersect(leaf.face, max_t);
-            } else {
-                let split = &self.splits[idx];
-                let (c1, c2) = if ray.dir().xyz()[split.axis as usize] < 0.0 {
-                    (split.children[0], split.children[1])
-                } else {
-                    (split.children[1], split.children[0])
-                };
+            if idx & LEAF_BIT == LEAF_BIT {
+                intersect(self.leaves[(idx & !LEAF_BIT) as usize], max_t);
+                continue;
+            }
+            let split = &self.splits[idx as usize];
+            visited += 2;
+            let ts = split.child_intersections(ray, *max_t);
+            let order = if ray.dir().xyz()[split.axis as usize] < 0.0 { [0, 1] } else { [1, 0] };
+            for child in order {
+                let Some(t) = ts[child] else { continue };
                 // Quite sketchy! Ideally, we'd allocate per-thread max-size
                 // buffer and pass that around, but requires quite some coding.
-                if w + 2 <= work.len() {
-                    work[w] = c1;
-                    work[w + 1] = c2;
-                    w += 2;
+                if w < work.len() {
+                    work[w] = (split.children[child], t);
+                    w += 1;
+                }
+            }
+        }
+        visited
+    }
+
+    /// [`Bvh::intersect`] for a batch of rays, like the shadow rays of a
+    /// pixel, with `max_t[i]` for `rays[i]`. `intersect` also gets the index
+    /// of the ray. Rays go in eight passes by the signs of their directions:
+    /// rays of one pass visit children in the same order, so they tend to
+    /// find the nodes the previous ray left in the cache. Returns the total
+    /// number of nodes visited.
+    pub fn intersect_stream(
+        &self,
+        rays: &[Ray],
+        max_t: &mut [f64],
+        intersect: &mut dyn FnMut(usize, u32, &mut f64),
+    ) -> u32 {
+        assert_eq!(rays.len(), max_t.len());
+        let octant = |ray: &Ray| {
+            let [x, y, z] = ray.dir().xyz().map(|it| u8::from(it < 0.0));
+            x | y << 1 | z << 2
+        };
+        let mut visited = 0;
+        for pass in 0..8 {
+            for (i, ray) in rays.iter().enumerate().filter(|(_, ray)| octant(ray) == pass) {
+                visited += self
+                    .intersect(ray, &mut max_t[i], &mut |face, max_t| intersect(i, face, max_t));
+            }
+        }
+        visited
+    }
+
+    /// Checks that the tree is well-formed: parents contain their children,
+    /// splits are in pre-order, each of the `n_faces` faces is in exactly one
+    /// leaf, and no leaf is too deep for [`Bvh::intersect`]. Needs `n_faces`
+    /// bits of `scratch`.
+    pub fn validate(&self, scratch: &mut Mem<'_>, n_faces: usize) -> Result<(), Invalid> {
+        if self.leaves.len() != n_faces {
+            return Err(Invalid::LeafCount { leaves: self.leaves.len(), faces: n_faces });
+        }
+        if n_faces == 0 {
+            return Ok(());
+        }
+        if self.splits.len() != n_faces - 1 {
+            return Err(Invalid::SplitCount { splits: self.splits.len(), faces: n_faces });
+        }
+        let seen =
+            scratch.alloc_array_default::<u64>(n_faces.div_ceil(64)).map_err(|_| Invalid::Oom)?;
+        let root = if self.splits.is_empty() { LEAF_BIT } else { 0 };
+        let mut next = (0, 0);
+        self.validate_node(root, self.root, 0, &mut next, seen)
+    }
+
+    /// `bb` is the box of the node, as its parent holds it.
+    fn validate_node(
+        &self,
+        idx: u32,
+        bb: BoundingBox,
+        depth: usize,
+        next: &mut (usize, usize),
+        seen: &mut [u64],
+    ) -> Result<(), Invalid> {
+        // The deepest leaf shares the stack with a pending sibling from
+        // each level above it.
+        if depth >= STACK_SIZE {
+            return Err(Invalid::TooDeep);
+        }
+        let node = NodeIdx(idx);
+        let proper = bb.or_empty();
+        if proper.lo != bb.lo || proper.hi != bb.hi {
+            return Err(Invalid::InvalidBox(node));
+        }
+        if idx & LEAF_BIT == LEAF_BIT {
+            let i = (idx & !LEAF_BIT) as usize;
+            if i != next.1 {
+                return Err(Invalid::OutOfOrder(node));
+            }
+            next.1 += 1;
+            let face = self.leaves[i];
+            if face as usize >= self.leaves.len() {
+                return Err(Invalid::FaceOutOfRange(face));
+            }
+            let (word, bit) = (face as usize / 64, 1 << (face % 64));
+            if seen[word] & bit != 0 {
+                return Err(Invalid::DuplicateFace(face));
+            }
+            seen[word] |= bit;
+        } else {
+            let i = idx as usize;
+            if i != next.0 {
+                return Err(Invalid::OutOfOrder(node));
+            }
+            next.0 += 1;
+            let split = &self.splits[i];
+            for (child, child_bb) in
+                split.children.into_iter().zip([0, 1].map(|it| split.child_bb(it)))
+            {
+                let in_range = if child & LEAF_BIT == LEAF_BIT {
+                    ((child & !LEAF_BIT) as usize) < self.leaves.len()
+                } else {
+                    (child as usize) < self.splits.len()
+                };
+                if !in_range {
+                    return Err(Invalid::OutOfRange(NodeIdx(child)));
+                }
+                if !bb.contains(&child_bb) {
+                    return Err(Invalid::NotContained { parent: node, child: NodeIdx(child) });
                 }
+                self.validate_node(child, child_bb, depth + 1, next, seen)?;
             }
         }
+        Ok(())
+    }
+}
+
+/// A broken invariant, found by [`Bvh::validate`].
+#[derive(Debug, displaydoc::Display)]
+pub enum Invalid {
+    /// {leaves} leaves for {faces} faces
+    LeafCount { leaves: usize, faces: usize },
+    /// {splits} splits for {faces} faces
+    SplitCount { splits: usize, faces: usize },
+    /// {0} points past the end
+    OutOfRange(NodeIdx),
+    /// {0} is out of pre-order, or shared between parents
+    OutOfOrder(NodeIdx),
+    /// the box of {0} has NaN or infinite coordinates
+    InvalidBox(NodeIdx),
+    /// the box of {parent} doesn't contain its child {child}
+    NotContained { parent: NodeIdx, child: NodeIdx },
+    /// face {0} is out of range
+    FaceOutOfRange(u32),
+    /// face {0} is in more than one leaf
+    DuplicateFace(u32),
+    /// a leaf is deeper than the traversal stack
+    TooDeep,
+    /// not enough memory to check the faces
+    Oom,
+}
+
+/// A split or a leaf, as stored in [`BvhSplit::children`].
+#[derive(Debug, Clone, Copy)]
+pub struct NodeIdx(u32);
+
+impl fmt::Display for NodeIdx {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 & LEAF_BIT == LEAF_BIT {
+            write!(f, "leaf {}", self.0 & !LEAF_BIT)
+        } else {
+            write!(f, "split {}", self.0)
+        }
     }
 }
 
@@ -101,6 +304,40 @@ impl BoundingBox {
         BoundingBox { lo: vs, hi: vs }
     }
 
+    /// Contains nothing, and leaves any box it's united with as is.
+    const EMPTY: BoundingBox = BoundingBox {
+        lo: v64(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+        hi: v64(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+    };
+
+    fn is_empty(&self) -> bool {
+        !(self.lo.x <= self.hi.x && self.lo.y <= self.hi.y && self.lo.z <= self.hi.z)
+    }
+
+    /// The box itself if it's a proper one, or [`BoundingBox::EMPTY`] for
+    /// the NaN and infinite coordinates of faces which can't be hit anyway.
+    fn or_empty(self) -> BoundingBox {
+        let finite = [self.lo, self.hi].iter().flat_map(|it| it.xyz()).all(f64::is_finite);
+        if finite && !self.is_empty() {
+            self
+        } else {
+            BoundingBox::EMPTY
+        }
+    }
+
+    pub fn lo(&self) -> v64 {
+        self.lo
+    }
+    pub fn hi(&self) -> v64 {
+        self.hi
+    }
+
+    /// The box grown by `margin` on all sides.
+    pub fn grow(self, margin: f64) -> BoundingBox {
+        let m = v64(margin, margin, margin);
+        BoundingBox { lo: self.lo - m, hi: self.hi + m }
+    }
+
     fn diag(&self) -> v64 {
         self.hi - self.lo
     }
@@ -125,7 +362,17 @@ impl BoundingBox {
         BoundingBox { lo: zip(f64::min, self.lo, other.lo), hi: zip(f64::max, self.hi, other.hi) }
     }
 
-    fn is_intersected(&self, ray: &Ray, mut max_t: f64) -> bool {
+    fn contains(&self, other: &BoundingBox) -> bool {
+        let [lo, hi, other_lo, other_hi] =
+            [self.lo, self.hi, other.lo, other.hi].map(|it| it.xyz());
+        (0..3).all(|axis| lo[axis] <= other_lo[axis] && other_hi[axis] <= hi[axis])
+    }
+
+    /// Where `ray` enters the box, if it does so closer than `max_t`.
+    fn intersection(&self, ray: &Ray, mut max_t: f64) -> Option<f64> {
+        if self.is_empty() {
+            return None;
+        }
         let mut min_t: f64 = 0.0;
         for axis in 0..3 {
             let inv_dir = 1.0 / ray.dir().xyz()[axis];
@@ -136,73 +383,209 @@ impl BoundingBox {
             min_t = min_t.max(t_near);
             max_t = max_t.min(t_far);
             if max_t < min_t {
-                return false;
+                return None;
             }
         }
-        true
+        Some(min_t)
     }
 }
 
-fn bvh_recur<'s>(
-    scratch: &mut Mem<'s>,
-    faces: &mut [u32],
-    bbs: &[BoundingBox],
-) -> Result<(&'s mut Node<'s>, u32, u32), Oom> {
-    if faces.len() == 1 {
-        let face = faces[0];
-        let bb = bbs[face as usize];
-        let res = scratch.alloc(Node::Leaf { face, bb })?;
-        return Ok((res, 0, 1));
-    }
-    let bb = faces
-        .iter()
-        .map(|&i| bbs[i as usize].center())
-        .map(BoundingBox::from_point)
-        .reduce(BoundingBox::union)
-        .unwrap();
-    let axis = bb.longest_axis();
-    let key = |i: u32| bbs[i as usize].center().xyz()[axis as usize];
-    faces.sort_by(|&i, &j| key(i).total_cmp(&key(j)));
-    let mid = faces.len() / 2;
-    let (left, right) = faces.split_at_mut(mid);
-
-    let (left, ls, ll) = bvh_recur(scratch, left, bbs)?;
-    let (right, rs, rl) = bvh_recur(scratch, right, bbs)?;
-    let bb = left.bounding_box().union(*right.bounding_box());
-    let res = scratch.alloc(Node::Split { children: [left, right], bb, axis })?;
-    Ok((res, ls + rs + 1, ll + rl))
-}
+impl BvhSplit {
+    fn child_bb(&self, child: usize) -> BoundingBox {
+        let [lo, hi] = [self.lo, self.hi].map(|it| v64(it[0][child], it[1][child], it[2][child]));
+        BoundingBox { lo, hi }
+    }
 
-enum Node<'s> {
-    Split { children: [&'s mut Node<'s>; 2], bb: BoundingBox, axis: u8 },
-    Leaf { face: u32, bb: BoundingBox },
-}
+    fn set_child_bb(&mut self, child: usize, bb: BoundingBox) {
+        for (axis, (lo, hi)) in bb.lo.xyz().into_iter().zip(bb.hi.xyz()).enumerate() {
+            self.lo[axis][child] = lo;
+            self.hi[axis][child] = hi;
+        }
+    }
 
-impl<'s> Node<'s> {
-    fn bounding_box(&self) -> &BoundingBox {
-        match self {
-            Node::Split { bb, .. } | Node::Leaf { bb, .. } => bb,
+    fn bounds(&self) -> BoundingBox {
+        self.child_bb(0).union(self.child_bb(1))
+    }
+
+    /// [`BoundingBox::intersection`] for both children, axis by axis.
+    fn child_intersections(&self, ray: &Ray, max_t: f64) -> [Option<f64>; 2] {
+        let mut min_t = [0.0f64; 2];
+        let mut max_t = [max_t; 2];
+        // Empty boxes have `lo` above `hi`, and rays would go through them.
+        let mut empty = [false; 2];
+        for axis in 0..3 {
+            let inv_dir = 1.0 / ray.dir().xyz()[axis];
+            let origin = ray.origin().xyz()[axis];
+            for child in 0..2 {
+                let (lo, hi) = (self.lo[axis][child], self.hi[axis][child]);
+                let t1 = (lo - origin) * inv_dir;
+                let t2 = (hi - origin) * inv_dir;
+                min_t[child] = min_t[child].max(t1.min(t2));
+                max_t[child] = max_t[child].min(t1.max(t2));
+                empty[child] |= hi < lo;
+            }
         }
+        [0, 1].map(|child| (!empty[child] && min_t[child] <= max_t[child]).then_some(min_t[child]))
     }
 }
 
+fn array_size<T>(n: usize) -> usize {
+    n * size_of::<T>() + align_of::<T>() - 1
+}
+
 const LEAF_BIT: u32 = 1u32.rotate_right(1);
 
-fn fill(res: &mut Bvh<'_>, idx: &mut (usize, usize), node: &Node<'_>) -> u32 {
-    match node {
-        Node::Split { children, bb, axis } => {
-            let i = idx.0;
-            idx.0 += 1;
-            let l = fill(res, idx, &children[0]);
-            let r = fill(res, idx, &children[1]);
-            res.splits[i] = BvhSplit { children: [l, r], bb: *bb, axis: *axis };
-            i as u32
-        }
-        Node::Leaf { face, bb } => {
-            let i = idx.1;
-            idx.1 += 1;
-            res.leaves[i] = BvhLeaf { face: *face, bb: *bb };
-            (i as u32) | LEAF_BIT
+/// Nodes [`Bvh::intersect`] can have pending at once, which limits the depth.
+const STACK_SIZE: usize = 64;
+
+/// Builds the tree top-down, splitting the faces at the median along the
+/// longest axis of their centers. Splits go in pre-order and leaves left to
+/// right. Median splits keep the depth within `log2` of the number of faces,
+/// so a fixed stack holds the pending work, without recursion however the
+/// faces are laid out.
+fn fill(res: &mut Bvh<'_>, faces: &mut [u32], bbs: &[BoundingBox]) {
+    let (mut n_splits, mut n_leaves) = (0, 0);
+    // Ranges of `faces` to place, and the child of a split to link them from.
+    let mut work = [(0, 0, None::<(usize, usize)>); STACK_SIZE];
+    work[0] = (0, faces.len(), None);
+    let mut w = 1;
+    while w > 0 {
+        w -= 1;
+        let (lo, hi, parent) = work[w];
+        let faces = &mut faces[lo..hi];
+        let (idx, bb) = if let [face] = *faces {
+            res.leaves[n_leaves] = face;
+            n_leaves += 1;
+            ((n_leaves - 1) as u32 | LEAF_BIT, bbs[face as usize])
+        } else {
+            let bb = faces.iter().map(|&i| bbs[i as usize]).reduce(BoundingBox::union).unwrap();
+            let centers = faces
+                .iter()
+                .map(|&i| bbs[i as usize])
+                .filter(|it| !it.is_empty())
+                .map(|it| BoundingBox::from_point(it.center()))
+                .reduce(BoundingBox::union);
+            // Faces with the same center, like copies of a face or faces
+            // around a common point, can still be told apart by size.
+            let (axis, by_size) = match centers {
+                Some(centers) if centers.diag() != v64::ZERO => (centers.longest_axis(), false),
+                _ => (bb.longest_axis(), true),
+            };
+            let key = |i: u32| {
+                let bb = bbs[i as usize];
+                let key = if by_size { bb.diag() } else { bb.center() };
+                // Empty boxes go last, out of the way of the others.
+                if bb.is_empty() {
+                    f64::INFINITY
+                } else {
+                    key.xyz()[axis as usize]
+                }
+            };
+            faces.sort_by(|&i, &j| key(i).total_cmp(&key(j)));
+            res.splits[n_splits] = BvhSplit { axis, ..BvhSplit::default() };
+
+            // The left half goes on top, so that it comes next.
+            let mid = lo + faces.len() / 2;
+            work[w] = (mid, hi, Some((n_splits, 1)));
+            work[w + 1] = (lo, mid, Some((n_splits, 0)));
+            w += 2;
+            n_splits += 1;
+            ((n_splits - 1) as u32, bb)
+        };
+        match parent {
+            Some((split, child)) => {
+                res.splits[split].children[child] = idx;
+                res.splits[split].set_child_bb(child, bb);
+            }
+            None => res.root = bb,
         }
     }
 }
+
+#[test]
+fn test_degenerate_boxes() {
+    let ray = Ray::new(v64(0.0, 0.0, -5.0), v64(0.0, 0.0, 1.0));
+    let nan = BoundingBox { lo: v64(-1.0, -1.0, -1.0), hi: v64(1.0, f64::NAN, 1.0) };
+    let infinite = BoundingBox::from_points(&[v64(-1.0, -1.0, -1.0), v64(f64::INFINITY, 1.0, 1.0)]);
+    for bb in [nan, infinite] {
+        let bb = bb.or_empty();
+        assert!(bb.is_empty() && bb.intersection(&ray, f64::INFINITY).is_none());
+    }
+
+    let flat = BoundingBox::from_points(&[v64(-1.0, -1.0, 0.0), v64(1.0, 1.0, 0.0)]).or_empty();
+    assert!(!flat.is_empty() && flat.intersection(&ray, f64::INFINITY) == Some(5.0));
+    let united = flat.union(BoundingBox::EMPTY);
+    assert!(united.lo == flat.lo && united.hi == flat.hi);
+}
+
+#[test]
+fn test_validate() {
+    let unit = |x: f64| BoundingBox::from_points(&[v64(x, 0.0, 0.0), v64(x + 1.0, 1.0, 1.0)]);
+    let ordinary = [0.0, 4.0, 2.0, 1.0, 3.0, 6.0, 5.0].map(unit);
+    let duplicate = [unit(0.0); 7];
+    let point = BoundingBox::from_point(v64::ZERO);
+    let flat = BoundingBox::from_points(&[v64(-1.0, -1.0, 0.0), v64(1.0, 1.0, 0.0)]);
+    let nan = BoundingBox { lo: v64(0.0, 0.0, 0.0), hi: v64(1.0, f64::NAN, 1.0) };
+    let inverted = BoundingBox { lo: v64(1.0, 1.0, 1.0), hi: v64::ZERO };
+    let degenerate = [point, point, flat, nan, inverted, unit(0.0)];
+    let mut buf = [0u8; 1 << 14];
+    for input in [&ordinary[..], &duplicate, &degenerate, &[]] {
+        Mem::with(&mut buf, |mem| {
+            let bvh = Bvh::build(mem, &mut input.iter().copied()).unwrap();
+            let free = mem.free();
+            mem.with_scratch(free, |_, scratch| bvh.validate(scratch, input.len())).unwrap();
+        });
+    }
+
+    Mem::with(&mut buf, |mem| {
+        let mut bvh = Bvh::build(mem, &mut ordinary.iter().copied()).unwrap();
+        let mut validate = |bvh: &Bvh<'_>| {
+            let free = mem.free();
+            mem.with_scratch(free, |_, scratch| bvh.validate(scratch, ordinary.len()))
+        };
+        validate(&bvh).unwrap();
+        let root = bvh.root;
+        bvh.root = unit(0.0);
+        assert!(matches!(validate(&bvh), Err(Invalid::NotContained { .. })));
+        bvh.root = root;
+        bvh.leaves[0] = bvh.leaves[1];
+        assert!(matches!(validate(&bvh), Err(Invalid::DuplicateFace(_))));
+    });
+}
+
+#[test]
+fn test_intersect() {
+    // SplitMix64, as the rest of the crates use it.
+    let mut state = 0u64;
+    let mut random = move || {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        (z ^ (z >> 31)) as f64 / u64::MAX as f64 * 2.0 - 1.0
+    };
+    let mut point = |scale: f64| v64(random(), random(), random()) * scale;
+    let faces: [BoundingBox; 200] = core::array::from_fn(|_| {
+        let lo = point(10.0);
+        BoundingBox::from_points(&[lo, lo + point(3.0)])
+    });
+    let mut buf = [0u8; 1 << 16];
+    Mem::with(&mut buf, |mem| {
+        let bvh = Bvh::build(mem, &mut faces.iter().copied()).unwrap();
+        for _ in 0..500 {
+            // Through the middle of the boxes, so that most rays hit some.
+            let ray = Ray::from_to(point(20.0), point(5.0));
+            let expected =
+                faces.iter().filter_map(|it| it.intersection(&ray, f64::INFINITY)).reduce(f64::min);
+            let mut max_t = f64::INFINITY;
+            let mut closest = None;
+            bvh.intersect(&ray, &mut max_t, &mut |face, max_t| {
+                if let Some(t) = faces[face as usize].intersection(&ray, *max_t) {
+                    *max_t = t;
+                    closest = Some(t);
+                }
+            });
+            assert_eq!(closest, expected);
+        }
+    });
+}
@@ -0,0 +1,94 @@
+//! `crt bake`: the lighting of the meshes of a scene as vertex colors, for
+//! viewers and game engines which show meshes without tracing rays.
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use render::rgb;
+use scene::{Color, Scene};
+
+/// Writes all meshes as a single ASCII PLY mesh, with colors as computed by
+/// `render::Renderer::bake`, `colors[mesh][face]`.
+///
+/// PLY has a color per vertex rather than per corner, so a vertex is written
+/// once for each of the colors of its corners, which keeps shadow edges sharp.
+/// Vertices of no face are left out.
+pub(crate) fn write_ply(
+    w: &mut dyn Write,
+    scene: &Scene<'_>,
+    colors: &[Vec<[Color; 3]>],
+) -> io::Result<()> {
+    let mut vertices = Vec::new();
+    let mut faces = Vec::new();
+    for (mesh, colors) in scene.meshes.iter().zip(colors) {
+        let mut written = HashMap::new();
+        for (face, corners) in mesh.f.iter().zip(colors) {
+            let v = face.v();
+            faces.push([0, 1, 2].map(|i| {
+                let rgb::Color { r, g, b } = rgb::quantize(&corners[i], [0, 0], rgb::Dither::None);
+                *written.entry((v[i], [r, g, b])).or_insert_with(|| {
+                    vertices.push((mesh.v[v[i] as usize] + mesh.offset, [r, g, b]));
+                    vertices.len() - 1
+                })
+            }));
+        }
+    }
+
+    writeln!(w, "ply")?;
+    writeln!(w, "format ascii 1.0")?;
+    writeln!(w, "element vertex {}", vertices.len())?;
+    for p in ["x", "y", "z"] {
+        writeln!(w, "property double {p}")?;
+    }
+    for p in ["red", "green", "blue"] {
+        writeln!(w, "property uchar {p}")?;
+    }
+    writeln!(w, "element face {}", faces.len())?;
+    writeln!(w, "property list uchar uint vertex_indices")?;
+    writeln!(w, "end_header")?;
+
+    for (v, [r, g, b]) in vertices {
+        let [x, y, z] = v.xyz();
+        writeln!(w, "{x} {y} {z} {r} {g} {b}")?;
+    }
+    for [a, b, c] in faces {
+        writeln!(w, "3 {a} {b} {c}")?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_write_ply() {
+    let crt = concat!(
+        "mesh { data {\n",
+        "v 0,0,0\nv 1,0,0\nv 0,1,0\nv 1,1,0\nvn 0,0,1\n",
+        "f 1/1 2/1 3/1\nf 2/1 4/1 3/1\n",
+        "} }\n",
+    );
+    let mut mem = [0; 1 << 12];
+    mem::Mem::with(&mut mem, |mem| {
+        let scene = Scene::parse(mem, crt).unwrap();
+        let [dark, light] = [0.0, 1.0].map(|it| Color::new(it, it, it));
+        // The faces agree on the color of the first shared vertex, but not
+        // on that of the second one.
+        let colors = vec![vec![[dark, dark, light], [dark, dark, dark]]];
+        let mut out = Vec::new();
+        write_ply(&mut out, &scene, &colors).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let body = out.split_once("end_header\n").unwrap().1;
+        assert_eq!(
+            body.lines().collect::<Vec<_>>(),
+            [
+                "0 0 0 0 0 0",
+                "1 0 0 0 0 0",
+                "0 1 0 255 255 255",
+                "1 1 0 0 0 0",
+                "0 1 0 0 0 0",
+                "3 0 1 2",
+                "3 1 3 4",
+            ]
+        );
+    });
+}
@@ -0,0 +1,50 @@
+use std::{sync::OnceLock, time::Instant};
+
+/// Timings of repeated renders of the same scene.
+pub(crate) struct Bench {
+    runs: Vec<render::Stats>,
+}
+
+impl Bench {
+    pub(crate) fn new() -> Bench {
+        Bench { runs: Vec::new() }
+    }
+
+    pub(crate) fn add(&mut self, stats: render::Stats) {
+        self.runs.push(stats);
+    }
+
+    pub(crate) fn print(&self) {
+        let runs = format!("{} runs", self.runs.len());
+        println!("{runs:<10}{:>12}{:>12}", "min", "median");
+        self.print_row("parse", |it| it.parse);
+        self.print_row("bvh build", |it| it.bvh_build);
+        self.print_row("trace", |it| it.trace);
+        let rays = self.runs.first().map_or(0, |it| it.rays);
+        let trace = self.median(|it| it.trace);
+        let rays_per_second = rays as f64 / (trace as f64 / 1e9);
+        println!("{rays} rays, {:.2} Mrays/s", rays_per_second / 1e6);
+    }
+
+    fn print_row(&self, name: &str, f: impl Fn(&render::Stats) -> u64) {
+        let min = self.runs.iter().map(&f).min().unwrap_or(0);
+        let median = self.median(f);
+        println!("{name:<10}{:>10.3}ms{:>10.3}ms", ms(min), ms(median));
+    }
+
+    fn median(&self, f: impl Fn(&render::Stats) -> u64) -> u64 {
+        let mut values: Vec<u64> = self.runs.iter().map(f).collect();
+        values.sort_unstable();
+        values.get(values.len() / 2).copied().unwrap_or(0)
+    }
+}
+
+/// Nanoseconds since the first call, for [`render::Options::clock`].
+pub(crate) fn clock() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+fn ms(nanos: u64) -> f64 {
+    nanos as f64 / 1e6
+}
@@ -0,0 +1,40 @@
+//! Ctrl-C handling. The first SIGINT asks the render to stop and write out what
+//! it has so far, the second one kills the process as usual.
+
+use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn install() {
+    #[cfg(unix)]
+    unsafe {
+        sys::signal(sys::SIGINT, on_sigint);
+    }
+}
+
+/// Stops the render as if Ctrl-C was pressed, when there is no point in going
+/// on, for example, because nobody reads the output anymore.
+pub(crate) fn request() {
+    CANCELLED.store(true, SeqCst);
+}
+
+pub(crate) fn requested() -> bool {
+    CANCELLED.load(SeqCst)
+}
+
+#[cfg(unix)]
+extern "C" fn on_sigint(_signum: i32) {
+    if CANCELLED.swap(true, SeqCst) {
+        unsafe { sys::_exit(130) }
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    pub(super) const SIGINT: i32 = 2;
+
+    extern "C" {
+        pub(super) fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+        pub(super) fn _exit(status: i32) -> !;
+    }
+}
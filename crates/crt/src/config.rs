@@ -0,0 +1,109 @@
+//! Defaults for command line options, from `crt.toml` in the current directory
+//! (or the file `CRT_CONFIG` points to) and from `CRT_*` environment variables,
+//! which take precedence. Explicit flags override both.
+//!
+//! Only the flat `key = value` subset of TOML is supported:
+//!
+//! ```toml
+//! jobs = 4
+//! mem = "auto"
+//! resolution = "1920x1080"
+//! format = "png"
+//! output_dir = "renders"
+//! ```
+
+use std::{env, fs, io, path::PathBuf};
+
+use anyhow::Context;
+
+const KEYS: [&str; 5] = ["jobs", "mem", "resolution", "format", "output_dir"];
+
+#[derive(Default)]
+pub(crate) struct Config {
+    values: Vec<Value>,
+}
+
+struct Value {
+    key: &'static str,
+    value: String,
+    /// Where the value comes from, like `crt.toml:3` or `CRT_JOBS`.
+    origin: String,
+}
+
+impl Config {
+    pub(crate) fn load() -> anyhow::Result<Config> {
+        let (path, required) = match env::var_os("CRT_CONFIG") {
+            Some(it) => (PathBuf::from(it), true),
+            None => (PathBuf::from("crt.toml"), false),
+        };
+        let mut res = match fs::read_to_string(&path) {
+            Ok(text) => parse(&text, &path.display().to_string())?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !required => Config::default(),
+            Err(err) => return Err(err).with_context(|| format!("reading {}", path.display())),
+        };
+        for key in KEYS {
+            let var = format!("CRT_{}", key.to_ascii_uppercase());
+            if let Ok(value) = env::var(&var) {
+                res.values.push(Value { key, value, origin: var });
+            }
+        }
+        Ok(res)
+    }
+
+    /// Parses the value for `key` with the same function as the flag.
+    pub(crate) fn get<T>(
+        &self,
+        key: &str,
+        parse: impl Fn(&str) -> Result<T, String>,
+    ) -> anyhow::Result<Option<T>> {
+        let Some(value) = self.values.iter().rev().find(|it| it.key == key) else {
+            return Ok(None);
+        };
+        let res =
+            parse(&value.value).map_err(|err| anyhow::format_err!("{}: {err}", value.origin))?;
+        Ok(Some(res))
+    }
+}
+
+fn parse(text: &str, file: &str) -> anyhow::Result<Config> {
+    let mut res = Config::default();
+    for (i, line) in text.lines().enumerate() {
+        let origin = format!("{file}:{}", i + 1);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            anyhow::bail!("{origin}: expected `key = value`");
+        };
+        let key = key.trim();
+        let Some(&key) = KEYS.iter().find(|it| **it == key) else {
+            anyhow::bail!("{origin}: unknown key `{key}`, expected one of {}", KEYS.join(", "));
+        };
+        let value = value.trim();
+        let value = match value.strip_prefix('"') {
+            Some(rest) => match rest.split_once('"') {
+                Some((value, rest)) if rest.trim().is_empty() || rest.trim().starts_with('#') => {
+                    value
+                }
+                _ => anyhow::bail!("{origin}: invalid string"),
+            },
+            None => value.split('#').next().unwrap_or_default().trim(),
+        };
+        res.values.push(Value { key, value: value.to_string(), origin });
+    }
+    Ok(res)
+}
+
+#[test]
+fn test_parse() {
+    let config =
+        parse("# defaults\njobs = 4 # cores\n\nformat = \"png\"\nmem=\"auto\"\n", "crt.toml")
+            .unwrap();
+    let get = |key| config.get(key, |it| Ok(it.to_string())).unwrap();
+    assert_eq!(get("jobs").as_deref(), Some("4"));
+    assert_eq!(get("format").as_deref(), Some("png"));
+    assert_eq!(get("mem").as_deref(), Some("auto"));
+    assert_eq!(get("output_dir"), None);
+    assert!(parse("samples = 4", "crt.toml").is_err());
+}
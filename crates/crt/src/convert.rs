@@ -0,0 +1,71 @@
+//! Importers for external mesh formats, and a writer for the `mesh` block of
+//! the scene format, for `crt convert`.
+
+mod obj;
+mod orient;
+mod stl;
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use geom::{cross, v64};
+
+pub(crate) use self::orient::orient;
+
+/// Triangle mesh in the shape of the scene's `mesh { data { .. } }` block.
+#[derive(Default)]
+pub(crate) struct MeshData {
+    pub(crate) v: Vec<v64>,
+    pub(crate) n: Vec<v64>,
+    /// Zero-based `[vertex, normal]` indices of the corners of each face.
+    pub(crate) f: Vec<[[u32; 2]; 3]>,
+}
+
+impl MeshData {
+    /// Adds a flat shaded triangle, with the normal computed from the winding
+    /// order.
+    fn push_flat(&mut self, v: [u32; 3]) {
+        let [a, b, c] = v.map(|it| self.v[it as usize]);
+        let n = cross(b - a, c - a);
+        let n = if n == v64::ZERO { v64(0.0, 0.0, 1.0) } else { n.to_unit() };
+        let ni = self.n.len() as u32;
+        self.n.push(n);
+        self.f.push(v.map(|vi| [vi, ni]));
+    }
+}
+
+pub(crate) fn read(path: &Path) -> anyhow::Result<MeshData> {
+    let ext = path.extension().and_then(|it| it.to_str()).unwrap_or_default();
+    let bytes = fs::read(path)?;
+    match ext.to_ascii_lowercase().as_str() {
+        "obj" => obj::parse(std::str::from_utf8(&bytes).context("OBJ is not valid UTF-8")?),
+        "stl" => stl::parse(&bytes),
+        _ => anyhow::bail!("unknown mesh format `{ext}`, expected obj or stl"),
+    }
+}
+
+pub(crate) fn write_mesh(w: &mut dyn Write, mesh: &MeshData) -> io::Result<()> {
+    writeln!(w, "mesh {{")?;
+    writeln!(w, "    material {{")?;
+    writeln!(w, "        color #cccccc")?;
+    writeln!(w, "        diffuse 1")?;
+    writeln!(w, "    }}")?;
+    writeln!(w)?;
+    writeln!(w, "    data {{")?;
+    for v in &mesh.v {
+        writeln!(w, "        v {v}")?;
+    }
+    for n in &mesh.n {
+        writeln!(w, "        vn {n}")?;
+    }
+    for [[v0, n0], [v1, n1], [v2, n2]] in &mesh.f {
+        let [v0, n0, v1, n1, v2, n2] = [v0, n0, v1, n1, v2, n2].map(|it| it + 1);
+        writeln!(w, "        f {v0}/{n0} {v1}/{n1} {v2}/{n2}")?;
+    }
+    writeln!(w, "    }}")?;
+    writeln!(w, "}}")
+}
@@ -0,0 +1,87 @@
+//! Wavefront OBJ: `v`, `vn` and `f` statements, everything else is ignored.
+//! Polygons are triangulated as fans, faces without normals are flat shaded.
+
+use geom::v64;
+
+use super::MeshData;
+
+pub(super) fn parse(text: &str) -> anyhow::Result<MeshData> {
+    let mut res = MeshData::default();
+    for (i, line) in text.lines().enumerate() {
+        parse_line(&mut res, line).map_err(|err| anyhow::format_err!("line {}: {err}", i + 1))?;
+    }
+    Ok(res)
+}
+
+fn parse_line(res: &mut MeshData, line: &str) -> anyhow::Result<()> {
+    let line = line.split('#').next().unwrap_or_default();
+    let mut words = line.split_ascii_whitespace();
+    match words.next() {
+        Some("v") => res.v.push(vector(words)?),
+        Some("vn") => res.n.push(vector(words)?),
+        Some("f") => {
+            let mut corners = Vec::new();
+            for word in words {
+                corners.push(corner(res, word)?);
+            }
+            if corners.len() < 3 {
+                anyhow::bail!("face with less than three vertices");
+            }
+            for i in 1..corners.len() - 1 {
+                let tri = [corners[0], corners[i], corners[i + 1]];
+                match tri.map(|[_, n]| n) {
+                    [Some(n0), Some(n1), Some(n2)] => {
+                        let [v0, v1, v2] = tri.map(|[v, _]| v.unwrap());
+                        res.f.push([[v0, n0], [v1, n1], [v2, n2]]);
+                    }
+                    _ => res.push_flat(tri.map(|[v, _]| v.unwrap())),
+                }
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+fn vector<'a>(words: impl Iterator<Item = &'a str>) -> anyhow::Result<v64> {
+    let mut xyz = [0.0; 3];
+    let mut words = words;
+    for c in &mut xyz {
+        let word = words.next().ok_or_else(|| anyhow::format_err!("expected three coordinates"))?;
+        *c = word.parse()?;
+    }
+    let [x, y, z] = xyz;
+    Ok(v64(x, y, z))
+}
+
+/// `v`, `v/vt`, `v//vn` or `v/vt/vn`, returns zero-based vertex and normal
+/// indices.
+fn corner(res: &MeshData, word: &str) -> anyhow::Result<[Option<u32>; 2]> {
+    let mut parts = word.split('/');
+    let v = index(parts.next().unwrap_or_default(), res.v.len())?;
+    let n = match parts.nth(1) {
+        Some(it) if !it.is_empty() => Some(index(it, res.n.len())?),
+        _ => None,
+    };
+    Ok([Some(v), n])
+}
+
+/// OBJ indices are one-based, negative ones count from the end.
+fn index(word: &str, len: usize) -> anyhow::Result<u32> {
+    let i: i64 = word.parse()?;
+    let res = if i < 0 { len as i64 + i } else { i - 1 };
+    if !(0 <= res && res < len as i64) {
+        anyhow::bail!("index {i} is out of bounds");
+    }
+    Ok(res as u32)
+}
+
+#[test]
+fn test_parse() {
+    let mesh =
+        parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1\nf -4 -2 -1\n")
+            .unwrap();
+    assert_eq!(mesh.v.len(), 4);
+    assert_eq!(mesh.f, [[[0, 0], [1, 0], [2, 0]], [[0, 1], [2, 1], [3, 1]]]);
+    assert_eq!(mesh.n[1], v64(0.0, 0.0, 1.0));
+}
@@ -0,0 +1,143 @@
+//! Consistent winding and outward normals for meshes whose faces point every
+//! which way, as is common with downloaded assets. Surfaces with normals
+//! pointing inside render as black patches.
+
+use std::collections::HashMap;
+
+use geom::{cross, dot, v64};
+
+use super::MeshData;
+
+/// Flips faces so that neighbors wind the same way, with closed parts wound
+/// counterclockwise seen from the outside, and open ones the way most of
+/// their area already was. Then flips the normals which disagree with the
+/// winding. Returns the number of faces changed.
+pub(crate) fn orient(mesh: &mut MeshData) -> usize {
+    let n_f = mesh.f.len();
+    // STL facets don't share vertices, so faces are connected through the
+    // positions of their corners rather than through indices. Adding zero
+    // turns `-0.0` into `0.0`.
+    let mut ids = HashMap::new();
+    let vertex: Vec<u32> = (mesh.v.iter())
+        .map(|v| {
+            let next = ids.len() as u32;
+            *ids.entry(v.xyz().map(|it| (it + 0.0).to_bits())).or_insert(next)
+        })
+        .collect();
+
+    // `[lo, hi, face, forward]` for each edge of each face, sorted so that the
+    // faces sharing an edge are next to each other. Faces collapsed into a
+    // line, as at the poles of a UV sphere, would glue their neighbors along
+    // edges shared by more than two faces.
+    let mut edges = Vec::with_capacity(3 * n_f);
+    for (i, face) in mesh.f.iter().enumerate() {
+        let [a, b, c] = face.map(|[v, _]| vertex[v as usize]);
+        if a == b || b == c || c == a {
+            continue;
+        }
+        for [a, b] in [[a, b], [b, c], [c, a]] {
+            edges.push([a.min(b), a.max(b), i as u32, u32::from(a < b)]);
+        }
+    }
+    edges.sort_unstable();
+    // Faces wound the same way go along their shared edge in opposite
+    // directions. Edges with one face, or more than two, connect nothing.
+    let mut neighbors = vec![Vec::new(); n_f];
+    let mut open = vec![false; n_f];
+    for group in edges.chunk_by(|l, r| l[..2] == r[..2]) {
+        match *group {
+            [[.., f, d], [.., g, e]] => {
+                neighbors[f as usize].push((g as usize, d != e));
+                neighbors[g as usize].push((f as usize, d != e));
+            }
+            _ => group.iter().for_each(|&[.., f, _]| open[f as usize] = true),
+        }
+    }
+
+    let mut flip = vec![None; n_f];
+    let mut part = Vec::new();
+    for start in 0..n_f {
+        if flip[start].is_some() {
+            continue;
+        }
+        // Where a part can't be oriented, as a Möbius strip, the first face
+        // to reach another wins.
+        flip[start] = Some(false);
+        part.clear();
+        part.push(start);
+        let mut i = 0;
+        while let Some(&f) = part.get(i) {
+            i += 1;
+            let flip_f = flip[f].unwrap();
+            for &(g, agree) in &neighbors[f] {
+                if flip[g].is_none() {
+                    flip[g] = Some(flip_f ^ !agree);
+                    part.push(g);
+                }
+            }
+        }
+
+        let (mut volume, mut kept, mut flipped) = (0.0, 0.0, 0.0);
+        for &f in &part {
+            let [a, b, c] = mesh.f[f].map(|[v, _]| mesh.v[v as usize]);
+            let n = cross(b - a, c - a);
+            let sign = if flip[f].unwrap() { -1.0 } else { 1.0 };
+            volume += sign * dot(a, n);
+            if flip[f].unwrap() {
+                flipped += n.norm();
+            } else {
+                kept += n.norm();
+            }
+        }
+        let closed = part.iter().all(|&f| !open[f]);
+        let invert = if closed { volume < 0.0 } else { flipped > kept };
+        if invert {
+            for &f in &part {
+                flip[f] = flip[f].map(|it| !it);
+            }
+        }
+    }
+
+    let mut res = 0;
+    let mut negated = HashMap::new();
+    for (face, flip) in mesh.f.iter_mut().zip(flip) {
+        let mut changed = flip == Some(true);
+        if changed {
+            face.swap(1, 2);
+        }
+        let [a, b, c] = face.map(|[v, _]| mesh.v[v as usize]);
+        let given = face.iter().fold(v64::ZERO, |acc, &[_, n]| acc + mesh.n[n as usize]);
+        if dot(cross(b - a, c - a), given) < 0.0 {
+            for [_, n] in face.iter_mut() {
+                *n = *negated.entry(*n).or_insert_with(|| {
+                    mesh.n.push(-mesh.n[*n as usize]);
+                    mesh.n.len() as u32 - 1
+                });
+            }
+            changed = true;
+        }
+        res += usize::from(changed);
+    }
+    res
+}
+
+#[test]
+fn test_orient() {
+    // A tetrahedron as STL facets, with one face wound inwards and its
+    // normal computed from that winding.
+    let mut mesh = MeshData::default();
+    let [o, x, y, z] = [v64::ZERO, v64(1.0, 0.0, 0.0), v64(0.0, 1.0, 0.0), v64(0.0, 0.0, 1.0)];
+    for tri in [[o, y, x], [o, x, z], [o, z, y], [x, z, y]] {
+        let vi = mesh.v.len() as u32;
+        mesh.v.extend(tri);
+        mesh.push_flat([vi, vi + 1, vi + 2]);
+    }
+    assert_eq!(orient(&mut mesh), 1);
+    let center = v64(0.25, 0.25, 0.25);
+    for face in &mesh.f {
+        let [a, b, c] = face.map(|[v, _]| mesh.v[v as usize]);
+        assert!(dot(cross(b - a, c - a), a - center) > 0.0);
+        assert!(dot(mesh.n[face[0][1] as usize], a - center) > 0.0);
+    }
+    assert_eq!(orient(&mut mesh), 0);
+}
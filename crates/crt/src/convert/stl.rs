@@ -0,0 +1,93 @@
+//! STL, both ASCII and binary. Facets don't share vertices, and zero normals
+//! are recomputed from the winding order.
+
+use std::str::SplitAsciiWhitespace;
+
+use geom::v64;
+
+use super::MeshData;
+
+pub(super) fn parse(bytes: &[u8]) -> anyhow::Result<MeshData> {
+    // Binary files may also start with `solid`, so check the size as well.
+    let is_binary = match bytes.get(80..84) {
+        Some(count) => {
+            let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+            bytes.len() == 84 + count * 50 || !bytes.starts_with(b"solid")
+        }
+        None => false,
+    };
+    if is_binary {
+        Ok(parse_binary(bytes))
+    } else {
+        parse_ascii(std::str::from_utf8(bytes)?)
+    }
+}
+
+fn parse_binary(bytes: &[u8]) -> MeshData {
+    let mut res = MeshData::default();
+    for facet in bytes[84..].chunks_exact(50) {
+        let mut floats = facet.chunks_exact(4).map(|it| f32::from_le_bytes(it.try_into().unwrap()));
+        let mut vector = || {
+            let [x, y, z] = [(); 3].map(|()| floats.next().unwrap() as f64);
+            v64(x, y, z)
+        };
+        let n = vector();
+        let v = [vector(), vector(), vector()];
+        push_facet(&mut res, n, v);
+    }
+    res
+}
+
+fn parse_ascii(text: &str) -> anyhow::Result<MeshData> {
+    let mut res = MeshData::default();
+    let mut words = text.split_ascii_whitespace();
+    while let Some(word) = words.next() {
+        if word != "facet" {
+            continue;
+        }
+        if words.next() != Some("normal") {
+            anyhow::bail!("expected `normal`");
+        }
+        let n = vector(&mut words)?;
+        let mut v = [v64::ZERO; 3];
+        let mut i = 0;
+        while let Some(word) = words.next() {
+            match word {
+                "vertex" if i < 3 => {
+                    v[i] = vector(&mut words)?;
+                    i += 1;
+                }
+                "vertex" => anyhow::bail!("facet with more than three vertices"),
+                "endfacet" => break,
+                _ => (),
+            }
+        }
+        if i != 3 {
+            anyhow::bail!("facet with less than three vertices");
+        }
+        push_facet(&mut res, n, v);
+    }
+    Ok(res)
+}
+
+fn vector(words: &mut SplitAsciiWhitespace<'_>) -> anyhow::Result<v64> {
+    let mut xyz = [0.0; 3];
+    for c in &mut xyz {
+        *c = words.next().ok_or_else(|| anyhow::format_err!("unexpected end of file"))?.parse()?;
+    }
+    let [x, y, z] = xyz;
+    Ok(v64(x, y, z))
+}
+
+fn push_facet(res: &mut MeshData, n: v64, v: [v64; 3]) {
+    let vi = res.v.len() as u32;
+    res.v.extend(v);
+    let idx = [vi, vi + 1, vi + 2];
+    if n == v64::ZERO {
+        res.push_flat(idx);
+    } else {
+        let ni = res.n.len() as u32;
+        res.n.push(n.to_unit());
+        res.f.push(idx.map(|it| [it, ni]));
+    }
+}
@@ -0,0 +1,156 @@
+//! `crt example`: ready to render scenes, from a handful of planes to a few
+//! hundred triangles, for trying things out and for reproducible bug reports.
+
+use std::io::{self, Write};
+
+use geom::v64;
+
+use crate::convert::{self, MeshData};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Example {
+    Cornell,
+    Spheres,
+    MeshGrid,
+}
+
+impl Example {
+    pub(crate) fn parse(value: &str) -> Result<Example, String> {
+        match value {
+            "cornell" => Ok(Example::Cornell),
+            "spheres" => Ok(Example::Spheres),
+            "mesh-grid" => Ok(Example::MeshGrid),
+            _ => Err(format!("unknown example `{value}`, expected cornell, spheres or mesh-grid")),
+        }
+    }
+}
+
+pub(crate) fn write(w: &mut dyn Write, example: Example) -> io::Result<()> {
+    match example {
+        Example::Cornell => cornell(w),
+        Example::Spheres => spheres(w),
+        Example::MeshGrid => mesh_grid(w),
+    }
+}
+
+/// A box, open towards the camera, with a red and a green wall and two
+/// spheres, one of them a mirror.
+fn cornell(w: &mut dyn Write) -> io::Result<()> {
+    header(w, v64(0.0, 0.0, -9.9), v64(0.0, 0.0, 0.0), 400.0)?;
+    light(w, v64(0.0, 8.0, -4.0), "#888888")?;
+    let walls = [
+        (v64(0.0, -10.0, 0.0), v64(0.0, 1.0, 0.0), "#bbbbbb"),
+        (v64(0.0, 10.0, 0.0), v64(0.0, -1.0, 0.0), "#bbbbbb"),
+        (v64(0.0, 0.0, 10.0), v64(0.0, 0.0, -1.0), "#bbbbbb"),
+        (v64(-10.0, 0.0, 0.0), v64(1.0, 0.0, 0.0), "#aa2222"),
+        (v64(10.0, 0.0, 0.0), v64(-1.0, 0.0, 0.0), "#22aa22"),
+    ];
+    for (pos, normal, color) in walls {
+        plane(w, pos, normal, color)?;
+    }
+    sphere(w, v64(-4.0, -6.5, 3.0), 3.5, "#dddddd", 0.8)?;
+    sphere(w, v64(4.5, -7.0, -2.0), 3.0, "#ddaa44", 0.0)
+}
+
+/// A grid of spheres of varying color and reflectance over a floor.
+fn spheres(w: &mut dyn Write) -> io::Result<()> {
+    header(w, v64(0.0, 25.0, -60.0), v64(0.0, 0.0, 0.0), 1000.0)?;
+    light(w, v64(-30.0, 40.0, -30.0), "#dddddd")?;
+    plane(w, v64(0.0, -4.0, 0.0), v64(0.0, 1.0, 0.0), "#334455")?;
+    const N: i32 = 5;
+    for i in 0..N {
+        for j in 0..N {
+            let pos = v64((i - N / 2) as f64 * 9.0, 0.0, (j - N / 2) as f64 * 9.0);
+            let color = format!("#{:02x}{:02x}{:02x}", 0x33 + i * 0x30, 0x88, 0x33 + j * 0x30);
+            let reflectance = ((i + j) % 3) as f64 * 0.3;
+            sphere(w, pos, 4.0, &color, reflectance)?;
+        }
+    }
+    Ok(())
+}
+
+/// A rippled height field triangle mesh with smooth normals.
+fn mesh_grid(w: &mut dyn Write) -> io::Result<()> {
+    header(w, v64(0.0, 20.0, -40.0), v64(0.0, 0.0, 0.0), 750.0)?;
+    light(w, v64(-20.0, 30.0, -20.0), "#dddddd")?;
+    plane(w, v64(0.0, -6.0, 0.0), v64(0.0, 1.0, 0.0), "#5566ff")?;
+
+    const N: u32 = 21;
+    const SIZE: f64 = 30.0;
+    let height = |x: f64, z: f64| 2.0 * (x * 0.4).sin() * (z * 0.4).cos();
+    let mut mesh = MeshData::default();
+    for i in 0..=N {
+        for j in 0..=N {
+            let x = SIZE * (i as f64 / N as f64 - 0.5);
+            let z = SIZE * (j as f64 / N as f64 - 0.5);
+            mesh.v.push(v64(x, height(x, z), z));
+            let dx = 0.8 * (x * 0.4).cos() * (z * 0.4).cos();
+            let dz = -0.8 * (x * 0.4).sin() * (z * 0.4).sin();
+            mesh.n.push(v64(-dx, 1.0, -dz).to_unit());
+        }
+    }
+    let idx = |i: u32, j: u32| i * (N + 1) + j;
+    for i in 0..N {
+        for j in 0..N {
+            let [a, b, c, d] = [idx(i, j), idx(i + 1, j), idx(i + 1, j + 1), idx(i, j + 1)];
+            mesh.f.push([a, b, c].map(|it| [it, it]));
+            mesh.f.push([a, c, d].map(|it| [it, it]));
+        }
+    }
+    writeln!(w)?;
+    convert::write_mesh(w, &mesh)
+}
+
+fn header(w: &mut dyn Write, pos: v64, look_at: v64, focus: f64) -> io::Result<()> {
+    writeln!(w, "background #000000")?;
+    writeln!(w)?;
+    writeln!(w, "camera {{")?;
+    writeln!(w, "    pos {pos}")?;
+    writeln!(w, "    look_at {look_at}")?;
+    writeln!(w, "    up 0,-1,0")?;
+    writeln!(w, "    focus {focus}")?;
+    writeln!(w, "    dim 800x600")?;
+    writeln!(w, "}}")
+}
+
+fn light(w: &mut dyn Write, pos: v64, color: &str) -> io::Result<()> {
+    writeln!(w)?;
+    writeln!(w, "light {{")?;
+    writeln!(w, "    pos {pos}")?;
+    writeln!(w, "    color {color}")?;
+    writeln!(w, "}}")
+}
+
+fn plane(w: &mut dyn Write, pos: v64, normal: v64, color: &str) -> io::Result<()> {
+    writeln!(w)?;
+    writeln!(w, "plane {{")?;
+    writeln!(w, "    pos {pos}")?;
+    writeln!(w, "    normal {normal}")?;
+    material(w, color, 0.0)?;
+    writeln!(w, "}}")
+}
+
+fn sphere(
+    w: &mut dyn Write,
+    pos: v64,
+    radius: f64,
+    color: &str,
+    reflectance: f64,
+) -> io::Result<()> {
+    writeln!(w)?;
+    writeln!(w, "sphere {{")?;
+    writeln!(w, "    pos {pos}")?;
+    writeln!(w, "    radius {radius}")?;
+    material(w, color, reflectance)?;
+    writeln!(w, "}}")
+}
+
+fn material(w: &mut dyn Write, color: &str, reflectance: f64) -> io::Result<()> {
+    writeln!(w, "    material {{")?;
+    writeln!(w, "        color {color}")?;
+    writeln!(w, "        diffuse 1")?;
+    if reflectance > 0.0 {
+        writeln!(w, "        reflectance {reflectance}")?;
+    }
+    writeln!(w, "    }}")
+}
@@ -0,0 +1,121 @@
+mod exr;
+mod png;
+mod qoi;
+
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use render::rgb;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Format {
+    Ppm,
+    PpmBinary,
+    Png,
+    Qoi,
+    Exr,
+}
+
+/// Streaming image encoder: `header`, then each row top to bottom, then
+/// `finish`.
+pub(crate) trait Encode: Send {
+    fn header(&mut self, w: &mut dyn Write) -> io::Result<()>;
+    fn row(&mut self, w: &mut dyn Write, y: u32, row: &[scene::Color]) -> io::Result<()>;
+    fn finish(&mut self, w: &mut dyn Write) -> io::Result<()>;
+}
+
+impl Format {
+    pub(crate) fn parse(value: &str) -> Result<Format, String> {
+        let res = match value {
+            "ppm" => Format::Ppm,
+            "ppm-binary" => Format::PpmBinary,
+            "png" => Format::Png,
+            "qoi" => Format::Qoi,
+            "exr" => Format::Exr,
+            _ => {
+                return Err(format!(
+                    "unknown format `{value}`, expected ppm, ppm-binary, png, qoi or exr"
+                ))
+            }
+        };
+        Ok(res)
+    }
+
+    pub(crate) fn from_path(path: &Path) -> Option<Format> {
+        let res = match path.extension()?.to_str()? {
+            "ppm" => Format::Ppm,
+            "png" => Format::Png,
+            "qoi" => Format::Qoi,
+            "exr" => Format::Exr,
+            _ => return None,
+        };
+        Some(res)
+    }
+
+    /// Whether the format is unfit for printing to a terminal.
+    pub(crate) fn is_binary(self) -> bool {
+        self != Format::Ppm
+    }
+
+    pub(crate) fn encoder(self, dim: rgb::Idx, dither: rgb::Dither) -> Box<dyn Encode> {
+        let q = Quantizer { dither, buf: Vec::new() };
+        match self {
+            Format::Ppm => Box::new(Ppm { dim, q, binary: false }),
+            Format::PpmBinary => Box::new(Ppm { dim, q, binary: true }),
+            Format::Png => Box::new(png::Png::new(dim, q)),
+            Format::Qoi => Box::new(qoi::Qoi::new(dim, q)),
+            Format::Exr => Box::new(exr::Exr::new(dim)),
+        }
+    }
+}
+
+/// Converts rows of linear colors to 8-bit sRGB for the formats that need it.
+struct Quantizer {
+    dither: rgb::Dither,
+    buf: Vec<rgb::Color>,
+}
+
+impl Quantizer {
+    fn quantize(&mut self, y: u32, row: &[scene::Color]) -> &[rgb::Color] {
+        self.buf.clear();
+        let colors = row.iter().enumerate();
+        self.buf.extend(colors.map(|(x, c)| rgb::quantize(c, [x as u32, y], self.dither)));
+        &self.buf
+    }
+}
+
+struct Ppm {
+    dim: rgb::Idx,
+    q: Quantizer,
+    binary: bool,
+}
+
+impl Encode for Ppm {
+    fn header(&mut self, w: &mut dyn Write) -> io::Result<()> {
+        let magic_number = if self.binary { "P6" } else { "P3" };
+        let max_color = 255;
+        let [width, height] = self.dim;
+        write!(w, "{}\n{} {}\n{}\n", magic_number, width, height, max_color)
+    }
+
+    fn row(&mut self, w: &mut dyn Write, y: u32, row: &[scene::Color]) -> io::Result<()> {
+        let row = self.q.quantize(y, row);
+        if self.binary {
+            for &rgb::Color { r, g, b } in row {
+                w.write_all(&[r, g, b])?;
+            }
+        } else {
+            writeln!(w)?;
+            for &rgb::Color { r, g, b } in row {
+                write!(w, "{r:3} {g:3} {b:3}  ")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, _w: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+//! Uncompressed single-part scanline OpenEXR with 32-bit float channels,
+//! preserving the linear high dynamic range colors.
+
+use std::io::{self, Write};
+
+use render::rgb;
+
+use super::Encode;
+
+pub(super) struct Exr {
+    dim: rgb::Idx,
+    buf: Vec<u8>,
+}
+
+impl Exr {
+    pub(super) fn new(dim: rgb::Idx) -> Exr {
+        Exr { dim, buf: Vec::new() }
+    }
+
+    fn row_size(&self) -> u64 {
+        // y coordinate, data size, and three channels of f32.
+        4 + 4 + self.dim[0] as u64 * 3 * 4
+    }
+}
+
+impl Encode for Exr {
+    fn header(&mut self, w: &mut dyn Write) -> io::Result<()> {
+        let [width, height] = self.dim;
+        let max = [width as i32 - 1, height as i32 - 1];
+        let window: Vec<u8> =
+            [0, 0, max[0], max[1]].iter().flat_map(|it| it.to_le_bytes()).collect();
+
+        let mut h = Vec::new();
+        h.extend(0x01312f76u32.to_le_bytes());
+        h.extend(2u32.to_le_bytes());
+
+        let mut channels = Vec::new();
+        // Channels must be sorted by name.
+        for name in [b"B", b"G", b"R"] {
+            channels.extend(name);
+            channels.push(0);
+            channels.extend(2i32.to_le_bytes()); // FLOAT
+            channels.extend([0, 0, 0, 0]); // pLinear and reserved.
+            channels.extend(1i32.to_le_bytes()); // xSampling
+            channels.extend(1i32.to_le_bytes()); // ySampling
+        }
+        channels.push(0);
+        attribute(&mut h, "channels", "chlist", &channels);
+        attribute(&mut h, "compression", "compression", &[0]);
+        attribute(&mut h, "dataWindow", "box2i", &window);
+        attribute(&mut h, "displayWindow", "box2i", &window);
+        attribute(&mut h, "lineOrder", "lineOrder", &[0]);
+        attribute(&mut h, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+        attribute(&mut h, "screenWindowCenter", "v2f", &[0; 8]);
+        attribute(&mut h, "screenWindowWidth", "float", &1f32.to_le_bytes());
+        h.push(0);
+
+        let table_size = height as u64 * 8;
+        let first_row = h.len() as u64 + table_size;
+        for y in 0..height as u64 {
+            h.extend((first_row + y * self.row_size()).to_le_bytes());
+        }
+        w.write_all(&h)
+    }
+
+    fn row(&mut self, w: &mut dyn Write, y: u32, row: &[scene::Color]) -> io::Result<()> {
+        self.buf.clear();
+        self.buf.extend((y as i32).to_le_bytes());
+        self.buf.extend(((self.row_size() - 8) as u32).to_le_bytes());
+        for channel in [|c: &scene::Color| c.b, |c: &scene::Color| c.g, |c: &scene::Color| c.r] {
+            for c in row {
+                self.buf.extend((channel(c) as f32).to_le_bytes());
+            }
+        }
+        w.write_all(&self.buf)
+    }
+
+    fn finish(&mut self, _w: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn attribute(h: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    for s in [name, kind] {
+        h.extend(s.as_bytes());
+        h.push(0);
+    }
+    h.extend((value.len() as u32).to_le_bytes());
+    h.extend(value);
+}
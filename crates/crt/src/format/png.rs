@@ -0,0 +1,128 @@
+//! PNG with uncompressed (stored) deflate blocks, which lets us emit the image
+//! one row at a time without implementing a compressor.
+
+use std::io::{self, Write};
+
+use render::rgb;
+
+use super::{Encode, Quantizer};
+
+pub(super) struct Png {
+    dim: rgb::Idx,
+    q: Quantizer,
+    adler: Adler32,
+    chunk: Vec<u8>,
+}
+
+impl Png {
+    pub(super) fn new(dim: rgb::Idx, q: Quantizer) -> Png {
+        Png { dim, q, adler: Adler32::new(), chunk: Vec::new() }
+    }
+
+    fn write_chunk(&mut self, w: &mut dyn Write, kind: &[u8; 4]) -> io::Result<()> {
+        w.write_all(&(self.chunk.len() as u32).to_be_bytes())?;
+        w.write_all(kind)?;
+        w.write_all(&self.chunk)?;
+        let crc = crc32(crc32(!0, kind), &self.chunk);
+        w.write_all(&(!crc).to_be_bytes())?;
+        self.chunk.clear();
+        Ok(())
+    }
+}
+
+impl Encode for Png {
+    fn header(&mut self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+        let [width, height] = self.dim;
+        self.chunk.extend(width.to_be_bytes());
+        self.chunk.extend(height.to_be_bytes());
+        // 8-bit depth, truecolor, default compression, filter and interlace.
+        self.chunk.extend([8, 2, 0, 0, 0]);
+        self.write_chunk(w, b"IHDR")?;
+
+        // zlib header: deflate with 32k window, no preset dictionary.
+        self.chunk.extend([0x78, 0x01]);
+        self.write_chunk(w, b"IDAT")
+    }
+
+    fn row(&mut self, w: &mut dyn Write, y: u32, row: &[scene::Color]) -> io::Result<()> {
+        let mut data = vec![0u8]; // Filter type `None`.
+        for &rgb::Color { r, g, b } in self.q.quantize(y, row) {
+            data.extend([r, g, b]);
+        }
+        self.adler.update(&data);
+        for block in data.chunks(u16::MAX as usize) {
+            let len = block.len() as u16;
+            self.chunk.push(0); // Not final, stored.
+            self.chunk.extend(len.to_le_bytes());
+            self.chunk.extend((!len).to_le_bytes());
+            self.chunk.extend(block);
+        }
+        self.write_chunk(w, b"IDAT")
+    }
+
+    fn finish(&mut self, w: &mut dyn Write) -> io::Result<()> {
+        // Empty final stored block.
+        self.chunk.extend([1, 0, 0, 0xff, 0xff]);
+        self.chunk.extend(self.adler.finish().to_be_bytes());
+        self.write_chunk(w, b"IDAT")?;
+        self.write_chunk(w, b"IEND")
+    }
+}
+
+struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    fn new() -> Adler32 {
+        Adler32 { a: 1, b: 0 }
+    }
+    fn update(&mut self, data: &[u8]) {
+        // 5552 is the largest n such that the sums don't overflow u32.
+        for chunk in data.chunks(5552) {
+            for &byte in chunk {
+                self.a += byte as u32;
+                self.b += self.a;
+            }
+            self.a %= 65521;
+            self.b %= 65521;
+        }
+    }
+    fn finish(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc = CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut res = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        res[n] = c;
+        n += 1;
+    }
+    res
+};
+
+#[test]
+fn test_checksums() {
+    assert_eq!(!crc32(!0, b"IEND"), 0xae426082);
+    let mut adler = Adler32::new();
+    adler.update(b"Wikipedia");
+    assert_eq!(adler.finish(), 0x11e60398);
+}
@@ -0,0 +1,99 @@
+//! The Quite OK Image format, <https://qoiformat.org/qoi-specification.pdf>.
+
+use std::io::{self, Write};
+
+use render::rgb;
+
+use super::{Encode, Quantizer};
+
+pub(super) struct Qoi {
+    dim: rgb::Idx,
+    q: Quantizer,
+    prev: rgb::Color,
+    index: [Option<rgb::Color>; 64],
+    run: u8,
+    remaining: u64,
+    out: Vec<u8>,
+}
+
+const OP_INDEX: u8 = 0x00;
+const OP_DIFF: u8 = 0x40;
+const OP_LUMA: u8 = 0x80;
+const OP_RUN: u8 = 0xc0;
+const OP_RGB: u8 = 0xfe;
+
+impl Qoi {
+    pub(super) fn new(dim: rgb::Idx, q: Quantizer) -> Qoi {
+        Qoi {
+            dim,
+            q,
+            prev: rgb::Color::new_black(),
+            index: [None; 64],
+            run: 0,
+            remaining: dim[0] as u64 * dim[1] as u64,
+            out: Vec::new(),
+        }
+    }
+}
+
+impl Encode for Qoi {
+    fn header(&mut self, w: &mut dyn Write) -> io::Result<()> {
+        let [width, height] = self.dim;
+        w.write_all(b"qoif")?;
+        w.write_all(&width.to_be_bytes())?;
+        w.write_all(&height.to_be_bytes())?;
+        // Three channels, sRGB with linear alpha.
+        w.write_all(&[3, 0])
+    }
+
+    fn row(&mut self, w: &mut dyn Write, y: u32, row: &[scene::Color]) -> io::Result<()> {
+        let row = self.q.quantize(y, row);
+        let out = &mut self.out;
+        for &px in row {
+            self.remaining -= 1;
+            if px == self.prev {
+                self.run += 1;
+                if self.run == 62 || self.remaining == 0 {
+                    out.push(OP_RUN | (self.run - 1));
+                    self.run = 0;
+                }
+                continue;
+            }
+            if self.run > 0 {
+                out.push(OP_RUN | (self.run - 1));
+                self.run = 0;
+            }
+
+            let hash = (px.r as usize * 3 + px.g as usize * 5 + px.b as usize * 7 + 255 * 11) % 64;
+            if self.index[hash] == Some(px) {
+                out.push(OP_INDEX | hash as u8);
+            } else {
+                self.index[hash] = Some(px);
+                let dr = px.r.wrapping_sub(self.prev.r) as i8;
+                let dg = px.g.wrapping_sub(self.prev.g) as i8;
+                let db = px.b.wrapping_sub(self.prev.b) as i8;
+                let dr_dg = dr.wrapping_sub(dg);
+                let db_dg = db.wrapping_sub(dg);
+                if [dr, dg, db].iter().all(|it| (-2..=1).contains(it)) {
+                    out.push(OP_DIFF | ((dr + 2) << 4 | (dg + 2) << 2 | (db + 2)) as u8);
+                } else if (-32..=31).contains(&dg)
+                    && (-8..=7).contains(&dr_dg)
+                    && (-8..=7).contains(&db_dg)
+                {
+                    out.push(OP_LUMA | (dg + 32) as u8);
+                    out.push(((dr_dg + 8) << 4 | (db_dg + 8)) as u8);
+                } else {
+                    out.extend([OP_RGB, px.r, px.g, px.b]);
+                }
+            }
+            self.prev = px;
+        }
+        w.write_all(out)?;
+        out.clear();
+        Ok(())
+    }
+
+    fn finish(&mut self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&[0, 0, 0, 0, 0, 0, 0, 1])
+    }
+}
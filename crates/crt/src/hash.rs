@@ -0,0 +1,41 @@
+//! Content hash of a rendered image for `--hash`, to check that a change
+//! doesn't affect the output without keeping reference images around.
+
+use render::rgb;
+
+/// 64-bit FNV-1a.
+pub(crate) struct Hasher(u64);
+
+impl Hasher {
+    pub(crate) fn new() -> Hasher {
+        Hasher(0xcbf29ce484222325)
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    /// Hashes the final 8-bit colors rather than the linear ones, so that the
+    /// last bits of floating point results, which may differ between
+    /// platforms, don't matter.
+    pub(crate) fn row(&mut self, y: u32, row: &[scene::Color], dither: rgb::Dither) {
+        for (x, c) in row.iter().enumerate() {
+            let rgb::Color { r, g, b } = rgb::quantize(c, [x as u32, y], dither);
+            self.update(&[r, g, b]);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[test]
+fn test_fnv() {
+    let mut h = Hasher::new();
+    assert_eq!(h.finish(), 0xcbf29ce484222325);
+    h.update(b"a");
+    assert_eq!(h.finish(), 0xaf63dc4c8601ec8c);
+}
@@ -0,0 +1,93 @@
+//! `crt info`: a human readable summary of a scene.
+
+use std::io::{self, Write};
+
+use render::rgb;
+use scene::{LightKind, Material, Scene};
+
+pub(crate) fn print(w: &mut dyn Write, scene: &Scene<'_>, mem_estimate: usize) -> io::Result<()> {
+    let stats = scene.stats();
+    writeln!(
+        w,
+        "objects: {} spheres, {} planes, {} tori, {} quadrics, {} meshes, {} curves",
+        stats.spheres, stats.planes, stats.tori, stats.quadrics, stats.meshes, stats.curves
+    )?;
+    writeln!(w, "triangles: {} ({} vertices)", stats.triangles, stats.vertices)?;
+    if stats.curves > 0 {
+        writeln!(w, "curve segments: {}", stats.curve_segments)?;
+    }
+    match stats.bounds {
+        Some([lo, hi]) => writeln!(w, "bounds: {lo} .. {hi}")?,
+        None => writeln!(w, "bounds: unbounded")?,
+    }
+    if stats.animated_properties > 0 {
+        writeln!(w, "animated properties: {}", stats.animated_properties)?;
+    }
+
+    let c = &scene.camera;
+    writeln!(
+        w,
+        "camera: pos {}, look_at {}, up {}, focus {}, dim {}x{}",
+        c.pos, c.look_at, c.up, c.focus, c.width, c.height
+    )?;
+    for light in scene.lights.iter() {
+        match light.kind {
+            LightKind::Point => write!(w, "light: pos {}", light.pos)?,
+            LightKind::Directional { dir } => write!(w, "directional light: dir {dir}")?,
+        }
+        write!(w, ", color {}", hex(&light.color))?;
+        if !light.group.is_empty() {
+            write!(w, ", group {}", light.group)?;
+        }
+        writeln!(w)?;
+    }
+
+    if let Some(clip) = &scene.clip {
+        write!(w, "clip: pos {}, normal {}", clip.normal.origin(), clip.normal.dir())?;
+        if let Some(cap) = &clip.cap {
+            write!(w, ", cap {}", hex(&cap.color))?;
+        }
+        writeln!(w)?;
+    }
+
+    writeln!(w, "materials:")?;
+    for (i, s) in scene.spheres.iter().enumerate() {
+        material(w, "sphere", i, &s.material)?;
+    }
+    for (i, p) in scene.planes.iter().enumerate() {
+        material(w, "plane", i, &p.material)?;
+    }
+    for (i, t) in scene.tori.iter().enumerate() {
+        material(w, "torus", i, &t.material)?;
+    }
+    for (i, q) in scene.quadrics.iter().enumerate() {
+        material(w, "quadric", i, &q.material)?;
+    }
+    for (i, m) in scene.meshes.iter().enumerate() {
+        material(w, "mesh", i, &m.material)?;
+    }
+    for (i, c) in scene.curves.iter().enumerate() {
+        material(w, "curves", i, &c.material)?;
+    }
+
+    writeln!(w, "memory: at most {} kilobytes", mem_estimate.div_ceil(1024))
+}
+
+fn material(w: &mut dyn Write, kind: &str, i: usize, m: &Material) -> io::Result<()> {
+    write!(
+        w,
+        "    {kind} {i}: color {}, diffuse {}, reflectance {}, roughness {}",
+        hex(&m.color),
+        m.diffuse,
+        m.reflectance,
+        m.roughness
+    )?;
+    if m.double_sided {
+        write!(w, ", double-sided")?;
+    }
+    writeln!(w)
+}
+
+fn hex(color: &scene::Color) -> rgb::Color {
+    rgb::Color::from_linear(color)
+}
@@ -0,0 +1,94 @@
+//! Output for `--json`: one flat object per line, tagged with a `type` field.
+
+use std::fmt::{self, Write};
+
+pub(crate) struct Object {
+    buf: String,
+}
+
+impl Object {
+    pub(crate) fn new(kind: &str) -> Object {
+        let mut res = Object { buf: String::from("{") };
+        res.key("type");
+        write_str(&mut res.buf, kind);
+        res
+    }
+
+    pub(crate) fn str(mut self, key: &str, value: &str) -> Object {
+        self.key(key);
+        write_str(&mut self.buf, value);
+        self
+    }
+
+    pub(crate) fn num(mut self, key: &str, value: u64) -> Object {
+        self.key(key);
+        let _ = write!(self.buf, "{value}");
+        self
+    }
+
+    /// Non-finite values, which JSON lacks, are written as `null`.
+    pub(crate) fn float(mut self, key: &str, value: f64) -> Object {
+        self.key(key);
+        if value.is_finite() {
+            let _ = write!(self.buf, "{value}");
+        } else {
+            self.buf.push_str("null");
+        }
+        self
+    }
+
+    pub(crate) fn opt_num(self, key: &str, value: Option<u32>) -> Object {
+        match value {
+            Some(it) => self.num(key, it.into()),
+            None => {
+                let mut res = self;
+                res.key(key);
+                res.buf.push_str("null");
+                res
+            }
+        }
+    }
+
+    pub(crate) fn stats(self, stats: &render::Stats) -> Object {
+        self.num("parse_ns", stats.parse)
+            .num("bvh_build_ns", stats.bvh_build)
+            .num("trace_ns", stats.trace)
+            .num("rays", stats.rays)
+    }
+
+    fn key(&mut self, key: &str) {
+        if self.buf.len() > 1 {
+            self.buf.push(',');
+        }
+        write_str(&mut self.buf, key);
+        self.buf.push(':');
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}}}", self.buf)
+    }
+}
+
+fn write_str(buf: &mut String, s: &str) {
+    buf.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            c if c < ' ' => {
+                let _ = write!(buf, "\\u{:04x}", c as u32);
+            }
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
+
+#[test]
+fn test_object() {
+    let obj = Object::new("error").str("message", "bad \"x\"\n").opt_num("line", None).num("n", 1);
+    assert_eq!(obj.to_string(), r#"{"type":"error","message":"bad \"x\"\n","line":null,"n":1}"#);
+}
@@ -0,0 +1,28 @@
+//! Prints [`trace`] events from the renderer to stderr.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering::Relaxed},
+        OnceLock,
+    },
+    time::Instant,
+};
+
+static START: OnceLock<Instant> = OnceLock::new();
+static PHASES: AtomicBool = AtomicBool::new(false);
+
+/// Warnings and out of memory details are always printed, the start and the
+/// end of each phase only if `phases` is set.
+pub(crate) fn install(phases: bool) {
+    START.get_or_init(Instant::now);
+    PHASES.store(phases, Relaxed);
+    trace::set_sink(print);
+}
+
+fn print(event: &trace::Event<'_>) {
+    if matches!(event, trace::Event::Start(_) | trace::Event::End(_)) && !PHASES.load(Relaxed) {
+        return;
+    }
+    let elapsed = START.get().map_or(0.0, |it| it.elapsed().as_secs_f64());
+    eprintln!("[{elapsed:8.3}s] {event}");
+}
@@ -1,66 +1,1174 @@
-mod threads;
+mod bake;
+mod bench;
+mod cancel;
+mod config;
+mod convert;
+mod example;
+mod format;
+mod hash;
+mod info;
+mod json;
+mod log;
+mod orbit;
+mod output;
+mod partial;
+mod preview;
+mod progress;
+mod serve;
+mod stream;
+mod watch;
 
 use std::{
-    io::{self, Read},
-    num::NonZeroUsize,
+    collections::HashMap,
+    fmt, fs,
+    io::{self, IsTerminal, Read},
+    net::IpAddr,
+    num::{NonZeroU32, NonZeroUsize},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::Ordering::SeqCst,
+    time::Duration,
 };
 
 use anyhow::Context;
+use bench::Bench;
+use example::Example;
+use format::Format;
+use mem::Mem;
+use output::Output;
+use preview::Preview;
+use progress::Progress;
 use render::rgb;
+use stream::RowWriter;
 use threads::Threads;
 
-/// Renders an image in ppm format.
+/// Renders an image.
 #[derive(argh::FromArgs)]
+#[argh(
+    note = "Defaults for --jobs, --mem, --resolution and --format, and a directory \
+            for relative --output paths can be set in crt.toml in the current \
+            directory (or $CRT_CONFIG), or with $CRT_JOBS, $CRT_MEM, \
+            $CRT_RESOLUTION, $CRT_FORMAT and $CRT_OUTPUT_DIR.",
+    error_code(1, "any other error, including invalid arguments"),
+    error_code(2, "the scene is invalid"),
+    error_code(3, "the scene doesn't fit into --mem"),
+    error_code(4, "reading or writing a file failed"),
+    error_code(130, "interrupted with Ctrl-C")
+)]
 struct Args {
+    /// scene to render, read from stdin if omitted
+    #[argh(positional)]
+    scene: Option<PathBuf>,
+
     /// amount of parallelism, defaults to the number of cores
     #[argh(option, short = 'j')]
     jobs: Option<NonZeroUsize>,
 
-    /// memory to use, in kilobytes
+    /// render on worker threads of the lowest priority, on one core less
+    /// unless --jobs is given
+    #[argh(switch)]
+    background: bool,
+
+    /// memory to use, in kilobytes, or `auto` to estimate it from the scene,
+    /// 640 by default
+    #[argh(option, from_str_fn(parse_mem))]
+    mem: Option<MemSize>,
+
+    /// width of the image, in pixels, 800 by default
+    #[argh(option)]
+    width: Option<u32>,
+
+    /// height of the image, in pixels, by default derived from the width and
+    /// the aspect ratio of the camera, or 600 if the width isn't set either
+    #[argh(option)]
+    height: Option<u32>,
+
+    /// width and height of the image together, like 1920x1080
+    #[argh(option, from_str_fn(parse_resolution))]
+    resolution: Option<rgb::Idx>,
+
+    /// file to write the image to, `-` for stdout (the default)
+    #[argh(option, short = 'o')]
+    output: Option<PathBuf>,
+
+    /// image format: ppm, ppm-binary, png, qoi or exr, guessed from the output
+    /// file extension by default
+    #[argh(option, from_str_fn(parse_format))]
+    format: Option<Format>,
+
+    /// dithering applied when quantizing colors: none or bayer
+    #[argh(option, default = "rgb::Dither::None", from_str_fn(parse_dither))]
+    dither: rgb::Dither,
+
+    /// render only tiles i of N, `crt merge` assembles the image from the
+    /// partial outputs of all N
+    #[argh(option, from_str_fn(parse_tiles))]
+    tiles: Option<render::Tiles>,
+
+    /// order in which rows are rendered: top-down, center-out, interleaved or
+    /// costliest-first
+    #[argh(option, default = "render::Order::TopDown", from_str_fn(parse_order))]
+    order: render::Order,
+
+    /// rows each thread takes at once, raised as needed to split an image
+    /// into at most 256 bands
+    #[argh(option)]
+    chunk: Option<NonZeroU32>,
+
+    /// rays per pixel, overrides the scene's `settings`
+    #[argh(option)]
+    samples: Option<NonZeroU32>,
+
+    /// maximum number of reflections per ray, overrides the scene's `settings`
+    #[argh(option)]
+    bounces: Option<u32>,
+
+    /// brightness adjustment in stops, overrides the scene's `settings`
+    #[argh(option, from_str_fn(parse_finite))]
+    exposure: Option<f64>,
+
+    /// pick the exposure so that the scene averages middle gray, unless
+    /// --exposure is given
+    #[argh(switch)]
+    auto_exposure: bool,
+
+    /// white balance from blue (negative) to red (positive), overrides the
+    /// scene's `settings`
+    #[argh(option, from_str_fn(parse_finite))]
+    temperature: Option<f64>,
+
+    /// white balance from green (negative) to magenta (positive), overrides
+    /// the scene's `settings`
+    #[argh(option, from_str_fn(parse_finite))]
+    tint: Option<f64>,
+
+    /// tone mapping: none or reinhard, overrides the scene's `settings`
+    #[argh(option, from_str_fn(parse_tonemap))]
+    tonemap: Option<scene::Tonemap>,
+
+    /// display gamma, overrides the scene's `settings`
+    #[argh(option, from_str_fn(parse_positive))]
+    gamma: Option<f64>,
+
+    /// integrator: whitted, path, or one of the --debug-view ones, overrides the
+    /// scene's `settings`
+    #[argh(option, from_str_fn(parse_integrator))]
+    integrator: Option<scene::Integrator>,
+
+    /// show normals, depth, ao, bvh-heatmap, wireframe or false-color instead of
+    /// shading
+    #[argh(option, from_str_fn(parse_debug_view))]
+    debug_view: Option<scene::Integrator>,
+
+    /// seed for sampling randomness, overrides the scene's `settings`
+    #[argh(option)]
+    seed: Option<u64>,
+
+    /// how far from surfaces shadow and reflection rays start, picked from
+    /// the scene size by default, overrides the scene's `settings`
+    #[argh(option, from_str_fn(parse_positive))]
+    shadow_bias: Option<f64>,
+
+    /// comma-separated light groups to keep on, switching off the lights of
+    /// the other groups
+    #[argh(option)]
+    lights: Option<String>,
+
+    /// comma-separated patterns of the names of the objects to render, where
+    /// * matches anything, or to hide with a leading -
+    #[argh(option)]
+    objects: Option<String>,
+
+    /// also show the image in the terminal: term, or orbit to look around the
+    /// scene interactively instead of writing the image
+    #[argh(option, from_str_fn(parse_preview))]
+    preview: Option<Preview>,
+
+    /// render the scene this many times and print timings instead of the
+    /// image
+    #[argh(option)]
+    bench: Option<NonZeroU32>,
+
+    /// render an animation with this many frames, numbered output files are
+    /// derived from --output
+    #[argh(option)]
+    frames: Option<NonZeroU32>,
+
+    /// frames per second of the animation
+    #[argh(option, default = "24.0", from_str_fn(parse_positive))]
+    fps: f64,
+
+    /// only parse the scene, print warnings and the memory estimate
+    #[argh(switch)]
+    validate: bool,
+
+    /// check the BVHs after building them, failing on broken invariants, for
+    /// debugging
+    #[argh(switch)]
+    validate_accel: bool,
+
+    /// instead of writing the image, print a hash of its pixels, for
+    /// regression testing
+    #[argh(switch)]
+    hash: bool,
+
+    /// burn the scene file name, the frame and its time, and the samples per
+    /// pixel into the bottom left corner of the image
+    #[argh(switch)]
+    label: bool,
+
+    /// print the luminance histogram of the image, its minimum, average and
+    /// maximum, and how many pixels clip, to stderr
+    #[argh(switch)]
+    histogram: bool,
+
+    /// print diagnostics and statistics as JSON, one object per line
+    #[argh(switch)]
+    json: bool,
+
+    /// log the phases of the render and scene warnings to stderr
+    #[argh(switch, short = 'v')]
+    verbose: bool,
+
+    /// keep running and re-render whenever the scene file changes
+    #[argh(switch)]
+    watch: bool,
+
+    #[argh(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(argh::FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Convert(ConvertArgs),
+    Bake(BakeArgs),
+    Info(InfoArgs),
+    Example(ExampleArgs),
+    Serve(ServeArgs),
+    Merge(MergeArgs),
+}
+
+/// Converts an OBJ or STL file to a scene `mesh` block, printed to stdout.
+#[derive(argh::FromArgs)]
+#[argh(subcommand, name = "convert")]
+struct ConvertArgs {
+    /// mesh to convert
+    #[argh(positional)]
+    input: PathBuf,
+    /// flip faces so that they wind consistently, with normals pointing
+    /// outward
+    #[argh(switch)]
+    orient: bool,
+}
+
+/// Lights the meshes of a scene and writes them with the lighting as vertex
+/// colors, in PLY format.
+#[derive(argh::FromArgs)]
+#[argh(subcommand, name = "bake")]
+struct BakeArgs {
+    /// scene to bake
+    #[argh(positional)]
+    scene: PathBuf,
+
+    /// file to write the meshes to, `-` for stdout (the default)
+    #[argh(option, short = 'o')]
+    output: Option<PathBuf>,
+}
+
+/// Prints what is in a scene, along with warnings and the memory estimate.
+#[derive(argh::FromArgs)]
+#[argh(subcommand, name = "info")]
+struct InfoArgs {
+    /// scene to describe
+    #[argh(positional)]
+    scene: PathBuf,
+}
+
+/// Prints a built-in example scene: cornell, spheres or mesh-grid.
+#[derive(argh::FromArgs)]
+#[argh(subcommand, name = "example")]
+struct ExampleArgs {
+    /// which example to print
+    #[argh(positional, from_str_fn(parse_example))]
+    name: Example,
+}
+
+/// Renders scenes POSTed over HTTP and responds with PNG images.
+#[derive(argh::FromArgs)]
+#[argh(subcommand, name = "serve")]
+struct ServeArgs {
+    /// address to listen on, 0.0.0.0 for all interfaces
+    #[argh(option, default = "IpAddr::from([127, 0, 0, 1])")]
+    bind: IpAddr,
+
+    /// port to listen on
+    #[argh(option, default = "8080")]
+    port: u16,
+
+    /// number of threads to use for each render
+    #[argh(option, short = 'j')]
+    jobs: Option<NonZeroUsize>,
+
+    /// memory for each render, in kilobytes
     #[argh(option, default = "640")]
     mem: usize,
 
-    /// width of the image, in pixels
-    #[argh(option, default = "800")]
-    width: u32,
+    /// largest width a request can ask for
+    #[argh(option, default = "1920")]
+    max_width: u32,
+
+    /// largest height a request can ask for
+    #[argh(option, default = "1080")]
+    max_height: u32,
 
-    /// height of the image, in pixels
-    #[argh(option, default = "600")]
-    height: u32,
+    /// time budget for each render, in seconds
+    #[argh(option, default = "Duration::from_secs(30)", from_str_fn(parse_seconds))]
+    timeout: Duration,
 }
 
-fn main() -> anyhow::Result<()> {
-    let args: Args = argh::from_env();
+/// Assembles the image from the partial outputs of renders with --tiles.
+#[derive(argh::FromArgs)]
+#[argh(subcommand, name = "merge")]
+struct MergeArgs {
+    /// partial images, one for each tile subset
+    #[argh(positional)]
+    parts: Vec<PathBuf>,
+
+    /// file to write the image to, `-` for stdout (the default)
+    #[argh(option, short = 'o')]
+    output: Option<PathBuf>,
+
+    /// image format: ppm, ppm-binary, png, qoi or exr, guessed from the output
+    /// file extension by default
+    #[argh(option, from_str_fn(parse_format))]
+    format: Option<Format>,
+
+    /// dithering applied when quantizing colors: none or bayer
+    #[argh(option, default = "rgb::Dither::None", from_str_fn(parse_dither))]
+    dither: rgb::Dither,
+}
+
+#[derive(Clone, Copy)]
+enum MemSize {
+    Auto,
+    Kilobytes(usize),
+}
+
+fn main() {
+    let mut args: Args = argh::from_env();
+    let Err(err) = apply_config(&mut args).and_then(|()| run(&args)) else { return };
+    if broken_pipe(&err) {
+        // Whoever reads the output, like `head`, has seen enough.
+        return;
+    }
+    let code = exit_code(&err);
+    if args.json {
+        let obj = json::Object::new("error").num("code", code.into());
+        let obj = match err.downcast_ref::<SceneError>() {
+            Some(err) => {
+                obj.str("scene", &err.scene).opt_num("line", err.line).str("message", &err.message)
+            }
+            None => obj.str("message", &format!("{err:#}")),
+        };
+        eprintln!("{obj}");
+    } else {
+        eprintln!("Error: {err:?}");
+    }
+    process::exit(code.into());
+}
+
+fn broken_pipe(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|it| it.downcast_ref::<io::Error>())
+        .any(|it| it.kind() == io::ErrorKind::BrokenPipe)
+}
+
+/// Refuses to mess up the terminal with binary data.
+fn check_binary_output(output: Option<&Path>, binary: bool) -> anyhow::Result<()> {
+    let stdout = output.is_none_or(|it| it == Path::new("-"));
+    if binary && stdout && io::stdout().is_terminal() {
+        anyhow::bail!(
+            "refusing to write a binary image to the terminal, \
+             use --output or redirect stdout to a file or a pipe"
+        );
+    }
+    Ok(())
+}
+
+/// See the `error_code`s of [`Args`].
+fn exit_code(err: &anyhow::Error) -> u8 {
+    if cancel::requested() {
+        return 130;
+    }
+    if let Some(err) = err.downcast_ref::<SceneError>() {
+        return if err.oom { 3 } else { 2 };
+    }
+    if err.chain().any(|it| it.is::<io::Error>()) {
+        return 4;
+    }
+    1
+}
 
-    let mut crt = String::new();
-    io::stdin().read_to_string(&mut crt).context("reading input")?;
+impl Args {
+    fn mem(&self) -> MemSize {
+        self.mem.unwrap_or(MemSize::Kilobytes(640))
+    }
+}
 
-    let mut mem = vec![0; args.mem * 1024];
+/// Fills in the options which aren't given on the command line from the
+/// config.
+fn apply_config(args: &mut Args) -> anyhow::Result<()> {
+    let config = config::Config::load()?;
+    if args.jobs.is_none() {
+        args.jobs =
+            config.get("jobs", |it| it.parse().map_err(|_| format!("invalid jobs `{it}`")))?;
+    }
+    if args.mem.is_none() {
+        args.mem = config.get("mem", parse_mem)?;
+    }
+    if args.resolution.is_none() && args.width.is_none() && args.height.is_none() {
+        args.resolution = config.get("resolution", parse_resolution)?;
+    }
+    // The extension of the output file is more specific than the default.
+    if args.format.is_none() && args.output.as_deref().and_then(Format::from_path).is_none() {
+        args.format = config.get("format", parse_format)?;
+    }
+    let dir = config.get("output_dir", |it| Ok(PathBuf::from(it)))?;
+    if let (Some(dir), Some(output)) = (dir, &args.output) {
+        if output.is_relative() && output != Path::new("-") {
+            fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+            args.output = Some(dir.join(output));
+        }
+    }
+    Ok(())
+}
+
+fn run(args: &Args) -> anyhow::Result<()> {
+    match &args.command {
+        Some(Command::Convert(cmd)) => return convert(cmd),
+        Some(Command::Bake(cmd)) => return bake(cmd),
+        Some(Command::Info(cmd)) => return info(cmd),
+        Some(Command::Example(cmd)) => return example(cmd),
+        Some(Command::Merge(cmd)) => return merge(cmd),
+        Some(Command::Serve(cmd)) => {
+            cancel::install();
+            log::install(false);
+            return serve(cmd);
+        }
+        None => (),
+    }
+    cancel::install();
+    if args.verbose {
+        log::install(true);
+    }
+    let mut mem = Vec::new();
     let threads = match args.jobs {
+        Some(it) => Threads::new(it),
+        None if args.background => {
+            // Leave a core for the user.
+            let n_threads = Threads::with_max_threads()?.n_threads() - 1;
+            Threads::new(NonZeroUsize::new(n_threads).unwrap_or(NonZeroUsize::MIN))
+        }
+        None => Threads::with_max_threads()?,
+    };
+    let threads = if args.background { threads.background() } else { threads };
+
+    if args.debug_view.is_some() && args.integrator.is_some() {
+        anyhow::bail!("--debug-view can't be combined with --integrator");
+    }
+    if args.resolution.is_some() && (args.width.is_some() || args.height.is_some()) {
+        anyhow::bail!("--resolution can't be combined with --width or --height");
+    }
+    if args.frames.is_some()
+        && !args.hash
+        && args.output.as_deref().is_none_or(|it| it == Path::new("-"))
+    {
+        anyhow::bail!("--frames needs an --output file");
+    }
+    if args.validate {
+        return validate(args);
+    }
+    if let Some(runs) = args.bench {
+        return bench(args, &threads, &mut mem, runs.get());
+    }
+    if args.preview == Some(Preview::Orbit) {
+        return orbit(args, &threads, &mut mem);
+    }
+    if !args.watch {
+        return render_scene(args, &threads, &mut mem, &|| false);
+    }
+    let Some(scene) = args.scene.as_deref() else {
+        anyhow::bail!("--watch needs a scene file");
+    };
+    if args.output.as_deref().is_none_or(|it| it == Path::new("-")) {
+        anyhow::bail!("--watch needs an --output file");
+    }
+    watch::watch(scene, |changed| {
+        // A render interrupted by a change is restarted right away.
+        if let Err(err) = render_scene(args, &threads, &mut mem, changed) {
+            if !changed() {
+                eprintln!("error: {err:#}");
+            }
+        }
+    })
+    .with_context(|| format!("watching {}", scene.display()))
+}
+
+fn convert(cmd: &ConvertArgs) -> anyhow::Result<()> {
+    let mut mesh = convert::read(&cmd.input)
+        .with_context(|| format!("reading mesh {}", cmd.input.display()))?;
+    if cmd.orient {
+        let n = convert::orient(&mut mesh);
+        eprintln!("reoriented {n} of {} faces", mesh.f.len());
+    }
+    let mut out = Output::create(None)?;
+    convert::write_mesh(&mut out, &mesh)?;
+    out.commit()?;
+    Ok(())
+}
+
+fn bake(cmd: &BakeArgs) -> anyhow::Result<()> {
+    let scene_name = cmd.scene.display();
+    let crt =
+        read_scene(Some(&cmd.scene)).with_context(|| format!("reading scene {scene_name}"))?;
+    let blobs = read_blobs(Some(&cmd.scene), &crt)?;
+    let blobs = lookup(&blobs);
+    let estimate = render::mem_estimate(&crt);
+    let mut mem = vec![0; estimate + estimate / 8 + 1024];
+    let output = cmd.output.as_deref();
+    let name = output_name(output);
+
+    let opts = render::Options { blobs: Some(&blobs), ..Default::default() };
+    render::with_renderer(&crt, &mut mem, &opts, |renderer| {
+        let meshes = &renderer.scene().meshes;
+        if meshes.is_empty() {
+            anyhow::bail!("{scene_name} has no meshes to bake");
+        }
+        let colors: Vec<_> = (0..meshes.len())
+            .map(|i| {
+                let mut colors = vec![[scene::Color::default(); 3]; meshes[i].f.len()];
+                renderer.bake(i, &mut colors);
+                colors
+            })
+            .collect();
+        let mut out = Output::create(output).with_context(|| format!("creating {name}"))?;
+        bake::write_ply(&mut out, renderer.scene(), &colors)?;
+        out.commit().with_context(|| format!("writing {name}"))?;
+        Ok(())
+    })
+    .map_err(|err| SceneError::new(&scene_name, err.line(), err.is_oom(), err))?
+}
+
+fn example(cmd: &ExampleArgs) -> anyhow::Result<()> {
+    let mut out = Output::create(None)?;
+    example::write(&mut out, cmd.name)?;
+    out.commit()?;
+    Ok(())
+}
+
+fn merge(cmd: &MergeArgs) -> anyhow::Result<()> {
+    let output = cmd.output.as_deref();
+    let format = cmd.format.or_else(|| output.and_then(Format::from_path)).unwrap_or(Format::Ppm);
+    check_binary_output(output, format.is_binary())?;
+    let name = output_name(output);
+    let mut out = Output::create(output).with_context(|| format!("creating {name}"))?;
+    partial::merge(&cmd.parts, &mut out, |dim| format.encoder(dim, cmd.dither))?;
+    out.commit().with_context(|| format!("writing {name}"))?;
+    Ok(())
+}
+
+fn serve(cmd: &ServeArgs) -> anyhow::Result<()> {
+    let threads = match cmd.jobs {
         Some(it) => Threads::new(it),
         None => Threads::with_max_threads()?,
     };
-    let mut buf = vec![rgb::Color::default(); (args.width * args.height) as usize];
-    let mut buf = rgb::Buf::new([args.width, args.height], &mut buf);
+    let limits = serve::Limits {
+        max_width: cmd.max_width,
+        max_height: cmd.max_height,
+        mem_kb: cmd.mem,
+        timeout: cmd.timeout,
+    };
+    serve::serve((cmd.bind, cmd.port), &limits, &threads)
+        .with_context(|| format!("serving on {}:{}", cmd.bind, cmd.port))
+}
+
+fn info(cmd: &InfoArgs) -> anyhow::Result<()> {
+    let scene_name = cmd.scene.display();
+    let crt =
+        read_scene(Some(&cmd.scene)).with_context(|| format!("reading scene {scene_name}"))?;
+    let blobs = read_blobs(Some(&cmd.scene), &crt)?;
+    let blobs = lookup(&blobs);
+
+    let estimate = render::mem_estimate(&crt);
+    let mut mem = vec![0; estimate];
+    Mem::with(&mut mem, |mem| {
+        let mut warn = |w: scene::Warning<'_>| println!("{scene_name}: warning: {w}");
+        let scene = scene::Scene::parse_with_warnings(mem, &crt, &blobs, &mut warn)
+            .map_err(|err| SceneError::new(&scene_name, err.line(), err.is_oom(), err))?;
+        info::print(&mut io::stdout(), &scene, estimate)?;
+        Ok(())
+    })
+}
+
+/// `changed` tells that the scene file was modified, and the render should be
+/// abandoned.
+fn render_scene(
+    args: &Args,
+    threads: &Threads,
+    mem: &mut Vec<u8>,
+    changed: &(dyn Fn() -> bool + Sync),
+) -> anyhow::Result<()> {
+    let scene_name = args.scene.as_deref().unwrap_or(Path::new("<stdin>")).display();
+    let crt =
+        read_scene(args.scene.as_deref()).with_context(|| format!("reading scene {scene_name}"))?;
+    let blobs = read_blobs(args.scene.as_deref(), &crt)?;
+    let blobs = lookup(&blobs);
+    let mem = arena(args, &crt, mem);
+    let cancel = || cancel::requested() || changed();
+    let progress = Progress::new();
+    let on_progress = |done, total| progress.update(done, total);
+    let opts = render::Options {
+        blobs: Some(&blobs),
+        cancel: Some(&cancel),
+        progress: Some(&on_progress),
+        ..options(args)
+    };
+    let opts = if args.json { render::Options { clock: Some(bench::clock), ..opts } } else { opts };
+
+    render::with_renderer(&crt, mem, &opts, |renderer| {
+        let Some(frames) = args.frames else {
+            if args.hash {
+                let (hash, stats) = hash_image(args, threads, renderer)?;
+                println!("{hash:016x}");
+                if args.json {
+                    eprintln!("{}", json::Object::new("stats").stats(&stats));
+                }
+                return Ok(());
+            }
+            let label = label(args, renderer, None);
+            let output = args.output.as_deref();
+            let stats = write_image(args, threads, renderer, &progress, output, label.as_deref())?;
+            if args.json {
+                eprintln!("{}", json::Object::new("stats").stats(&stats));
+            }
+            return Ok(());
+        };
+        for frame in 0..frames.get() {
+            renderer.set_time(f64::from(frame) / args.fps);
+            let stats = if args.hash {
+                let (hash, stats) = hash_image(args, threads, renderer)?;
+                println!("{hash:016x}");
+                stats
+            } else {
+                let output = args.output.as_deref().map(|it| frame_path(it, frame + 1));
+                let label = label(args, renderer, Some(frame));
+                write_image(
+                    args,
+                    threads,
+                    renderer,
+                    &progress,
+                    output.as_deref(),
+                    label.as_deref(),
+                )?
+            };
+            if args.json {
+                let obj = json::Object::new("stats").num("frame", (frame + 1).into());
+                eprintln!("{}", obj.stats(&stats));
+            }
+        }
+        Ok(())
+    })
+    .map_err(|err| SceneError::new(&scene_name, err.line(), err.is_oom(), err))?
+}
+
+fn orbit(args: &Args, threads: &Threads, mem: &mut Vec<u8>) -> anyhow::Result<()> {
+    let Some(scene) = args.scene.as_deref() else {
+        anyhow::bail!("--preview orbit needs a scene file, stdin is for the keyboard");
+    };
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        anyhow::bail!("--preview orbit needs a terminal");
+    }
+    let scene_name = scene.display();
+    let crt = read_scene(Some(scene)).with_context(|| format!("reading scene {scene_name}"))?;
+    let blobs = read_blobs(Some(scene), &crt)?;
+    let blobs = lookup(&blobs);
+    let mem = arena(args, &crt, mem);
+
+    let terminal = orbit::Terminal::setup().context("setting up the terminal")?;
+    let input = orbit::Input::start();
+    let pending = input.pending();
+    let cancel = || cancel::requested() || pending.load(SeqCst);
+    let opts = render::Options { blobs: Some(&blobs), cancel: Some(&cancel), ..options(args) };
+    let res =
+        render::with_renderer(&crt, mem, &opts, |renderer| orbit::view(renderer, threads, &input));
+    drop(terminal);
+    res.map_err(|err| SceneError::new(&scene_name, err.line(), err.is_oom(), err))?
+}
+
+/// The text of `--label` for the given frame of an animation.
+fn label(args: &Args, renderer: &render::Renderer<'_, '_>, frame: Option<u32>) -> Option<String> {
+    if !args.label {
+        return None;
+    }
+    let scene = args.scene.as_deref().and_then(Path::file_name);
+    let mut res = scene.map_or("stdin".into(), |it| it.to_string_lossy().into_owned());
+    if let Some(frame) = frame {
+        let time = f64::from(frame) / args.fps;
+        res.push_str(&format!("  frame {}  t {time:.2}s", frame + 1));
+    }
+    let samples = args.samples.map_or(renderer.scene().settings.samples, NonZeroU32::get);
+    res.push_str(&format!("  {samples} spp"));
+    Some(res)
+}
+
+fn write_image(
+    args: &Args,
+    threads: &Threads,
+    renderer: &render::Renderer<'_, '_>,
+    progress: &Progress,
+    output: Option<&Path>,
+    label: Option<&str>,
+) -> anyhow::Result<render::Stats> {
+    let dim @ [width, height] = dim(args, &renderer.scene().camera);
+    let format = args.format.or_else(|| output.and_then(Format::from_path)).unwrap_or(Format::Ppm);
+    let mut encoder: Box<dyn format::Encode> = match args.tiles {
+        Some(tiles) => Box::new(partial::Partial::new(dim, tiles)),
+        None => format.encoder(dim, args.dither),
+    };
+    check_binary_output(output, args.tiles.is_some() || format.is_binary())?;
 
-    render::render(&crt, &mut mem, &|f| threads.in_parallel(f), &mut buf)
-        .map_err(|err| anyhow::format_err!("{err}"))?;
+    let name = output_name(output);
+    let mut out = Output::create(output).with_context(|| format!("creating {name}"))?;
+    let mut started = false;
+    let mut image = match args.preview {
+        Some(Preview::Term) => vec![scene::Color::default(); (width * height) as usize],
+        Some(Preview::Orbit) | None => Vec::new(),
+    };
+    let label = label.map(|it| rgb::Label::new(dim, it));
+    let mut labelled = Vec::new();
+    let mut histogram = args.histogram.then(rgb::Histogram::default);
+    let writer = RowWriter::new(width, |y, row| {
+        if let Some(histogram) = &mut histogram {
+            histogram.add(row);
+        }
+        let row = match &label {
+            Some(label) => {
+                labelled.clear();
+                labelled.extend_from_slice(row);
+                label.row(y, &mut labelled);
+                &labelled[..]
+            }
+            None => row,
+        };
+        if !image.is_empty() {
+            image[(y * width) as usize..][..row.len()].copy_from_slice(row);
+        }
+        if !started {
+            encoder.header(&mut out)?;
+            started = true;
+        }
+        let res = encoder.row(&mut out, y, row);
+        if res.as_ref().is_err_and(|it| it.kind() == io::ErrorKind::BrokenPipe) {
+            cancel::request();
+        }
+        res
+    });
 
-    write_ppm(&buf, &mut io::stdout().lock()).context("writing output")?;
+    let render =
+        || renderer.render_rows(&|f| threads.in_parallel(f), dim, &|span| writer.push(span));
+    let stats = if io::stderr().is_terminal() { progress.report_while(render) } else { render() };
+    let cancelled = renderer.cancelled();
+    if cancelled && !cancel::requested() {
+        // The scene changed, leave the old image alone until the new one is
+        // ready.
+        anyhow::bail!("the scene changed");
+    }
+    if cancelled {
+        writer.fill_missing(height);
+    }
+
+    writer.finish().with_context(|| format!("writing {name}"))?;
+    if !started {
+        encoder.header(&mut out).with_context(|| format!("writing {name}"))?;
+    }
+    encoder.finish(&mut out).with_context(|| format!("writing {name}"))?;
+    out.commit().with_context(|| format!("writing {name}"))?;
+    if let Some(histogram) = &histogram {
+        print_histogram(args, histogram);
+    }
+
+    if args.preview == Some(Preview::Term) {
+        let image = rgb::Buf::new(dim, &mut image);
+        preview::print_term(&mut io::stderr(), &image, args.dither).context("printing preview")?;
+    }
+    if cancelled {
+        anyhow::bail!("interrupted, the image is incomplete");
+    }
+    Ok(stats)
+}
+
+fn print_histogram(args: &Args, histogram: &rgb::Histogram) {
+    if args.json {
+        let obj = json::Object::new("histogram")
+            .float("min", histogram.min)
+            .float("average", histogram.average())
+            .float("max", histogram.max)
+            .float("clipped", histogram.clipped_fraction());
+        eprintln!("{obj}");
+        return;
+    }
+    eprintln!(
+        "luminance: min {:.3}, average {:.3}, max {:.3}, {:.1}% clipped",
+        histogram.min,
+        histogram.average(),
+        histogram.max,
+        100.0 * histogram.clipped_fraction()
+    );
+    // From black on the left to white on the right, as tall as the share of
+    // the pixels, relative to the most common luminance.
+    const BARS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let most = histogram.bins.iter().copied().max().unwrap_or(0).max(1);
+    let bars = histogram.bins.iter().map(|&it| BARS[((it * 8).div_ceil(most)) as usize]);
+    eprintln!("|{}|", bars.collect::<String>());
+}
+
+/// Pixels don't depend on the number of threads or the order in which rows are
+/// rendered, and the sample pattern is fixed by the seed, so the hash only
+/// changes when the image does.
+fn hash_image(
+    args: &Args,
+    threads: &Threads,
+    renderer: &render::Renderer<'_, '_>,
+) -> anyhow::Result<(u64, render::Stats)> {
+    let dim = dim(args, &renderer.scene().camera);
+    let mut hasher = hash::Hasher::new();
+    let writer = RowWriter::new(dim[0], |y, row| {
+        hasher.row(y, row, args.dither);
+        Ok(())
+    });
+    let stats = renderer.render_rows(&|f| threads.in_parallel(f), dim, &|span| writer.push(span));
+    if renderer.cancelled() {
+        anyhow::bail!("interrupted");
+    }
+    writer.finish()?;
+    Ok((hasher.finish(), stats))
+}
+
+fn dim(args: &Args, camera: &scene::Camera) -> rgb::Idx {
+    if let Some(it) = args.resolution {
+        return it;
+    }
+    let width = args.width.unwrap_or(800);
+    let aspect = camera.height / camera.width;
+    // A degenerate camera, which the parser warns about, gets the default.
+    let aspect = if aspect.is_finite() && aspect > 0.0 { aspect } else { 0.75 };
+    let height = match (args.width, args.height) {
+        (_, Some(it)) => it,
+        (Some(_), None) => ((width as f64 * aspect).round() as u32).max(1),
+        (None, None) => 600,
+    };
+    [width, height]
+}
+
+fn output_name(output: Option<&Path>) -> String {
+    match output {
+        Some(it) if it != Path::new("-") => it.display().to_string(),
+        _ => "<stdout>".to_string(),
+    }
+}
+
+/// `out.png` becomes `out_0001.png` for the first frame.
+fn frame_path(path: &Path, frame: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{stem}_{frame:04}");
+    if let Some(ext) = path.extension() {
+        name.push('.');
+        name.push_str(&ext.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
+
+fn bench(args: &Args, threads: &Threads, mem: &mut Vec<u8>, runs: u32) -> anyhow::Result<()> {
+    let scene_name = args.scene.as_deref().unwrap_or(Path::new("<stdin>")).display();
+    let crt =
+        read_scene(args.scene.as_deref()).with_context(|| format!("reading scene {scene_name}"))?;
+    let blobs = read_blobs(args.scene.as_deref(), &crt)?;
+    let blobs = lookup(&blobs);
+    let mem = arena(args, &crt, mem);
+    let opts = render::Options { blobs: Some(&blobs), clock: Some(bench::clock), ..options(args) };
+    let dim = render::with_renderer(&crt, mem, &opts, |it| dim(args, &it.scene().camera))
+        .map_err(|err| SceneError::new(&scene_name, err.line(), err.is_oom(), err))?;
+
+    let mut bench = Bench::new();
+    for _ in 0..runs {
+        let stats =
+            render::render_rows(&crt, mem, &opts, &|f| threads.in_parallel(f), dim, &|_span| ())
+                .map_err(|err| SceneError::new(&scene_name, err.line(), err.is_oom(), err))?;
+        if args.json {
+            println!("{}", json::Object::new("stats").stats(&stats));
+        }
+        bench.add(stats);
+        if cancel::requested() {
+            anyhow::bail!("interrupted");
+        }
+    }
+    if !args.json {
+        bench.print();
+    }
     Ok(())
 }
 
-fn write_ppm(buf: &rgb::Buf, w: &mut dyn io::Write) -> io::Result<()> {
-    let magic_number = "P3";
-    let max_color = 255;
-    write!(w, "{}\n{} {}\n{}\n", magic_number, buf.width(), buf.height(), max_color)?;
+fn validate(args: &Args) -> anyhow::Result<()> {
+    let scene_name = args.scene.as_deref().unwrap_or(Path::new("<stdin>")).display();
+    let crt =
+        read_scene(args.scene.as_deref()).with_context(|| format!("reading scene {scene_name}"))?;
+    let blobs = read_blobs(args.scene.as_deref(), &crt)?;
+    let blobs = lookup(&blobs);
+
+    let estimate = render::mem_estimate(&crt);
+    let mut mem = vec![0; estimate];
+    Mem::with(&mut mem, |mem| {
+        let mut warn = |w: scene::Warning<'_>| {
+            if args.json {
+                let obj = json::Object::new("warning").str("scene", &scene_name.to_string());
+                println!("{}", obj.opt_num("line", w.line()).str("message", &w.to_string()));
+            } else {
+                println!("{scene_name}: warning: {w}")
+            }
+        };
+        scene::Scene::parse_with_warnings(mem, &crt, &blobs, &mut warn).map(drop)
+    })
+    .map_err(|err| SceneError::new(&scene_name, err.line(), err.is_oom(), err))?;
 
-    for idx in buf.by_row() {
-        if idx[0] == 0 {
-            write!(w, "\n")?;
+    let kb = estimate.div_ceil(1024);
+    if args.json {
+        println!("{}", json::Object::new("memory").num("bytes", estimate as u64));
+    } else {
+        println!("{scene_name}: needs at most {kb} kilobytes of memory");
+    }
+    if let MemSize::Kilobytes(mem) = args.mem() {
+        if kb > mem {
+            let message = format!("--mem {mem} is not enough");
+            if args.json {
+                let obj = json::Object::new("warning").str("scene", &scene_name.to_string());
+                println!("{}", obj.opt_num("line", None).str("message", &message));
+            } else {
+                println!("{scene_name}: warning: {message}");
+            }
         }
-        let rgb::Color { r, g, b } = buf[idx];
-        write!(w, "{r:3} {g:3} {b:3}  ")?;
     }
     Ok(())
 }
+
+/// Sizes `mem` according to `--mem`, reusing the allocation across renders.
+fn arena<'m>(args: &Args, crt: &str, mem: &'m mut Vec<u8>) -> &'m mut [u8] {
+    let size = match args.mem() {
+        // The estimate is an upper bound, the headroom is for alignment slop
+        // the estimate might have missed.
+        MemSize::Auto => {
+            let estimate = render::mem_estimate(crt);
+            estimate + estimate / 8 + 1024
+        }
+        MemSize::Kilobytes(kb) => kb * 1024,
+    };
+    mem.clear();
+    mem.resize(size, 0);
+    mem
+}
+
+fn options(args: &Args) -> render::Options<'_> {
+    render::Options {
+        samples: args.samples.map(NonZeroU32::get),
+        bounces: args.bounces,
+        exposure: args.exposure,
+        auto_exposure: args.auto_exposure.then_some(true),
+        temperature: args.temperature,
+        tint: args.tint,
+        tonemap: args.tonemap,
+        gamma: args.gamma,
+        integrator: args.debug_view.or(args.integrator),
+        seed: args.seed,
+        shadow_bias: args.shadow_bias,
+        lights: args.lights.as_deref(),
+        objects: args.objects.as_deref(),
+        tiles: args.tiles,
+        order: args.order,
+        chunk: args.chunk.map(NonZeroU32::get),
+        validate_accel: args.validate_accel,
+        cancel: Some(&cancel::requested),
+        ..render::Options::default()
+    }
+}
+
+/// A problem with the scene itself, remembered separately from I/O errors so
+/// that `--json` can report the location.
+#[derive(Debug)]
+struct SceneError {
+    scene: String,
+    line: Option<u32>,
+    /// The scene is valid, but needs more memory.
+    oom: bool,
+    message: String,
+}
+
+impl SceneError {
+    fn new(
+        scene: &impl fmt::Display,
+        line: Option<u32>,
+        oom: bool,
+        message: impl fmt::Display,
+    ) -> SceneError {
+        SceneError { scene: scene.to_string(), line, oom, message: message.to_string() }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.scene, self.message)
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+fn read_scene(path: Option<&Path>) -> io::Result<String> {
+    match path {
+        Some(path) => fs::read_to_string(path),
+        None => {
+            let mut crt = String::new();
+            io::stdin().read_to_string(&mut crt)?;
+            Ok(crt)
+        }
+    }
+}
+
+/// Reads the binary mesh data `crt` refers to, with names relative to the
+/// directory of the scene file.
+fn read_blobs(scene: Option<&Path>, crt: &str) -> anyhow::Result<HashMap<String, Vec<u8>>> {
+    let dir = scene.and_then(Path::parent).unwrap_or(Path::new(""));
+    let mut res = HashMap::new();
+    for name in scene::Scene::blobs(crt) {
+        if !res.contains_key(name) {
+            let path = dir.join(name);
+            let blob = fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
+            res.insert(name.to_string(), blob);
+        }
+    }
+    Ok(res)
+}
+
+fn lookup(blobs: &HashMap<String, Vec<u8>>) -> Box<scene::Blobs<'_>> {
+    Box::new(|name, f| {
+        if let Some(blob) = blobs.get(name) {
+            f(blob)
+        }
+    })
+}
+
+fn parse_format(value: &str) -> Result<Format, String> {
+    Format::parse(value)
+}
+
+fn parse_example(value: &str) -> Result<Example, String> {
+    Example::parse(value)
+}
+
+fn parse_tiles(value: &str) -> Result<render::Tiles, String> {
+    let err = || format!("invalid tiles `{value}`, expected i/N with i from 1 to N");
+    let (index, count) = value.split_once('/').ok_or_else(err)?;
+    let (index, count) = (index.parse::<u32>(), count.parse::<u32>());
+    match (index, count) {
+        (Ok(index), Ok(count)) if 1 <= index && index <= count => {
+            Ok(render::Tiles { index: index - 1, count })
+        }
+        _ => Err(err()),
+    }
+}
+
+fn parse_resolution(value: &str) -> Result<rgb::Idx, String> {
+    let err = || format!("invalid resolution `{value}`, expected WIDTHxHEIGHT");
+    let [width, height] = geom::split_n(value, 'x').ok_or_else(err)?;
+    match (width.parse::<u32>(), height.parse::<u32>()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok([width, height]),
+        _ => Err(err()),
+    }
+}
+
+fn parse_mem(value: &str) -> Result<MemSize, String> {
+    match value {
+        "auto" => Ok(MemSize::Auto),
+        _ => value
+            .parse()
+            .map(MemSize::Kilobytes)
+            .map_err(|_| format!("invalid memory size `{value}`, expected kilobytes or auto")),
+    }
+}
+
+fn parse_preview(value: &str) -> Result<Preview, String> {
+    Preview::parse(value)
+}
+
+fn parse_dither(value: &str) -> Result<rgb::Dither, String> {
+    match value {
+        "none" => Ok(rgb::Dither::None),
+        "bayer" => Ok(rgb::Dither::Bayer),
+        _ => Err(format!("unknown dithering `{value}`, expected none or bayer")),
+    }
+}
+
+fn parse_tonemap(value: &str) -> Result<scene::Tonemap, String> {
+    scene::Tonemap::from_name(value)
+        .ok_or_else(|| format!("unknown tone mapping `{value}`, expected none or reinhard"))
+}
+
+fn parse_order(value: &str) -> Result<render::Order, String> {
+    render::Order::from_name(value).ok_or_else(|| {
+        format!(
+            "unknown order `{value}`, expected top-down, center-out, interleaved or \
+             costliest-first"
+        )
+    })
+}
+
+fn parse_positive(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(it) if it > 0.0 => Ok(it),
+        _ => Err(format!("invalid value `{value}`, expected a positive number")),
+    }
+}
+
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    let secs = value.parse::<f64>().ok().filter(|&it| it > 0.0);
+    match secs.and_then(|it| Duration::try_from_secs_f64(it).ok()) {
+        Some(it) => Ok(it),
+        None => Err(format!("invalid duration `{value}`, expected a positive number of seconds")),
+    }
+}
+
+fn parse_finite(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(it) if it.is_finite() => Ok(it),
+        _ => Err(format!("invalid value `{value}`, expected a number")),
+    }
+}
+
+fn parse_debug_view(value: &str) -> Result<scene::Integrator, String> {
+    use scene::Integrator::{Path, Whitted};
+    scene::Integrator::from_name(value).filter(|it| !matches!(it, Whitted | Path)).ok_or_else(
+        || {
+            format!(
+                "unknown debug view `{value}`, expected normals, depth, ao, bvh-heatmap, \
+                 wireframe or false-color"
+            )
+        },
+    )
+}
+
+fn parse_integrator(value: &str) -> Result<scene::Integrator, String> {
+    scene::Integrator::from_name(value).ok_or_else(|| {
+        format!(
+            "unknown integrator `{value}`, expected whitted, path, normals, depth, ao, \
+             bvh-heatmap, wireframe or false-color"
+        )
+    })
+}
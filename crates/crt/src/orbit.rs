@@ -0,0 +1,344 @@
+//! `--preview orbit`: an interactive viewer in the terminal, for a quick look
+//! at imported meshes from all sides.
+//!
+//! Dragging with the left mouse button orbits the camera around the point it
+//! looks at, dragging with the right one (or with shift held) pans, and the
+//! wheel zooms. Arrows, `wasd` and `+`/`-` do the same from the keyboard, `r`
+//! goes back to the scene's camera and `q` quits. Every change restarts the
+//! render, which shows up at half the resolution first.
+//!
+//! Mouse events come from the xterm SGR mouse protocol, which most terminal
+//! emulators support.
+
+use std::{
+    io::{self, Read, Write},
+    iter,
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use geom::{cross, dot, v64};
+use render::rgb;
+use threads::Threads;
+
+use crate::cancel;
+
+const HELP: &str = "drag: orbit  right drag: pan  wheel: zoom  r: reset  q: quit";
+
+/// Raw-ish terminal mode, on the alternate screen with mouse reporting, for
+/// as long as this is alive.
+pub(crate) struct Terminal {
+    saved: String,
+}
+
+impl Terminal {
+    pub(crate) fn setup() -> io::Result<Terminal> {
+        let saved = stty(&["-g"])?.trim().to_string();
+        // Keeps `isig`, so that Ctrl-C still works as usual.
+        stty(&["-icanon", "-echo", "min", "1"])?;
+        let mut stderr = io::stderr();
+        stderr.write_all(b"\x1b[?1049h\x1b[?25l\x1b[?1002h\x1b[?1006h")?;
+        stderr.flush()?;
+        Ok(Terminal { saved })
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = io::stderr().write_all(b"\x1b[?1006l\x1b[?1002l\x1b[?25h\x1b[?1049l");
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+/// Keyboard and mouse input, read and decoded on a background thread.
+pub(crate) struct Input {
+    actions: mpsc::Receiver<Action>,
+    pending: Arc<AtomicBool>,
+}
+
+impl Input {
+    pub(crate) fn start() -> Input {
+        let (tx, actions) = mpsc::channel();
+        let pending = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&pending);
+        thread::spawn(move || {
+            let mut stdin = io::stdin().lock();
+            let mut decoder = Decoder::default();
+            let mut buf = [0; 64];
+            while let Ok(n @ 1..) = stdin.read(&mut buf) {
+                for action in buf[..n].iter().filter_map(|&b| decoder.push(b)) {
+                    flag.store(true, SeqCst);
+                    if tx.send(action).is_err() {
+                        return;
+                    }
+                }
+            }
+            let _ = tx.send(Action::Quit);
+        });
+        Input { actions, pending }
+    }
+
+    /// Set when there is input the current render doesn't reflect yet, to
+    /// cancel it.
+    pub(crate) fn pending(&self) -> &AtomicBool {
+        &self.pending
+    }
+
+    /// Blocks until there is input, or returns `None` on Ctrl-C.
+    fn wait(&self) -> Option<Action> {
+        loop {
+            if cancel::requested() {
+                return None;
+            }
+            match self.actions.recv_timeout(Duration::from_millis(100)) {
+                Ok(it) => return Some(it),
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Action {
+    /// Radians to move the camera along the screen axes, right and up.
+    Rotate(f64, f64),
+    /// Fractions of the distance to the target to move by, right and up.
+    Pan(f64, f64),
+    /// Factor to scale the distance to the target by.
+    Zoom(f64),
+    Reset,
+    Quit,
+}
+
+/// Re-renders the scene whenever the camera moves, until `q` or Ctrl-C.
+pub(crate) fn view(
+    renderer: &mut render::Renderer<'_, '_>,
+    threads: &Threads,
+    input: &Input,
+) -> anyhow::Result<()> {
+    let home = renderer.scene().camera;
+    let mut camera = home;
+    loop {
+        input.pending.store(false, SeqCst);
+        renderer.set_camera(camera);
+        let [cols, rows] = terminal_size();
+        let dim = fit(&camera, [cols, 2 * rows.saturating_sub(1)]);
+        let mut screen = vec![rgb::Color::default(); (dim[0] * dim[1]) as usize];
+        let mut screen = rgb::Buf::new(dim, &mut screen);
+        for scale in [2, 1] {
+            let small = dim.map(|it| (it / scale).max(1));
+            let mut pixels = vec![rgb::Color::default(); (small[0] * small[1]) as usize];
+            let mut pixels = rgb::Buf::new(small, &mut pixels);
+            renderer.render(&|f| threads.in_parallel(f), &mut pixels);
+            if renderer.cancelled() {
+                break;
+            }
+            pixels.resize(&mut screen);
+            draw(&screen)?;
+        }
+
+        let Some(first) = input.wait() else { return Ok(()) };
+        for action in iter::once(first).chain(input.actions.try_iter()) {
+            match action {
+                Action::Rotate(right, up) => rotate(&mut camera, right, up),
+                Action::Pan(right, up) => pan(&mut camera, right, up),
+                Action::Zoom(factor) => zoom(&mut camera, factor),
+                Action::Reset => camera = home,
+                Action::Quit => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Moves the camera over the sphere around `look_at`. The camera's `up`
+/// stays the axis of rotation, and the camera never quite reaches it, so
+/// that the horizon doesn't flip.
+fn rotate(camera: &mut scene::Camera, right: f64, up: f64) {
+    let axis = camera.up.to_unit();
+    let offset = rotate_around(camera.pos - camera.look_at, axis, right);
+    let screen_right = cross(axis, offset).to_unit();
+    let tilted = rotate_around(offset, screen_right, -up);
+    let offset = if dot(tilted.to_unit(), axis).abs() < 0.99 { tilted } else { offset };
+    camera.pos = camera.look_at + offset;
+}
+
+fn pan(camera: &mut scene::Camera, right: f64, up: f64) {
+    let offset = camera.pos - camera.look_at;
+    let gaze = -offset.to_unit();
+    let screen_right = cross(gaze, camera.up).to_unit();
+    let screen_up = cross(screen_right, gaze);
+    let shift = (screen_right * right + screen_up * up) * offset.norm();
+    camera.pos = camera.pos + shift;
+    camera.look_at = camera.look_at + shift;
+}
+
+fn zoom(camera: &mut scene::Camera, factor: f64) {
+    let offset = (camera.pos - camera.look_at) * factor;
+    if offset.norm() > 1e-6 {
+        camera.pos = camera.look_at + offset;
+    }
+}
+
+/// Rodrigues' rotation of `v` by `angle` around the unit vector `axis`.
+fn rotate_around(v: v64, axis: v64, angle: f64) -> v64 {
+    let (sin, cos) = angle.sin_cos();
+    v * cos + cross(axis, v) * sin + axis * (dot(axis, v) * (1.0 - cos))
+}
+
+/// The largest image with the camera's aspect ratio which fits into `max`.
+fn fit(camera: &scene::Camera, [max_width, max_height]: rgb::Idx) -> rgb::Idx {
+    let aspect = camera.width / camera.height;
+    let height = (max_width as f64 / aspect).round() as u32;
+    if height <= max_height {
+        return [max_width.max(1), height.max(1)];
+    }
+    let width = (max_height as f64 * aspect).round() as u32;
+    [width.clamp(1, max_width.max(1)), max_height.max(1)]
+}
+
+fn draw(screen: &rgb::Buf<'_>) -> io::Result<()> {
+    let mut frame = b"\x1b[H".to_vec();
+    crate::preview::write_blocks(&mut frame, screen)?;
+    frame.extend_from_slice(format!("\x1b[J{HELP}").as_bytes());
+    let mut stderr = io::stderr().lock();
+    stderr.write_all(&frame)?;
+    stderr.flush()
+}
+
+/// Columns and rows, from `stty size`.
+fn terminal_size() -> rgb::Idx {
+    let size = stty(&["size"]).ok().and_then(|it| {
+        let (rows, cols) = it.trim().split_once(' ')?;
+        Some([cols.parse().ok()?, rows.parse().ok()?]).filter(|&[cols, rows]| cols > 0 && rows > 1)
+    });
+    size.unwrap_or([80, 24])
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty").args(args).stdin(Stdio::inherit()).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!("stty: {}", stderr.trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Turns the bytes of keys and SGR mouse reports, `ESC [ < b ; x ; y M`, into
+/// [`Action`]s.
+#[derive(Default)]
+struct Decoder {
+    seq: Vec<u8>,
+    /// Where the last mouse event with a button held was, to turn drags into
+    /// relative moves.
+    drag: Option<[i32; 2]>,
+}
+
+impl Decoder {
+    fn push(&mut self, b: u8) -> Option<Action> {
+        if self.seq.is_empty() && b != 0x1b {
+            return key(b);
+        }
+        self.seq.push(b);
+        let done = match self.seq.as_slice() {
+            [0x1b] | [0x1b, b'['] | [0x1b, b'[', b'<', ..] if !matches!(b, b'M' | b'm') => false,
+            [0x1b, b'[', ..] => true,
+            // Alt and a key, or a lone escape followed by one.
+            _ => true,
+        };
+        if !done {
+            return None;
+        }
+        let seq = std::mem::take(&mut self.seq);
+        match seq.as_slice() {
+            [0x1b, b'[', b'A'] => Some(Action::Rotate(0.0, 0.15)),
+            [0x1b, b'[', b'B'] => Some(Action::Rotate(0.0, -0.15)),
+            [0x1b, b'[', b'C'] => Some(Action::Rotate(0.15, 0.0)),
+            [0x1b, b'[', b'D'] => Some(Action::Rotate(-0.15, 0.0)),
+            [0x1b, b'[', b'<', report @ ..] => self.mouse(report),
+            _ => None,
+        }
+    }
+
+    fn mouse(&mut self, report: &[u8]) -> Option<Action> {
+        let (&kind, report) = report.split_last()?;
+        let report = std::str::from_utf8(report).ok()?;
+        let mut fields = report.split(';').map(|it| it.parse::<i32>().ok());
+        let [button, x, y] = [fields.next()??, fields.next()??, fields.next()??];
+        if kind == b'm' {
+            self.drag = None;
+            return None;
+        }
+        const WHEEL: i32 = 64;
+        const MOTION: i32 = 32;
+        const SHIFT: i32 = 4;
+        if button & WHEEL != 0 {
+            return Some(Action::Zoom(if button & 1 == 0 { 0.9 } else { 1.0 / 0.9 }));
+        }
+        let last = self.drag.replace([x, y]);
+        if button & MOTION == 0 {
+            return None;
+        }
+        let [dx, dy] = last.map(|[lx, ly]| [x - lx, y - ly])?.map(f64::from);
+        // Cells are about twice as tall as they are wide.
+        let (dx, dy) = (-dx * 0.03, dy * 0.06);
+        if button & 3 != 0 || button & SHIFT != 0 {
+            Some(Action::Pan(dx / 2.0, -dy / 2.0))
+        } else {
+            Some(Action::Rotate(dx, dy))
+        }
+    }
+}
+
+fn key(b: u8) -> Option<Action> {
+    let res = match b {
+        b'q' | b'Q' => Action::Quit,
+        b'r' | b'R' => Action::Reset,
+        b'+' | b'=' => Action::Zoom(0.8),
+        b'-' | b'_' => Action::Zoom(1.25),
+        b'w' => Action::Pan(0.0, 0.1),
+        b'a' => Action::Pan(-0.1, 0.0),
+        b's' => Action::Pan(0.0, -0.1),
+        b'd' => Action::Pan(0.1, 0.0),
+        _ => return None,
+    };
+    Some(res)
+}
+
+#[test]
+fn test_orbit() {
+    let mut camera = scene::Camera {
+        pos: v64(0.0, 0.0, -10.0),
+        look_at: v64(1.0, 2.0, 3.0),
+        up: v64(0.0, 1.0, 0.0),
+        ..scene::Camera::default()
+    };
+    let distance = (camera.pos - camera.look_at).norm();
+    rotate(&mut camera, 0.7, -0.4);
+    assert!(((camera.pos - camera.look_at).norm() - distance).abs() < 1e-9);
+    for _ in 0..100 {
+        rotate(&mut camera, 0.0, 0.3);
+    }
+    let offset = (camera.pos - camera.look_at).to_unit();
+    assert!(dot(offset, camera.up) < 0.99);
+
+    let mut decoder = Decoder::default();
+    let mut actions = Vec::new();
+    for &b in b"q\x1b[A\x1b[<0;10;5M\x1b[<32;12;5M\x1b[<0;12;5m\x1b[<65;1;1M" {
+        actions.extend(decoder.push(b));
+    }
+    assert_eq!(
+        actions,
+        [
+            Action::Quit,
+            Action::Rotate(0.0, 0.15),
+            Action::Rotate(-0.06, 0.0),
+            Action::Zoom(1.0 / 0.9),
+        ]
+    );
+}
@@ -0,0 +1,65 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+/// Where the image goes: stdout, or a file which is written to a temporary
+/// location and renamed into place on `commit`, so that an interrupted render
+/// never leaves a truncated image behind.
+pub(crate) enum Output {
+    Stdout(io::BufWriter<io::Stdout>),
+    File { file: io::BufWriter<fs::File>, tmp: PathBuf, path: PathBuf },
+}
+
+impl Output {
+    /// `None` or `-` mean stdout.
+    pub(crate) fn create(path: Option<&Path>) -> io::Result<Output> {
+        let path = match path {
+            Some(it) if it != Path::new("-") => it,
+            _ => return Ok(Output::Stdout(io::BufWriter::new(io::stdout()))),
+        };
+        let name = path.file_name().ok_or(io::ErrorKind::InvalidInput)?.to_string_lossy();
+        let tmp = path.with_file_name(format!(".{name}.{}.tmp", process::id()));
+        let file = io::BufWriter::new(fs::File::create(&tmp)?);
+        Ok(Output::File { file, tmp, path: path.to_path_buf() })
+    }
+
+    pub(crate) fn commit(mut self) -> io::Result<()> {
+        self.flush()?;
+        if let Output::File { file, tmp, path } = &mut self {
+            file.get_ref().sync_all()?;
+            fs::rename(&*tmp, &*path)?;
+            // Disarm the cleanup in `drop`.
+            tmp.clear();
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(it) => it.write(buf),
+            Output::File { file, .. } => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(it) => it.flush(),
+            Output::File { file, .. } => file.flush(),
+        }
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        if let Output::File { tmp, .. } = self {
+            if !tmp.as_os_str().is_empty() {
+                let _ = fs::remove_file(tmp);
+            }
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! Container for the tiles rendered with `--tiles`, which `crt merge` puts
+//! together into the final image.
+//!
+//! After a header of `MAGIC` and little-endian `u32` width, height, tile size,
+//! tile index and tile count, come the linear colors of the pixels in the
+//! tiles, as little-endian `f64` triples, row by row. Pixels are stored at full
+//! precision, so that the merged image is exactly what a single process would
+//! have rendered.
+
+use std::{
+    fs,
+    io::{self, BufReader, Read, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use render::{rgb, Tiles};
+
+use crate::format::Encode;
+
+const MAGIC: &[u8; 8] = b"crt-part";
+
+pub(crate) struct Partial {
+    dim: rgb::Idx,
+    tiles: Tiles,
+}
+
+impl Partial {
+    pub(crate) fn new(dim: rgb::Idx, tiles: Tiles) -> Partial {
+        Partial { dim, tiles }
+    }
+}
+
+impl Encode for Partial {
+    fn header(&mut self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        let [width, height] = self.dim;
+        for value in [width, height, Tiles::SIZE, self.tiles.index, self.tiles.count] {
+            w.write_all(&value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn row(&mut self, w: &mut dyn Write, y: u32, row: &[scene::Color]) -> io::Result<()> {
+        let mut data = Vec::new();
+        for (x, c) in row.iter().enumerate() {
+            if self.tiles.contains(self.dim[0], [x as u32, y]) {
+                for value in [c.r, c.g, c.b] {
+                    data.extend(value.to_le_bytes());
+                }
+            }
+        }
+        w.write_all(&data)
+    }
+
+    fn finish(&mut self, _w: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Part {
+    dim: rgb::Idx,
+    tiles: Tiles,
+    r: BufReader<fs::File>,
+}
+
+fn open(path: &Path) -> anyhow::Result<Part> {
+    let mut r = BufReader::new(fs::File::open(path)?);
+    let mut magic = [0; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        anyhow::bail!("not a partial image, render it with --tiles");
+    }
+    let mut header = [0; 5];
+    for value in &mut header {
+        let mut bytes = [0; 4];
+        r.read_exact(&mut bytes)?;
+        *value = u32::from_le_bytes(bytes);
+    }
+    let [width, height, size, index, count] = header;
+    if size != Tiles::SIZE {
+        anyhow::bail!("tiles are {size} pixels, expected {}", Tiles::SIZE);
+    }
+    if count == 0 || index >= count {
+        anyhow::bail!("invalid tile {index} of {count}");
+    }
+    Ok(Part { dim: [width, height], tiles: Tiles { index, count }, r })
+}
+
+/// Assembles partial images, which must cover all the tiles exactly once, and
+/// encodes the result with the encoder made for its dimensions.
+pub(crate) fn merge(
+    paths: &[impl AsRef<Path>],
+    w: &mut dyn Write,
+    encoder: impl FnOnce(rgb::Idx) -> Box<dyn Encode>,
+) -> anyhow::Result<()> {
+    let mut parts = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        parts.push(open(path).with_context(|| format!("reading {}", path.display()))?);
+    }
+    let Some(first) = parts.first() else {
+        anyhow::bail!("nothing to merge");
+    };
+    let (dim, count) = (first.dim, first.tiles.count);
+    if let Some(part) = parts.iter().find(|it| it.dim != dim || it.tiles.count != count) {
+        let [w0, h0] = dim;
+        let [w1, h1] = part.dim;
+        anyhow::bail!(
+            "partial images are from different renders: {w0}x{h0} in {count} tiles and {w1}x{h1} in {} tiles",
+            part.tiles.count
+        );
+    }
+    parts.sort_by_key(|it| it.tiles.index);
+    for (i, part) in parts.iter().enumerate() {
+        let index = part.tiles.index;
+        if index > i as u32 {
+            anyhow::bail!("tile {}/{count} is missing", i + 1);
+        }
+        if index < i as u32 {
+            anyhow::bail!("tile {}/{count} is given twice", index + 1);
+        }
+    }
+    if parts.len() != count as usize {
+        anyhow::bail!("tile {}/{count} is missing", parts.len() + 1);
+    }
+
+    let mut encoder = encoder(dim);
+    encoder.header(w)?;
+    let [width, height] = dim;
+    let mut row = vec![scene::Color::default(); width as usize];
+    for y in 0..height {
+        for (x, c) in row.iter_mut().enumerate() {
+            let owner = Tiles::owner(count, width, [x as u32, y]);
+            let mut bytes = [0; 24];
+            parts[owner.index as usize]
+                .r
+                .read_exact(&mut bytes)
+                .with_context(|| format!("tile {}/{count} is truncated", owner.index + 1))?;
+            let [r, g, b] =
+                [0, 8, 16].map(|i| f64::from_le_bytes(bytes[i..i + 8].try_into().unwrap()));
+            *c = scene::Color::new(r, g, b);
+        }
+        encoder.row(w, y, &row)?;
+    }
+    encoder.finish(w)?;
+    Ok(())
+}
+
+#[test]
+fn test_merge() {
+    let dim @ [width, height] = [100, 70];
+    let color = |x: u32, y: u32| scene::Color::new(x as f64 / 7.0, y as f64 / 3.0, 0.5);
+    let encode = |tiles: Tiles| {
+        let mut partial = Partial::new(dim, tiles);
+        let mut res = Vec::new();
+        partial.header(&mut res).unwrap();
+        for y in 0..height {
+            let row: Vec<_> = (0..width).map(|x| color(x, y)).collect();
+            partial.row(&mut res, y, &row).unwrap();
+        }
+        res
+    };
+    let dir = std::env::temp_dir().join(format!("crt-test-merge-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let paths = [0, 1].map(|index| {
+        let path = dir.join(format!("{index}.part"));
+        fs::write(&path, encode(Tiles { index, count: 2 })).unwrap();
+        path
+    });
+    let merge = |paths: &[&Path]| {
+        let mut res = Vec::new();
+        let whole = Tiles { index: 0, count: 1 };
+        merge(paths, &mut res, |dim| Box::new(Partial::new(dim, whole))).map(|()| res)
+    };
+
+    let merged = merge(&[&paths[1], &paths[0]]).unwrap();
+    assert!(merged == encode(Tiles { index: 0, count: 1 }));
+    let err = merge(&[&paths[1]]).unwrap_err();
+    assert_eq!(err.to_string(), "tile 1/2 is missing");
+    let err = merge(&[&paths[0], &paths[0], &paths[1]]).unwrap_err();
+    assert_eq!(err.to_string(), "tile 1/2 is given twice");
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,74 @@
+//! Shows the rendered image right in the terminal, using 24-bit ANSI colors
+//! and the upper half block character, so each cell displays two pixels.
+
+use std::{
+    env,
+    io::{self, Write},
+};
+
+use render::rgb;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Preview {
+    Term,
+    /// Interactive, see [`crate::orbit`].
+    Orbit,
+}
+
+impl Preview {
+    pub(crate) fn parse(value: &str) -> Result<Preview, String> {
+        match value {
+            "term" => Ok(Preview::Term),
+            "orbit" => Ok(Preview::Orbit),
+            _ => Err(format!("unknown preview `{value}`, expected term or orbit")),
+        }
+    }
+}
+
+/// Downsamples `image` to fit the width of the terminal and prints it.
+pub(crate) fn print_term(
+    w: &mut dyn Write,
+    image: &rgb::FBuf<'_>,
+    dither: rgb::Dither,
+) -> io::Result<()> {
+    let [width, height] = image.dim();
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+    let columns = env::var("COLUMNS").ok().and_then(|it| it.parse::<u32>().ok()).unwrap_or(80);
+    let cols = columns.clamp(1, width);
+    let rows = (u64::from(cols) * u64::from(height) / u64::from(width)).max(1) as u32;
+    let rows = rows.next_multiple_of(2);
+
+    let mut small = vec![scene::Color::default(); (cols * rows) as usize];
+    let mut small = rgb::Buf::new([cols, rows], &mut small);
+    image.resize(&mut small);
+
+    let mut pixels = vec![rgb::Color::default(); (cols * rows) as usize];
+    let mut pixels = rgb::Buf::new([cols, rows], &mut pixels);
+    for idx in small.by_row() {
+        pixels[idx] = rgb::quantize(&small[idx], idx, dither);
+    }
+    write_blocks(w, &pixels)?;
+    w.flush()
+}
+
+/// Writes `image` as lines of half blocks, one for every two rows of pixels.
+pub(crate) fn write_blocks(w: &mut dyn Write, image: &rgb::Buf<'_>) -> io::Result<()> {
+    let [width, height] = image.dim();
+    let mut line = String::new();
+    for y in (0..height).step_by(2) {
+        line.clear();
+        for x in 0..width {
+            let top = image[[x, y]];
+            let bottom = if y + 1 < height { image[[x, y + 1]] } else { rgb::Color::default() };
+            line.push_str(&format!(
+                "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                top.r, top.g, top.b, bottom.r, bottom.g, bottom.b
+            ));
+        }
+        line.push_str("\x1b[0m\n");
+        w.write_all(line.as_bytes())?;
+    }
+    Ok(())
+}
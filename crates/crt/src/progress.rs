@@ -0,0 +1,63 @@
+use std::{
+    io::{self, Write},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering::Relaxed},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Tracks finished rows and periodically prints percentage, elapsed time and
+/// an estimate of the remaining time to stderr.
+pub(crate) struct Progress {
+    done: AtomicU64,
+    total: AtomicU64,
+    finished: AtomicBool,
+}
+
+impl Progress {
+    pub(crate) fn new() -> Progress {
+        Progress {
+            done: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    /// For [`render::Options::progress`].
+    pub(crate) fn update(&self, done: u32, total: u32) {
+        self.total.store(total.into(), Relaxed);
+        self.done.fetch_max(done.into(), Relaxed);
+    }
+
+    /// Runs `f`, reporting progress from a background thread meanwhile.
+    pub(crate) fn report_while<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.done.store(0, Relaxed);
+        self.finished.store(false, Relaxed);
+        let start = Instant::now();
+        thread::scope(|s| {
+            let reporter = s.spawn(|| {
+                while !self.finished.load(Relaxed) {
+                    thread::park_timeout(Duration::from_millis(200));
+                    self.print(start);
+                }
+                eprintln!();
+            });
+            let res = f();
+            self.finished.store(true, Relaxed);
+            reporter.thread().unpark();
+            res
+        })
+    }
+
+    fn print(&self, start: Instant) {
+        let (done, total) = (self.done.load(Relaxed), self.total.load(Relaxed));
+        let elapsed = start.elapsed().as_secs_f64();
+        let fraction = if total == 0 { 0.0 } else { done as f64 / total as f64 };
+        let mut line = format!("\r{:3.0}% {elapsed:.1}s elapsed", fraction * 100.0);
+        if 0.0 < fraction && fraction < 1.0 {
+            let left = elapsed / fraction - elapsed;
+            line.push_str(&format!(", {left:.1}s left"));
+        }
+        line.push_str("\x1b[K");
+        let _ = io::stderr().write_all(line.as_bytes());
+    }
+}
@@ -0,0 +1,302 @@
+//! `crt serve`: a minimal HTTP/1.1 server which renders POSTed scenes to PNG.
+//!
+//! `POST /render?width=W&height=H` with the scene as the body responds with
+//! the image, streamed with chunked encoding as rows are rendered. Requests are
+//! handled one at a time, as each render already uses all the threads.
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{IpAddr, TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+use render::rgb;
+
+use threads::Threads;
+
+use crate::{cancel, format::Format, stream::RowWriter};
+
+/// What a single request is allowed to use.
+pub(crate) struct Limits {
+    pub(crate) max_width: u32,
+    pub(crate) max_height: u32,
+    pub(crate) mem_kb: usize,
+    pub(crate) timeout: Duration,
+}
+
+const MAX_BODY: usize = 16 << 20;
+/// For the request line and the headers together.
+const MAX_HEAD: u64 = 16 << 10;
+/// To receive the whole request, so that a slow client can't hold the server,
+/// which handles one request at a time, for longer.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct HttpError {
+    status: u16,
+    message: String,
+}
+
+impl HttpError {
+    fn new(status: u16, message: impl Into<String>) -> HttpError {
+        HttpError { status, message: message.into() }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    body: Vec<u8>,
+}
+
+pub(crate) fn serve(addr: (IpAddr, u16), limits: &Limits, threads: &Threads) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    eprintln!("listening on http://{}", listener.local_addr()?);
+    let mut mem = vec![0; limits.mem_kb * 1024];
+    for stream in listener.incoming() {
+        if cancel::requested() {
+            break;
+        }
+        let stream = match stream {
+            Ok(it) => it,
+            Err(err) => {
+                eprintln!("error: accepting connection: {err}");
+                continue;
+            }
+        };
+        let peer = stream.peer_addr().map(|it| it.to_string()).unwrap_or_default();
+        match handle(&stream, limits, threads, &mut mem) {
+            Ok(status) => eprintln!("{peer}: {status}"),
+            Err(err) => eprintln!("{peer}: error: {err}"),
+        }
+    }
+    Ok(())
+}
+
+/// Returns the status of the response, or an error if the connection broke.
+fn handle(
+    stream: &TcpStream,
+    limits: &Limits,
+    threads: &Threads,
+    mem: &mut [u8],
+) -> io::Result<u16> {
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    let res = read_request(&mut BufReader::new(Deadline { stream, deadline })).and_then(|req| {
+        if req.path != "/" && req.path != "/render" {
+            return Err(HttpError::new(404, format!("no such page `{}`", req.path)));
+        }
+        if req.method != "POST" {
+            return Err(HttpError::new(405, "POST a scene to render it"));
+        }
+        Ok(req)
+    });
+    let res = match res {
+        Ok(req) => render(stream, &req, limits, threads, mem)?,
+        Err(err) => Err(err),
+    };
+    match res {
+        Ok(()) => Ok(200),
+        Err(err) => {
+            let mut w = stream;
+            write!(
+                w,
+                "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n{}\n",
+                err.status,
+                reason(err.status),
+                err.message.len() + 1,
+                err.message
+            )?;
+            Ok(err.status)
+        }
+    }
+}
+
+fn read_request(r: &mut impl BufRead) -> Result<Request, HttpError> {
+    let bad_request = |err: io::Error| match err.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {
+            HttpError::new(408, "timed out reading the request")
+        }
+        _ => HttpError::new(400, "malformed request"),
+    };
+    let mut head = r.by_ref().take(MAX_HEAD);
+    let mut read_line = |line: &mut String| {
+        line.clear();
+        head.read_line(line).map_err(bad_request)?;
+        if !line.ends_with('\n') {
+            if head.limit() == 0 {
+                return Err(HttpError::new(
+                    431,
+                    format!("headers are limited to {MAX_HEAD} bytes"),
+                ));
+            }
+            return Err(HttpError::new(400, "incomplete request"));
+        }
+        Ok(())
+    };
+    let mut line = String::new();
+    read_line(&mut line)?;
+    let mut words = line.split_whitespace();
+    let (Some(method), Some(target)) = (words.next(), words.next()) else {
+        return Err(HttpError::new(400, "malformed request line"));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut req = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        body: Vec::new(),
+    };
+
+    let mut content_length = None;
+    loop {
+        read_line(&mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(HttpError::new(400, "malformed header"));
+        };
+        if name.eq_ignore_ascii_case("content-length") {
+            let len = value.trim().parse::<usize>();
+            content_length = Some(len.map_err(|_| HttpError::new(400, "invalid Content-Length"))?);
+        }
+    }
+
+    if let Some(len) = content_length {
+        if len > MAX_BODY {
+            return Err(HttpError::new(413, format!("scenes are limited to {MAX_BODY} bytes")));
+        }
+        req.body.resize(len, 0);
+        r.read_exact(&mut req.body).map_err(bad_request)?;
+    }
+    Ok(req)
+}
+
+/// The outer error means the connection is broken, the inner one is reported
+/// to the client.
+fn render(
+    stream: &TcpStream,
+    req: &Request,
+    limits: &Limits,
+    threads: &Threads,
+    mem: &mut [u8],
+) -> io::Result<Result<(), HttpError>> {
+    let Ok(crt) = std::str::from_utf8(&req.body) else {
+        return Ok(Err(HttpError::new(400, "the scene is not valid UTF-8")));
+    };
+    let dim @ [width, _] = match dim(&req.query, limits) {
+        Ok(it) => it,
+        Err(err) => return Ok(Err(err)),
+    };
+
+    // Enforces the time budget, and stops on Ctrl-C as well.
+    let start = Instant::now();
+    let cancel = || start.elapsed() > limits.timeout || cancel::requested();
+    let opts = render::Options { cancel: Some(&cancel), ..render::Options::default() };
+    let res = render::with_renderer(crt, mem, &opts, |renderer| {
+        let mut w = stream;
+        write!(
+            w,
+            "HTTP/1.1 200 OK\r\nContent-Type: image/png\r\n\
+             Transfer-Encoding: chunked\r\nConnection: close\r\n\r\n"
+        )?;
+        let mut w = io::BufWriter::new(Chunked(stream));
+        let mut encoder = Format::Png.encoder(dim, rgb::Dither::None);
+        encoder.header(&mut w)?;
+        let writer = RowWriter::new(width, |y, row| encoder.row(&mut w, y, row));
+        renderer.render_rows(&|f| threads.in_parallel(f), dim, &|span| writer.push(span));
+        if renderer.cancelled() {
+            // Drop the connection without the final chunk, so that the
+            // client sees an incomplete response rather than a bad image.
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "render took too long"));
+        }
+        writer.finish()?;
+        encoder.finish(&mut w)?;
+        w.into_inner().map_err(|it| it.into_error())?.finish()
+    });
+    match res {
+        Ok(res) => res.map(Ok),
+        Err(err) => Ok(Err(HttpError::new(400, err.to_string()))),
+    }
+}
+
+/// Reads `width` and `height` from the query string, 800x600 by default.
+fn dim(query: &str, limits: &Limits) -> Result<rgb::Idx, HttpError> {
+    let mut res = [800.min(limits.max_width), 600.min(limits.max_height)];
+    for param in query.split('&').filter(|it| !it.is_empty()) {
+        let (name, value) = param.split_once('=').unwrap_or((param, ""));
+        let (i, max) = match name {
+            "width" => (0, limits.max_width),
+            "height" => (1, limits.max_height),
+            _ => return Err(HttpError::new(400, format!("unknown parameter `{name}`"))),
+        };
+        res[i] = match value.parse::<u32>() {
+            Ok(it) if 0 < it && it <= max => it,
+            _ => {
+                let message = format!("invalid {name} `{value}`, expected at most {max}");
+                return Err(HttpError::new(400, message));
+            }
+        };
+    }
+    Ok(res)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Content Too Large",
+        431 => "Request Header Fields Too Large",
+        _ => "Error",
+    }
+}
+
+/// Reads from a stream, failing with [`io::ErrorKind::TimedOut`] once the
+/// `deadline` passes, however slowly the data trickles in.
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+/// `Transfer-Encoding: chunked`, so that the image can be sent before its size
+/// is known.
+struct Chunked<W>(W);
+
+impl<W: Write> Chunked<W> {
+    fn finish(mut self) -> io::Result<()> {
+        self.0.write_all(b"0\r\n\r\n")?;
+        self.0.flush()
+    }
+}
+
+impl<W: Write> Write for Chunked<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !buf.is_empty() {
+            write!(self.0, "{:x}\r\n", buf.len())?;
+            self.0.write_all(buf)?;
+            self.0.write_all(b"\r\n")?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
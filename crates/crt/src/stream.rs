@@ -0,0 +1,92 @@
+use std::{collections::HashMap, io, sync::Mutex};
+
+use render::rgb;
+
+/// Puts spans rendered by different threads back in order, and writes each row
+/// out as soon as it and all the rows above it are complete.
+pub(crate) struct RowWriter<C, F> {
+    width: u32,
+    state: Mutex<State<C, F>>,
+}
+
+struct State<C, F> {
+    write_row: F,
+    next_row: u32,
+    pending: HashMap<u32, PendingRow<C>>,
+    spare: Vec<Vec<C>>,
+    error: Option<io::Error>,
+}
+
+struct PendingRow<C> {
+    pixels: Vec<C>,
+    filled: u32,
+}
+
+impl<C, F> RowWriter<C, F>
+where
+    C: Copy + Default,
+    F: FnMut(u32, &[C]) -> io::Result<()>,
+{
+    pub(crate) fn new(width: u32, write_row: F) -> RowWriter<C, F> {
+        let state = State {
+            write_row,
+            next_row: 0,
+            pending: HashMap::new(),
+            spare: Vec::new(),
+            error: None,
+        };
+        RowWriter { width, state: Mutex::new(state) }
+    }
+
+    pub(crate) fn push(&self, span: rgb::Span<'_, C>) {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let width = self.width as usize;
+        let row = state.pending.entry(span.y).or_insert_with(|| {
+            let mut pixels = state.spare.pop().unwrap_or_default();
+            pixels.resize(width, C::default());
+            PendingRow { pixels, filled: 0 }
+        });
+        row.pixels[span.x as usize..][..span.pixels.len()].copy_from_slice(span.pixels);
+        row.filled += span.pixels.len() as u32;
+        state.flush(self.width);
+    }
+
+    /// Completes the image with default pixels after an interrupted render.
+    pub(crate) fn fill_missing(&self, height: u32) {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        for y in state.next_row..height {
+            let row = state.pending.entry(y).or_insert_with(|| PendingRow {
+                pixels: state.spare.pop().unwrap_or_default(),
+                filled: 0,
+            });
+            row.pixels.resize(self.width as usize, C::default());
+            row.filled = self.width;
+        }
+        state.flush(self.width);
+    }
+
+    pub(crate) fn finish(self) -> io::Result<()> {
+        let state = self.state.into_inner().unwrap();
+        if let Some(err) = state.error {
+            return Err(err);
+        }
+        assert!(state.pending.is_empty());
+        Ok(())
+    }
+}
+
+impl<C, F: FnMut(u32, &[C]) -> io::Result<()>> State<C, F> {
+    fn flush(&mut self, width: u32) {
+        while self.pending.get(&self.next_row).is_some_and(|it| it.filled == width) {
+            let y = self.next_row;
+            let row = self.pending.remove(&y).unwrap();
+            self.next_row += 1;
+            if self.error.is_none() {
+                self.error = (self.write_row)(y, &row.pixels).err();
+            }
+            self.spare.push(row.pixels);
+        }
+    }
+}
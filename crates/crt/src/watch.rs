@@ -0,0 +1,64 @@
+use std::{
+    fs, io,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering::SeqCst},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::cancel;
+
+/// Calls `on_change` once, and then again every time the modification time of
+/// the file at `path` changes. Runs until interrupted with Ctrl-C, or until the
+/// file can't be stat'ed.
+///
+/// `on_change` gets a check which tells whether the file changed again while
+/// it was running, so that it can abandon a render which is already stale.
+///
+/// Polls rather than subscribing to file system events: that works everywhere,
+/// and scene files are small and edited by hand.
+pub(crate) fn watch(
+    path: &Path,
+    mut on_change: impl FnMut(&(dyn Fn() -> bool + Sync)),
+) -> io::Result<()> {
+    let mut last: Option<SystemTime> = None;
+    while !cancel::requested() {
+        let mtime = match modified(path) {
+            Ok(it) => it,
+            // Editors often save by writing a new file and renaming it over
+            // the old one, so the file might briefly disappear.
+            Err(err) if err.kind() == io::ErrorKind::NotFound && last.is_some() => {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        if last != Some(mtime) {
+            last = Some(mtime);
+            let changed = AtomicBool::new(false);
+            let done = AtomicBool::new(false);
+            thread::scope(|s| {
+                s.spawn(|| {
+                    while !done.load(SeqCst) {
+                        thread::sleep(POLL_INTERVAL);
+                        if modified(path).ok() != Some(mtime) {
+                            changed.store(true, SeqCst);
+                            break;
+                        }
+                    }
+                });
+                on_change(&|| changed.load(SeqCst));
+                done.store(true, SeqCst);
+            });
+            continue;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+fn modified(path: &Path) -> io::Result<SystemTime> {
+    fs::metadata(path).and_then(|it| it.modified())
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
@@ -0,0 +1,42 @@
+/* C interface of the crt ray tracer, built as `libcrt_ffi` by
+ * `cargo build -p crt-ffi`. */
+
+#ifndef CRT_H
+#define CRT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Zero fields keep the defaults: the scene's `settings`, one thread per core,
+ * and as much memory as the scene needs. */
+typedef struct {
+    uint32_t samples;
+    uint32_t bounces;
+    uint32_t threads;
+    size_t mem_kb;
+} crt_settings;
+
+#define CRT_OK 0
+#define CRT_INVALID_ARGUMENT 1
+#define CRT_INVALID_SCENE 2
+#define CRT_OUT_OF_MEMORY 3
+
+/* Renders the NUL-terminated `scene` into `out_rgb8`, which must hold
+ * `width * height * 3` bytes, row by row, top to bottom. `settings` can be
+ * NULL. Returns one of the codes above, see `crt_last_error` for details. */
+int32_t crt_render(const char *scene, const crt_settings *settings, uint8_t *out_rgb8,
+                   uint32_t width, uint32_t height);
+
+/* The message of the last failed `crt_render` on this thread, valid until the
+ * next call. */
+const char *crt_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
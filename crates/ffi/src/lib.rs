@@ -0,0 +1,131 @@
+//! C interface to the renderer, declared in `crt.h`.
+//!
+//! Everything [`render`] needs comes from the caller or is allocated here, one
+//! arena per call, so the library keeps no state besides the last error.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    num::NonZeroUsize,
+    slice,
+};
+
+use render::rgb;
+use threads::Threads;
+
+/// `crt_settings`. Zero fields keep the defaults.
+#[repr(C)]
+pub struct Settings {
+    pub samples: u32,
+    pub bounces: u32,
+    pub threads: u32,
+    pub mem_kb: usize,
+}
+
+pub const CRT_OK: i32 = 0;
+pub const CRT_INVALID_ARGUMENT: i32 = 1;
+pub const CRT_INVALID_SCENE: i32 = 2;
+pub const CRT_OUT_OF_MEMORY: i32 = 3;
+
+struct Error {
+    code: i32,
+    message: String,
+}
+
+impl Error {
+    fn new(code: i32, message: impl Into<String>) -> Error {
+        Error { code, message: message.into() }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+/// Renders `scene` into `out_rgb8`, see `crt.h`.
+///
+/// # Safety
+///
+/// `scene` must be a NUL-terminated string, `settings` either null or valid,
+/// and `out_rgb8` must point to `width * height * 3` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn crt_render(
+    scene: *const c_char,
+    settings: *const Settings,
+    out_rgb8: *mut u8,
+    width: u32,
+    height: u32,
+) -> i32 {
+    let len = (width as usize).checked_mul(height as usize).and_then(|it| it.checked_mul(3));
+    let res = match len {
+        _ if scene.is_null() || out_rgb8.is_null() => {
+            Err(Error::new(CRT_INVALID_ARGUMENT, "scene and out_rgb8 must not be null"))
+        }
+        None => Err(Error::new(CRT_INVALID_ARGUMENT, "the image is too large")),
+        Some(len) => {
+            let scene = CStr::from_ptr(scene);
+            let settings = if settings.is_null() { None } else { Some(&*settings) };
+            let out = slice::from_raw_parts_mut(out_rgb8, len);
+            render(scene, settings, out, [width, height])
+        }
+    };
+    match res {
+        Ok(()) => CRT_OK,
+        Err(err) => {
+            let message = CString::new(err.message.replace('\0', " ")).unwrap_or_default();
+            LAST_ERROR.with(|it| *it.borrow_mut() = message);
+            err.code
+        }
+    }
+}
+
+/// See `crt.h`.
+#[no_mangle]
+pub extern "C" fn crt_last_error() -> *const c_char {
+    LAST_ERROR.with(|it| it.borrow().as_ptr())
+}
+
+fn render(
+    scene: &CStr,
+    settings: Option<&Settings>,
+    out: &mut [u8],
+    dim @ [width, height]: rgb::Idx,
+) -> Result<(), Error> {
+    let crt = scene
+        .to_str()
+        .map_err(|_| Error::new(CRT_INVALID_ARGUMENT, "the scene is not valid UTF-8"))?;
+    if width.checked_mul(height).is_none_or(|it| it == 0) {
+        return Err(Error::new(CRT_INVALID_ARGUMENT, "invalid image size"));
+    }
+    let settings = settings.unwrap_or(&Settings { samples: 0, bounces: 0, threads: 0, mem_kb: 0 });
+
+    let opts = render::Options {
+        samples: (settings.samples != 0).then_some(settings.samples),
+        bounces: (settings.bounces != 0).then_some(settings.bounces),
+        ..render::Options::default()
+    };
+    let threads = match NonZeroUsize::new(settings.threads as usize) {
+        Some(it) => Threads::new(it),
+        None => Threads::with_max_threads().unwrap_or(Threads::new(NonZeroUsize::MIN)),
+    };
+    let mem_size = match settings.mem_kb {
+        // Same headroom as `crt --mem auto`.
+        0 => {
+            let estimate = render::mem_estimate(crt);
+            estimate + estimate / 8 + 1024
+        }
+        kb => kb.saturating_mul(1024),
+    };
+    let mut mem = vec![0; mem_size];
+
+    let mut pixels = vec![rgb::Color::default(); out.len() / 3];
+    let mut buf = rgb::Buf::new(dim, &mut pixels);
+    render::render(crt, &mut mem, &opts, &|f| threads.in_parallel(f), &mut buf).map_err(|err| {
+        let code = if err.is_oom() { CRT_OUT_OF_MEMORY } else { CRT_INVALID_SCENE };
+        Error::new(code, err.to_string())
+    })?;
+    for (rgb, pixel) in out.chunks_exact_mut(3).zip(&pixels) {
+        rgb.copy_from_slice(&[pixel.r, pixel.g, pixel.b]);
+    }
+    Ok(())
+}
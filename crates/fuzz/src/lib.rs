@@ -0,0 +1,332 @@
+//! Fuzzing entry points for the parser and the renderer, and a generator of
+//! random scenes to feed them.
+//!
+//! [`parse`] and [`render`] take arbitrary bytes, so they can be used as
+//! `cargo fuzz` targets as is:
+//!
+//! ```ignore
+//! fuzz_target!(|data: &[u8]| fuzz::render(data));
+//! ```
+//!
+//! Without a coverage-guided fuzzer, `cargo run -r -p fuzz` throws
+//! [`scene`]-generated documents at both.
+
+use mem::Mem;
+
+/// Parses `data` as a scene, checking that [`render::mem_estimate`] is
+/// enough memory for any scene which parses.
+pub fn parse(data: &[u8]) {
+    parsed(data);
+}
+
+/// Renders `data` as a scene at a tiny resolution, with sampling and
+/// reflections capped so that every input finishes quickly. A scene which
+/// parses must render, with valid BVHs, in the estimated memory.
+pub fn render(data: &[u8]) {
+    let Some(crt) = parsed(data) else { return };
+    let mut mem = vec![0; mem_size(crt)];
+    let opts = render::Options {
+        samples: Some(2),
+        bounces: Some(2),
+        validate_accel: true,
+        objects: Some("-b"),
+        ..Default::default()
+    };
+    let mut pixels = [render::rgb::Color::default(); 8 * 6];
+    let mut buf = render::rgb::Buf::new([8, 6], &mut pixels);
+    if let Err(err) = render::render(crt, &mut mem, &opts, &render::serial, &mut buf) {
+        panic!("the scene parses, but doesn't render: {err}")
+    }
+}
+
+fn parsed(data: &[u8]) -> Option<&str> {
+    let crt = std::str::from_utf8(data).ok()?;
+    let mut mem = vec![0; mem_size(crt)];
+    let res = Mem::with(&mut mem, |mem| scene::Scene::parse(mem, crt).map(drop));
+    match res {
+        Ok(()) => Some(crt),
+        Err(err) if err.is_oom() => panic!("the estimated memory is too small: {err}"),
+        Err(_) => None,
+    }
+}
+
+/// Same headroom as `crt --mem auto`.
+fn mem_size(crt: &str) -> usize {
+    let estimate = render::mem_estimate(crt);
+    estimate + estimate / 8 + 1024
+}
+
+/// A random scene: valid for most seeds, with a few mutations for the rest,
+/// so that the error paths are exercised as well.
+pub fn scene(seed: u64) -> String {
+    let mut rng = Rng(seed);
+    let mut res = String::new();
+    let push = |res: &mut String, s: &str| {
+        res.push_str(s);
+        res.push('\n');
+    };
+
+    push(&mut res, &format!("background {}", rng.color()));
+    if rng.below(2) == 0 {
+        push(&mut res, &format!("ambient {}", rng.color()));
+    }
+    push(&mut res, "camera {");
+    push(&mut res, &format!("pos {}", rng.animated()));
+    push(&mut res, &format!("look_at {}", rng.vector()));
+    push(&mut res, &format!("up {}", rng.vector()));
+    push(&mut res, &format!("focus {}", rng.scalar()));
+    push(&mut res, &format!("dim {}x{}", rng.scalar(), rng.scalar()));
+    if rng.below(3) == 0 {
+        push(&mut res, &format!("aperture {}", rng.scalar()));
+        push(&mut res, &format!("focal_distance {}", rng.scalar()));
+    }
+    if rng.below(3) == 0 {
+        push(&mut res, &format!("shutter {}", rng.scalar()));
+    }
+    push(&mut res, "}");
+    for _ in 0..rng.below(3) + 1 {
+        if rng.below(3) == 0 {
+            push(&mut res, "directional_light {");
+            push(&mut res, &format!("dir {}", rng.vector()));
+        } else {
+            push(&mut res, "light {");
+            push(&mut res, &format!("pos {}", rng.animated()));
+        }
+        push(&mut res, &format!("color {}", rng.color()));
+        if rng.below(2) == 0 {
+            let groups = ["key", "fill", "light"];
+            push(&mut res, &format!("group {}", groups[rng.below(3) as usize]));
+        }
+        push(&mut res, "}");
+    }
+    if rng.below(2) == 0 {
+        push(&mut res, "settings {");
+        push(&mut res, &format!("samples {}", rng.below(4)));
+        push(&mut res, &format!("bounces {}", rng.below(4)));
+        push(&mut res, &format!("min_survival {}", (rng.below(4) + 1) as f64 / 4.0));
+        push(&mut res, &format!("glossy_splits {}", rng.below(4) + 1));
+        push(&mut res, &format!("exposure {}", rng.scalar()));
+        push(&mut res, &format!("auto_exposure {}", rng.below(2) == 0));
+        push(&mut res, &format!("temperature {}", rng.scalar()));
+        push(&mut res, &format!("tint {}", rng.scalar()));
+        push(&mut res, &format!("tonemap {}", ["none", "reinhard"][rng.below(2) as usize]));
+        push(&mut res, &format!("gamma {}", rng.scalar()));
+        push(&mut res, &format!("vignette {}", rng.below(3) as f64 / 2.0));
+        push(&mut res, &format!("grain {}", rng.below(3) as f64 / 2.0));
+        push(&mut res, &format!("seed {}", rng.next()));
+        push(&mut res, &format!("shadow_bias {}", rng.scalar()));
+        let integrators = [
+            "whitted",
+            "path",
+            "normals",
+            "depth",
+            "ao",
+            "bvh-heatmap",
+            "wireframe",
+            "false-color",
+        ];
+        push(&mut res, &format!("integrator {}", integrators[rng.below(8) as usize]));
+        push(&mut res, &format!("ao_rays {}", rng.below(4)));
+        push(&mut res, &format!("ao_distance {}", rng.scalar()));
+        push(&mut res, "}");
+    }
+    for _ in 0..rng.below(4) {
+        push(&mut res, "sphere {");
+        push(&mut res, &format!("pos {}", rng.animated()));
+        push(&mut res, &format!("radius {}", rng.scalar()));
+        if rng.below(3) == 0 {
+            push(&mut res, &format!("velocity {}", rng.vector()));
+        }
+        rng.material(&mut res);
+        rng.name(&mut res);
+        push(&mut res, "}");
+    }
+    for _ in 0..rng.below(3) {
+        push(&mut res, "plane {");
+        push(&mut res, &format!("pos {}", rng.vector()));
+        push(&mut res, &format!("normal {}", rng.vector()));
+        rng.material(&mut res);
+        rng.name(&mut res);
+        push(&mut res, "}");
+    }
+    for _ in 0..rng.below(3) {
+        push(&mut res, "torus {");
+        push(&mut res, &format!("pos {}", rng.vector()));
+        push(&mut res, &format!("axis {}", rng.vector()));
+        push(&mut res, &format!("radius {}", rng.scalar()));
+        push(&mut res, &format!("tube {}", rng.scalar()));
+        rng.material(&mut res);
+        rng.name(&mut res);
+        push(&mut res, "}");
+    }
+    for _ in 0..rng.below(3) {
+        push(&mut res, "quadric {");
+        push(&mut res, &format!("pos {}", rng.vector()));
+        push(&mut res, &format!("square {}", rng.vector()));
+        push(&mut res, &format!("mixed {}", rng.vector()));
+        push(&mut res, &format!("linear {}", rng.vector()));
+        push(&mut res, &format!("constant {}", rng.scalar()));
+        rng.material(&mut res);
+        rng.name(&mut res);
+        push(&mut res, "}");
+    }
+    if rng.below(3) == 0 {
+        push(&mut res, "clip {");
+        push(&mut res, &format!("pos {}", rng.vector()));
+        push(&mut res, &format!("normal {}", rng.vector()));
+        if rng.below(2) == 0 {
+            push(&mut res, &format!("cap {{\ncolor {}\ndiffuse {}\n}}", rng.color(), rng.scalar()));
+        }
+        push(&mut res, "}");
+    }
+    for _ in 0..rng.below(3) {
+        push(&mut res, "mesh {");
+        rng.material(&mut res);
+        rng.name(&mut res);
+        if rng.below(2) == 0 {
+            push(&mut res, &format!("translate {}", rng.animated()));
+        }
+        if rng.below(3) == 0 {
+            push(&mut res, &format!("velocity {}", rng.vector()));
+        }
+        if rng.below(3) == 0 {
+            push(&mut res, &format!("subdivide {}", rng.below(4)));
+        }
+        if rng.below(3) == 0 {
+            push(&mut res, &format!("simplify {}", rng.below(5) as f64 / 4.0));
+        }
+        if rng.below(3) == 0 {
+            push(&mut res, "displace {");
+            push(&mut res, &format!("noise {}", ["perlin", "fbm"][rng.below(2) as usize]));
+            push(&mut res, &format!("amplitude {}", rng.scalar()));
+            push(&mut res, &format!("frequency {}", rng.scalar()));
+            push(&mut res, "}");
+        }
+        push(&mut res, "data {");
+        let n_v = rng.below(8) + 1;
+        let n_n = rng.below(8) + 1;
+        for _ in 0..n_v {
+            push(&mut res, &format!("v {}", rng.vector()));
+        }
+        for _ in 0..n_n {
+            push(&mut res, &format!("vn {}", rng.vector()));
+        }
+        for _ in 0..rng.below(12) {
+            let mut face = "f".to_string();
+            for _ in 0..3 {
+                face.push_str(&format!(" {}/{}", rng.below(n_v) + 1, rng.below(n_n) + 1));
+            }
+            push(&mut res, &face);
+        }
+        push(&mut res, "}");
+        push(&mut res, "}");
+    }
+    for _ in 0..rng.below(2) {
+        push(&mut res, "curves {");
+        push(&mut res, &format!("radius {}", rng.scalar()));
+        push(&mut res, &format!("basis {}", ["bezier", "bspline"][rng.below(2) as usize]));
+        rng.material(&mut res);
+        rng.name(&mut res);
+        push(&mut res, "data {");
+        for _ in 0..rng.below(6) {
+            let points = [(); 4].map(|()| rng.vector());
+            push(&mut res, &format!("c {}", points.join(" ")));
+        }
+        push(&mut res, "}");
+        push(&mut res, "}");
+    }
+
+    if rng.below(4) == 0 {
+        res = rng.mutate(&res);
+    }
+    res
+}
+
+/// SplitMix64, good enough and reproducible from the seed alone.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    /// Mostly moderate values, with the occasional zero, negative or extreme.
+    fn scalar(&mut self) -> f64 {
+        match self.below(16) {
+            0 => 0.0,
+            1 => -(self.below(100) as f64),
+            2 => 1e300,
+            3 => 1e-300,
+            _ => (self.below(2000) as f64 - 500.0) / 10.0,
+        }
+    }
+
+    fn vector(&mut self) -> String {
+        format!("{},{},{}", self.scalar(), self.scalar(), self.scalar())
+    }
+
+    fn animated(&mut self) -> String {
+        let mut res = self.vector();
+        let mut time = 0;
+        for _ in 0..self.below(3) {
+            time += self.below(3);
+            res.push_str(&format!(" @{time} {}", self.vector()));
+        }
+        res
+    }
+
+    fn color(&mut self) -> String {
+        format!("#{:06x}", self.next() & 0xffffff)
+    }
+
+    fn material(&mut self, res: &mut String) {
+        res.push_str(&format!(
+            "material {{\ncolor {}\nambient {}\ndiffuse {}\nspecular {}\nshininess {}\nreflectance {}\nroughness {}\ndouble_sided {}\nvisible {}\nemission {}\nemission_strength {}\nshadow_catcher {}\n}}\n",
+            self.color(),
+            self.scalar(),
+            self.scalar(),
+            self.scalar(),
+            self.scalar(),
+            self.below(3) as f64 / 2.0,
+            self.scalar(),
+            self.below(2) == 1,
+            ["camera,shadow,reflection", "none", "shadow", "camera,reflection"]
+                [self.below(4) as usize],
+            self.color(),
+            self.below(3),
+            self.below(4) == 0,
+        ));
+    }
+
+    fn name(&mut self, res: &mut String) {
+        if self.below(2) == 0 {
+            res.push_str(&format!("name {}\n", ["a", "b", "sphere"][self.below(3) as usize]));
+        }
+    }
+
+    /// Deletes, duplicates or replaces a few words.
+    fn mutate(&mut self, text: &str) -> String {
+        let mut words: Vec<&str> = text.split_ascii_whitespace().collect();
+        const JUNK: [&str; 8] = ["{", "}", "sphere", "@1", "1/1", "#zz", "-0", "1e999"];
+        for _ in 0..self.below(3) + 1 {
+            if words.is_empty() {
+                break;
+            }
+            let i = self.below(words.len() as u64) as usize;
+            match self.below(3) {
+                0 => drop(words.remove(i)),
+                1 => words.insert(i, words[i]),
+                _ => words[i] = JUNK[self.below(JUNK.len() as u64) as usize],
+            }
+        }
+        words.join(" ")
+    }
+}
@@ -0,0 +1,33 @@
+//! `fuzz [iterations] [seed]`: parses and renders random scenes until one of
+//! them panics, which prints the scene to reproduce it with.
+
+use std::{cell::RefCell, env, panic, process};
+
+thread_local! {
+    static SCENE: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+fn main() {
+    let mut args = env::args().skip(1).map(|it| it.parse::<u64>());
+    let (iterations, seed) = match (args.next(), args.next(), args.next()) {
+        (None, None, None) => (10_000, 0),
+        (Some(Ok(iterations)), None, None) => (iterations, 0),
+        (Some(Ok(iterations)), Some(Ok(seed)), None) => (iterations, seed),
+        _ => {
+            eprintln!("usage: fuzz [iterations] [seed]");
+            process::exit(1)
+        }
+    };
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        SCENE.with(|it| eprintln!("scene:\n{}\n", it.borrow()));
+        default_hook(info)
+    }));
+    for i in 0..iterations {
+        let scene = fuzz::scene(seed.wrapping_add(i));
+        SCENE.with(|it| it.borrow_mut().clone_from(&scene));
+        fuzz::render(scene.as_bytes());
+    }
+    println!("{iterations} scenes, no crashes");
+}
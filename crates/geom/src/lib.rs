@@ -25,6 +25,9 @@ enum ParseVectorErrorRepr {
 pub struct Ray {
     origin: v64,
     dir: v64,
+    /// When the ray is cast, from the moment the shutter opens, for motion
+    /// blur.
+    time: f64,
 }
 
 pub const fn v64(x: f64, y: f64, z: f64) -> v64 {
@@ -66,11 +69,13 @@ impl FromStr for v64 {
     }
 }
 
-fn split_n<const N: usize>(s: &str, p: char) -> Option<[&str; N]> {
+/// Splits `s` at `p` into exactly `N` parts, `None` if there are more or
+/// fewer.
+pub fn split_n<const N: usize>(s: &str, p: char) -> Option<[&str; N]> {
     let mut components = s.split(p);
     let mut res = [""; N];
-    for i in 0..N {
-        res[i] = components.next()?;
+    for slot in &mut res {
+        *slot = components.next()?;
     }
     if components.next().is_some() {
         return None;
@@ -145,7 +150,7 @@ impl From<ParseVectorErrorRepr> for ParseVectorError {
 impl Ray {
     pub fn new(origin: v64, dir: v64) -> Ray {
         let dir = dir.to_unit();
-        Ray { origin, dir }
+        Ray { origin, dir, time: 0.0 }
     }
 
     pub fn from_to(from: v64, to: v64) -> Ray {
@@ -160,13 +165,197 @@ impl Ray {
         self.dir
     }
 
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// The same ray cast at `time`, rays are cast at zero by default.
+    pub fn at_time(self, time: f64) -> Ray {
+        Ray { time, ..self }
+    }
+
     pub fn at(&self, dt: f64) -> v64 {
         self.origin + self.dir * dt
     }
+
+    /// The same ray translated by `by`, which keeps distances along it.
+    pub fn moved(&self, by: v64) -> Ray {
+        Ray { origin: self.origin + by, ..*self }
+    }
+}
+
+/// Real roots of a polynomial, in increasing order.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Roots {
+    buf: [f64; 4],
+    len: usize,
+}
+
+impl Roots {
+    fn push(&mut self, root: f64) {
+        self.buf[self.len] = root;
+        self.len += 1;
+    }
+}
+
+impl ops::Deref for Roots {
+    type Target = [f64];
+
+    fn deref(&self) -> &[f64] {
+        &self.buf[..self.len]
+    }
+}
+
+/// Roots of `c[0] + c[1] * t + c[2] * t^2` in `lo..hi`.
+pub fn quadratic_roots(c: [f64; 3], lo: f64, hi: f64) -> Roots {
+    let [c, b, a] = c;
+    let mut res = Roots::default();
+    let mut add = |t: f64| {
+        if lo < t && t < hi {
+            res.push(t)
+        }
+    };
+    if a == 0.0 {
+        if b != 0.0 {
+            add(-c / b);
+        }
+        return res;
+    }
+    let disc = b * b - 4.0 * a * c;
+    if disc < 0.0 {
+        return res;
+    }
+    // Avoids subtracting nearly equal numbers, see Numerical Recipes, 5.6.
+    let q = -0.5 * (b + sqrt(disc).copysign(b));
+    let (t1, t2) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+    add(t1.min(t2));
+    if t1 != t2 {
+        add(t1.max(t2));
+    }
+    res
+}
+
+/// Roots of `c[0] + c[1] * t + ... + c[4] * t^4` in `lo..hi`, which must be
+/// finite.
+///
+/// Between the roots of the derivative, the polynomial is monotonic, so each
+/// stretch holds at most one root, found by bisection. Roots where the
+/// polynomial touches zero without changing sign, like those of a ray
+/// grazing a surface, may be missed.
+pub fn quartic_roots(c: [f64; 5], lo: f64, hi: f64) -> Roots {
+    let d1 = [c[1], 2.0 * c[2], 3.0 * c[3], 4.0 * c[4]];
+    let d2 = [d1[1], 2.0 * d1[2], 3.0 * d1[3]];
+    let cubic_extrema = quadratic_roots(d2, lo, hi);
+    let extrema = monotonic_roots(&d1, &cubic_extrema, lo, hi);
+    monotonic_roots(&c, &extrema, lo, hi)
+}
+
+/// Roots of the polynomial with coefficients `c` in `lo..hi`, given the
+/// roots of its derivative there.
+fn monotonic_roots(c: &[f64], extrema: &[f64], lo: f64, hi: f64) -> Roots {
+    let eval = |t: f64| c.iter().rev().fold(0.0, |acc, &it| acc * t + it);
+    let mut res = Roots::default();
+    let mut a = lo;
+    for &b in extrema.iter().chain([hi].iter()) {
+        let (mut x, mut y) = (a, b);
+        let below = eval(x) < 0.0;
+        if below != (eval(y) < 0.0) {
+            loop {
+                let mid = 0.5 * (x + y);
+                if mid <= x || mid >= y {
+                    break;
+                }
+                if (eval(mid) < 0.0) == below {
+                    x = mid
+                } else {
+                    y = mid
+                }
+            }
+            if lo < y && y < hi {
+                res.push(y);
+            }
+        }
+        a = b;
+    }
+    res
+}
+
+/// SplitMix64 random numbers. Seeded per pixel and sample rather than per
+/// thread, so that renders don't depend on the thread count or on the order
+/// in which pixels are traced.
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    /// The generator for sample `sample` of pixel `[x, y]`.
+    pub fn new(seed: u64, [x, y]: [u32; 2], sample: u32) -> Rng {
+        Rng(splitmix64(seed ^ (u64::from(y) << 32 | u64::from(x))) ^ u64::from(sample) << 48)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let res = splitmix64(self.0);
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        res
+    }
+
+    /// Uniform in `0.0..1.0`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in the unit disk.
+    pub fn in_unit_disk(&mut self) -> [f64; 2] {
+        loop {
+            let [x, y] = [(); 2].map(|()| 2.0 * self.next_f64() - 1.0);
+            if x * x + y * y <= 1.0 {
+                return [x, y];
+            }
+        }
+    }
+
+    /// Uniform in the unit ball.
+    pub fn in_unit_ball(&mut self) -> v64 {
+        loop {
+            let [x, y, z] = [(); 3].map(|()| 2.0 * self.next_f64() - 1.0);
+            let res = v64(x, y, z);
+            if res.norm_squared() <= 1.0 {
+                return res;
+            }
+        }
+    }
+}
+
+/// The SplitMix64 finalizer, a cheap hash which mixes every bit of `x` into
+/// every bit of the result.
+pub fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Square root, the same on every target unless the `strict` feature is off
+/// and the hardware has a different idea about the last bit, which IEEE 754
+/// doesn't allow for `sqrt`.
+pub fn sqrt(v: f64) -> f64 {
+    #[cfg(not(feature = "strict"))]
+    return hard_sqrt(v);
+    #[cfg(feature = "strict")]
+    return soft_sqrt(v);
 }
 
-#[cfg(target_arch="x86_64")]
-fn sqrt(mut v: f64) -> f64 {
+/// `x` to the power of `y`. With the `strict` feature, computed with basic
+/// arithmetic only, so that the result doesn't depend on the platform's libm.
+pub fn powf(x: f64, y: f64) -> f64 {
+    #[cfg(not(feature = "strict"))]
+    return x.powf(y);
+    #[cfg(feature = "strict")]
+    return soft_powf(x, y);
+}
+
+#[cfg(target_arch = "x86_64")]
+#[cfg_attr(feature = "strict", allow(dead_code))]
+fn hard_sqrt(mut v: f64) -> f64 {
     unsafe {
         core::arch::asm!(
             "vsqrtsd {v}, {v}, {v}",
@@ -176,9 +365,9 @@ fn sqrt(mut v: f64) -> f64 {
     v
 }
 
-
-#[cfg(target_arch="aarch64")]
-fn sqrt(mut v: f64) -> f64 {
+#[cfg(target_arch = "aarch64")]
+#[cfg_attr(feature = "strict", allow(dead_code))]
+fn hard_sqrt(mut v: f64) -> f64 {
     unsafe {
         core::arch::asm!(
             "fsqrt d3, d3",
@@ -187,3 +376,167 @@ fn sqrt(mut v: f64) -> f64 {
     }
     v
 }
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+#[cfg_attr(feature = "strict", allow(dead_code))]
+fn hard_sqrt(v: f64) -> f64 {
+    soft_sqrt(v)
+}
+
+/// Newton's method, for targets without a stable way to reach the hardware
+/// instruction from `no_std`, like `wasm32`. Can be off by an ulp.
+#[allow(dead_code)]
+fn soft_sqrt(v: f64) -> f64 {
+    if v.is_nan() || v.is_infinite() || v == 0.0 {
+        return v;
+    }
+    if v < 0.0 {
+        return f64::NAN;
+    }
+    // Halving the exponent gets within a few percent, and after one step the
+    // estimate is above the root and decreases until it converges.
+    let mut res = f64::from_bits((v.to_bits() >> 1) + 0x1ff8_0000_0000_0000);
+    res = 0.5 * (res + v / res);
+    loop {
+        let next = 0.5 * (res + v / res);
+        if next >= res {
+            return res;
+        }
+        res = next;
+    }
+}
+
+/// `exp(y * ln(x))` for non-negative `x`, which is all colors need. Not as
+/// accurate as libm, as the rounding error of the product is magnified, but
+/// well within what an 8-bit or even a 16-bit channel can show.
+#[allow(dead_code)]
+fn soft_powf(x: f64, y: f64) -> f64 {
+    if y == 0.0 || x == 1.0 {
+        return 1.0;
+    }
+    if x.is_nan() || y.is_nan() || x < 0.0 {
+        return f64::NAN;
+    }
+    if x == 0.0 {
+        return if y > 0.0 { 0.0 } else { f64::INFINITY };
+    }
+    soft_exp(y * soft_ln(x))
+}
+
+// ln(2) split in two, so that `k * LN_2_HI` is exact for the `k` of `exp`.
+const LN_2_HI: f64 = f64::from_bits(0x3fe6_2e42_fee0_0000);
+const LN_2_LO: f64 = f64::from_bits(0x3dea_39ef_3579_3c76);
+
+fn soft_ln(x: f64) -> f64 {
+    if x.is_infinite() {
+        return x;
+    }
+    // x = m * 2^e with m in [sqrt(1/2), sqrt(2)), subnormals scaled up first.
+    let (x, mut e) = if x < f64::MIN_POSITIVE { (x * (1u64 << 54) as f64, -54) } else { (x, 0) };
+    let bits = x.to_bits();
+    e += ((bits >> 52) & 0x7ff) as i32 - 1023;
+    let mut m = f64::from_bits(bits & !(0x7ff << 52) | 1023 << 52);
+    if m > core::f64::consts::SQRT_2 {
+        m /= 2.0;
+        e += 1;
+    }
+    // ln(m) = 2 atanh(s), and |s| < 0.172 makes the series converge quickly.
+    let s = (m - 1.0) / (m + 1.0);
+    let s2 = s * s;
+    let mut term = s;
+    let mut sum = 0.0;
+    for k in 0..14 {
+        sum += term / (2 * k + 1) as f64;
+        term *= s2;
+    }
+    let e = e as f64;
+    e * LN_2_HI + (2.0 * sum + e * LN_2_LO)
+}
+
+fn soft_exp(z: f64) -> f64 {
+    if z.is_nan() {
+        return z;
+    }
+    if z > 709.8 {
+        return f64::INFINITY;
+    }
+    if z < -745.2 {
+        return 0.0;
+    }
+    // exp(z) = 2^k exp(r) with |r| <= ln(2) / 2.
+    let k = (z / core::f64::consts::LN_2 + if z < 0.0 { -0.5 } else { 0.5 }) as i32;
+    let r = (z - k as f64 * LN_2_HI) - k as f64 * LN_2_LO;
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    for i in 1..20 {
+        term *= r / i as f64;
+        sum += term;
+    }
+    // Two steps, as 2^k alone can be out of range when the result is not.
+    let half = k / 2;
+    sum * pow2(half) * pow2(k - half)
+}
+
+fn pow2(k: i32) -> f64 {
+    f64::from_bits(((k + 1023) as u64) << 52)
+}
+
+#[test]
+fn test_soft_sqrt() {
+    for v in [0.0, 1e-310, 1e-20, 0.25, 1.0, 2.0, 3.0, 1e10, 1.7e308] {
+        let (hard, soft) = (hard_sqrt(v), soft_sqrt(v));
+        assert!((hard - soft).abs() <= hard * f64::EPSILON, "{v}: {hard} != {soft}");
+    }
+    assert_eq!(soft_sqrt(f64::INFINITY), f64::INFINITY);
+    assert!(soft_sqrt(-1.0).is_nan());
+}
+
+#[test]
+fn test_soft_powf() {
+    for x in [1e-300f64, 1e-5, 0.0031308, 0.04045, 0.5, 0.99, 1.5, 2.0, 10.0, 1e10] {
+        for y in [2.4, 1.0 / 2.4, 1.0 / 2.2, 0.5, 2.0, -1.0, 3.0] {
+            let (hard, soft) = (x.powf(y), soft_powf(x, y));
+            assert!((hard - soft).abs() <= hard * 1e-12, "{x}^{y}: {hard} != {soft}");
+        }
+    }
+    assert_eq!(soft_powf(0.0, 2.4), 0.0);
+    assert_eq!(soft_powf(0.7, 0.0), 1.0);
+}
+
+#[test]
+fn test_roots() {
+    let close = |roots: &[f64], expected: &[f64]| {
+        roots.len() == expected.len()
+            && roots.iter().zip(expected).all(|(a, b)| (a - b).abs() <= 1e-9 * b.abs().max(1.0))
+    };
+    // (t - 1)(t - 2)
+    assert!(close(&quadratic_roots([2.0, -3.0, 1.0], 0.0, 10.0), &[1.0, 2.0]));
+    assert!(close(&quadratic_roots([2.0, -3.0, 1.0], 1.5, 10.0), &[2.0]));
+    assert!(close(&quadratic_roots([1.0, 0.0, 1.0], -10.0, 10.0), &[]));
+    assert!(close(&quadratic_roots([-4.0, 2.0, 0.0], 0.0, 10.0), &[2.0]));
+
+    // (t - 1)(t - 2)(t - 3)(t - 4)
+    let c = [24.0, -50.0, 35.0, -10.0, 1.0];
+    assert!(close(&quartic_roots(c, 0.0, 10.0), &[1.0, 2.0, 3.0, 4.0]));
+    assert!(close(&quartic_roots(c, 2.5, 10.0), &[3.0, 4.0]));
+    // (t^2 + 1)(t - 0.5)(t - 7)
+    let c = [3.5, -7.5, 4.5, -7.5, 1.0];
+    assert!(close(&quartic_roots(c, -100.0, 100.0), &[0.5, 7.0]));
+    assert!(close(&quartic_roots([1.0, 0.0, 0.0, 0.0, 1.0], -10.0, 10.0), &[]));
+}
+
+#[test]
+fn test_rng() {
+    let draw = |seed, pixel, sample| {
+        let mut rng = Rng::new(seed, pixel, sample);
+        [(); 4].map(|()| rng.next_f64())
+    };
+    let a = draw(1, [2, 3], 0);
+    assert!(a.iter().all(|it| (0.0..1.0).contains(it)));
+    assert_eq!(a, draw(1, [2, 3], 0));
+    for other in [draw(2, [2, 3], 0), draw(1, [3, 2], 0), draw(1, [2, 3], 1)] {
+        assert!(a.iter().zip(&other).all(|(a, b)| a != b));
+    }
+    let mut rng = Rng::new(1, [2, 3], 0);
+    assert!((0..100).all(|_| rng.in_unit_ball().norm_squared() <= 1.0));
+}
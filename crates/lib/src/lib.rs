@@ -0,0 +1,42 @@
+//! The whole ray tracer as a single library.
+//!
+//! [`render()`] works under the same constraints as the rest of the crates:
+//! `no_std`, and all memory is provided by the caller. With the `std` feature,
+//! [`render_image`] takes care of memory and threads instead.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub use bvh;
+pub use geom;
+pub use mem;
+pub use render::{self, rgb, Error, Options, Stats};
+pub use scene;
+
+/// Parses `crt` and renders it into `buf` on the current thread, allocating
+/// from `mem`. [`render::mem_estimate`] tells how much memory is enough.
+pub fn render<'a>(
+    crt: &'a str,
+    mem: &mut [u8],
+    buf: &mut rgb::Buf<'_>,
+) -> Result<Stats, Error<'a>> {
+    render::render(crt, mem, &Options::default(), &render::serial, buf)
+}
+
+/// Renders `crt` to a new `width` by `height` image, row by row, using as
+/// much memory as the scene needs and a thread per core.
+#[cfg(feature = "std")]
+pub fn render_image(
+    crt: &str,
+    dim @ [width, height]: rgb::Idx,
+) -> Result<Vec<rgb::Color>, Error<'_>> {
+    use std::num::NonZeroUsize;
+
+    let threads = threads::Threads::with_max_threads()
+        .unwrap_or_else(|_| threads::Threads::new(NonZeroUsize::MIN));
+    let estimate = render::mem_estimate(crt);
+    let mut mem = vec![0; estimate + estimate / 8 + 1024];
+    let mut pixels = vec![rgb::Color::default(); width as usize * height as usize];
+    let mut buf = rgb::Buf::new(dim, &mut pixels);
+    let in_parallel = |f: &(dyn Fn() + Sync)| threads.in_parallel(f);
+    render::render(crt, &mut mem, &Options::default(), &in_parallel, &mut buf)?;
+    Ok(pixels)
+}
@@ -38,7 +38,7 @@ impl<'m> Mem<'m> {
     pub fn alloc<T>(&mut self, t: T) -> Result<&'m mut T, Oom> {
         let size = mem::size_of::<T>();
         let align = mem::align_of::<T>();
-        assert!(size % align == 0);
+        assert!(size.is_multiple_of(align));
         self.align_to(align)?;
         let res = self.alloc_bytes(size)?;
         let ptr = res as *mut u8 as *mut T;
@@ -55,14 +55,18 @@ impl<'m> Mem<'m> {
     ) -> Result<&'m mut [T], Oom> {
         let size = mem::size_of::<T>();
         let align = mem::align_of::<T>();
-        assert!(size % align == 0);
+        assert!(size.is_multiple_of(align));
         self.align_to(align)?;
-        let alloc_size = size.checked_mul(n).ok_or(Oom)?;
+        let Some(alloc_size) = size.checked_mul(n) else {
+            trace::emit(trace::Event::Oom { requested: usize::MAX, free: self.free() });
+            return Err(Oom);
+        };
         let res = self.alloc_bytes(alloc_size)?;
         let mut ptr = res as *mut u8 as *mut T;
         let res = ptr::slice_from_raw_parts_mut(ptr, n);
+        // A panic in `element` leaks the elements written so far, which is
+        // safe: the slice is only handed out once all of them are.
         for i in 0..n {
-            assert!(cfg!(panic = "abort"));
             unsafe {
                 ptr::write(ptr, element(i));
                 ptr = ptr.add(1);
@@ -87,6 +91,7 @@ impl<'m> Mem<'m> {
 
     fn alloc_bytes(&mut self, n: usize) -> Result<*mut [u8], Oom> {
         if self.raw.len() < n {
+            trace::emit(trace::Event::Oom { requested: n, free: self.free() });
             return Err(Oom);
         }
         let raw = mem::take(&mut self.raw);
@@ -0,0 +1,688 @@
+//! Finding what a ray hits, behind a trait, so that the built-in BVHs can be
+//! swapped for another acceleration structure.
+
+use core::{fmt, iter};
+
+use bvh::{BoundingBox, Bvh};
+use geom::{cross, dot, v64, Ray};
+use mem::{Mem, Oom};
+use scene::{Clip, Curves, Material, Mesh, Plane, Quadric, Scene, Sphere, Torus, Triangle};
+
+/// A way to find intersections, see [`with_backend`](crate::with_backend).
+pub trait Backend {
+    type Built<'m>: Intersect<'m>;
+
+    /// Builds acceleration structures for `scene`, allocating from `mem`.
+    fn build<'m>(mem: &mut Mem<'m>, scene: &Scene<'m>) -> Result<Self::Built<'m>, Oom>;
+}
+
+/// Acceleration structures built for a scene by a [`Backend`].
+pub trait Intersect<'m>: Sync {
+    /// The nearest intersection of `ray` with the surfaces `kind` of rays see,
+    /// closer than `max_t`.
+    fn closest_hit<'s>(
+        &'s self,
+        scene: &'s Scene<'m>,
+        ray: &Ray,
+        max_t: f64,
+        kind: RayKind,
+    ) -> Option<Hit<'s>>;
+
+    /// Like [`Intersect::closest_hit`], also adding the number of nodes of the
+    /// acceleration structures visited to `visited`, for the BVH heatmap.
+    fn closest_hit_counted<'s>(
+        &'s self,
+        scene: &'s Scene<'m>,
+        ray: &Ray,
+        max_t: f64,
+        kind: RayKind,
+        visited: &mut u32,
+    ) -> Option<Hit<'s>> {
+        let _ = visited;
+        self.closest_hit(scene, ray, max_t, kind)
+    }
+
+    /// Whether `ray` hits anything closer than `max_t`, for shadows.
+    fn any_hit(&self, scene: &Scene<'m>, ray: &Ray, max_t: f64, kind: RayKind) -> bool {
+        self.closest_hit(scene, ray, max_t, kind).is_some()
+    }
+
+    /// [`Intersect::any_hit`] for a batch of rays, like the shadow rays from a
+    /// point to all the lights, with `max_t[i]` for `rays[i]`, which it may
+    /// lower. Sets `hit[i]` to whether `rays[i]` hits anything.
+    fn any_hit_stream(
+        &self,
+        scene: &Scene<'m>,
+        rays: &[Ray],
+        max_t: &mut [f64],
+        kind: RayKind,
+        hit: &mut [bool],
+    ) {
+        for (i, ray) in rays.iter().enumerate() {
+            hit[i] = self.any_hit(scene, ray, max_t[i], kind);
+        }
+    }
+
+    /// Catches up with the `mesh`-th mesh of `scene` having moved.
+    fn mesh_moved(&mut self, scene: &Scene<'m>, mesh: usize);
+
+    /// Checks the invariants of the structures, see
+    /// [`Options::validate_accel`](crate::Options::validate_accel).
+    fn validate(&self, _scratch: &mut Mem<'_>, _scene: &Scene<'m>) -> Result<(), Invalid> {
+        Ok(())
+    }
+}
+
+/// A broken invariant of the built structures.
+#[derive(Debug)]
+pub struct Invalid {
+    /// `None` for the structure over all meshes.
+    pub mesh: Option<usize>,
+    pub reason: bvh::Invalid,
+}
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mesh {
+            Some(mesh) => write!(f, "invalid bvh for mesh {mesh}: {}", self.reason),
+            None => write!(f, "invalid top-level bvh: {}", self.reason),
+        }
+    }
+}
+
+/// What a ray is cast for, which decides the surfaces it sees, see
+/// [`Visibility`](scene::Visibility).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RayKind {
+    Camera,
+    Shadow,
+    Reflection,
+}
+
+impl RayKind {
+    fn sees(self, material: &Material) -> bool {
+        let visible = material.visible;
+        match self {
+            RayKind::Camera => visible.camera,
+            RayKind::Shadow => visible.shadow,
+            RayKind::Reflection => visible.reflection,
+        }
+    }
+}
+
+pub struct Hit<'a> {
+    /// Distance along the ray.
+    pub t: f64,
+    /// Surface normal, not necessarily of unit length.
+    pub n: v64,
+    pub material: &'a Material,
+    /// Where on a mesh triangle the hit is, weights of its three vertices.
+    pub barycentric: Option<[f64; 3]>,
+}
+
+/// The default backend: spheres, planes, tori and quadrics are checked one by
+/// one, and each mesh and each set of curves has its own [`Bvh`].
+///
+/// The BVHs of the meshes are in the coordinates of their data, under a
+/// top-level BVH over the boxes of the meshes. Moving a mesh as a whole only
+/// refits the small top-level one.
+pub struct Builtin;
+
+pub struct Bvhs<'m> {
+    bvhs: &'m mut [Bvh<'m>],
+    top_level: Bvh<'m>,
+    curves: &'m mut [Bvh<'m>],
+}
+
+impl Backend for Builtin {
+    type Built<'m> = Bvhs<'m>;
+
+    fn build<'m>(mem: &mut Mem<'m>, scene: &Scene<'m>) -> Result<Bvhs<'m>, Oom> {
+        let bvhs = mem.alloc_array_default(scene.meshes.len())?;
+        for (i, m) in scene.meshes.iter().enumerate() {
+            let mut bbs = (0..m.f.len()).map(|it| triangle_bounding_box(m.local_triangle(it)));
+            bvhs[i] = Bvh::build(mem, &mut bbs)?;
+        }
+        let shutter = scene.camera.shutter;
+        let mut bbs =
+            iter::zip(&*scene.meshes, &*bvhs).map(|(m, bvh)| mesh_bounding_box(m, bvh, shutter));
+        let top_level = Bvh::build(mem, &mut bbs)?;
+        let curves = mem.alloc_array_default(scene.curves.len())?;
+        for (i, c) in scene.curves.iter().enumerate() {
+            let mut bbs = c.segments.iter().map(|it| curve_bounding_box(it, c.radius));
+            curves[i] = Bvh::build(mem, &mut bbs)?;
+        }
+        Ok(Bvhs { bvhs, top_level, curves })
+    }
+}
+
+impl<'m> Intersect<'m> for Bvhs<'m> {
+    fn closest_hit<'s>(
+        &'s self,
+        scene: &'s Scene<'m>,
+        ray: &Ray,
+        max_t: f64,
+        kind: RayKind,
+    ) -> Option<Hit<'s>> {
+        self.closest_hit_counted(scene, ray, max_t, kind, &mut 0)
+    }
+
+    fn closest_hit_counted<'s>(
+        &'s self,
+        scene: &'s Scene<'m>,
+        ray: &Ray,
+        max_t: f64,
+        kind: RayKind,
+        visited: &mut u32,
+    ) -> Option<Hit<'s>> {
+        let mut res = None;
+        let mut max_t = max_t;
+        let kept = |t: f64| {
+            let clip = scene.clip.as_ref();
+            clip.is_none_or(|it| dot(ray.at(t) - it.normal.origin(), it.normal.dir()) <= 0.0)
+        };
+        intersect_with(ray, kind, &mut res, &mut max_t, &kept, &*scene.spheres);
+        intersect_with(ray, kind, &mut res, &mut max_t, &kept, &*scene.planes);
+        intersect_with(ray, kind, &mut res, &mut max_t, &kept, &*scene.tori);
+        intersect_with(ray, kind, &mut res, &mut max_t, &kept, &*scene.quadrics);
+        let mut hit = None;
+        let mut visited_meshes = 0;
+        *visited += self.top_level.intersect(ray, &mut max_t, &mut |mesh, max_t| {
+            let (m, bvh) = (&scene.meshes[mesh as usize], &self.bvhs[mesh as usize]);
+            if !kind.sees(&m.material) {
+                return;
+            }
+            // A translation keeps distances along the ray, and so `max_t`.
+            let local = ray.moved(-(m.offset + m.velocity * ray.time()));
+            visited_meshes += bvh.intersect(&local, max_t, &mut |face, max_t| {
+                if let Some((t, n, barycentric)) =
+                    intersect_triangle(&m.local_triangle(face as usize), &local, *max_t)
+                        .filter(|&(t, ..)| kept(t))
+                {
+                    *max_t = t;
+                    hit = Some((m, n, barycentric));
+                }
+            });
+        });
+        *visited += visited_meshes;
+        if let Some((mesh, n, barycentric)) = hit {
+            let barycentric = Some(barycentric);
+            res = Some(Hit { t: max_t, n, material: &mesh.material, barycentric });
+        }
+        for (curves, bvh) in iter::zip(&*scene.curves, &*self.curves) {
+            if !kind.sees(&curves.material) {
+                continue;
+            }
+            let mut hit = None;
+            *visited += bvh.intersect(ray, &mut max_t, &mut |segment, max_t| {
+                if let Some((t, n)) = intersect_curve(curves, segment as usize, ray, *max_t, &kept)
+                {
+                    *max_t = t;
+                    hit = Some(n);
+                }
+            });
+            if let Some(n) = hit {
+                res = Some(Hit { t: max_t, n, material: &curves.material, barycentric: None });
+            }
+        }
+        if let Some(Clip { normal, cap: Some(material) }) = &scene.clip {
+            if let Some(hit) = &res {
+                // Coming from the removed side, the ray enters the kept one
+                // at `t`. If the next surface faces away, the ray is inside a
+                // solid from `t` on, and sees the cut through it.
+                let t = dot(normal.origin() - ray.origin(), normal.dir())
+                    / dot(ray.dir(), normal.dir());
+                let entering = dot(ray.dir(), normal.dir()) < 0.0 && 0.0 < t && t < hit.t;
+                if entering && dot(hit.n, ray.dir()) > 0.0 && kind.sees(material) {
+                    res = Some(Hit { t, n: normal.dir(), material, barycentric: None });
+                }
+            }
+        }
+        res
+    }
+
+    fn any_hit_stream(
+        &self,
+        scene: &Scene<'m>,
+        rays: &[Ray],
+        max_t: &mut [f64],
+        kind: RayKind,
+        hit: &mut [bool],
+    ) {
+        assert!(rays.len() == max_t.len() && rays.len() == hit.len());
+        // Any hit will do, so a hit drops `max_t` below every box, which ends
+        // the traversal for the ray.
+        let done = f64::NEG_INFINITY;
+        let kept = |ray: &Ray, t: f64| {
+            let clip = scene.clip.as_ref();
+            clip.is_none_or(|it| dot(ray.at(t) - it.normal.origin(), it.normal.dir()) <= 0.0)
+        };
+        for (i, ray) in rays.iter().enumerate() {
+            let (mut res, mut t) = (None, max_t[i]);
+            let kept = |t| kept(ray, t);
+            intersect_with(ray, kind, &mut res, &mut t, &kept, &*scene.spheres);
+            intersect_with(ray, kind, &mut res, &mut t, &kept, &*scene.planes);
+            intersect_with(ray, kind, &mut res, &mut t, &kept, &*scene.tori);
+            intersect_with(ray, kind, &mut res, &mut t, &kept, &*scene.quadrics);
+            hit[i] = res.is_some();
+            if hit[i] {
+                max_t[i] = done;
+            }
+        }
+        self.top_level.intersect_stream(rays, max_t, &mut |i, mesh, max_t| {
+            let (m, bvh) = (&scene.meshes[mesh as usize], &self.bvhs[mesh as usize]);
+            if !kind.sees(&m.material) {
+                return;
+            }
+            let local = rays[i].moved(-(m.offset + m.velocity * rays[i].time()));
+            bvh.intersect(&local, max_t, &mut |face, max_t| {
+                if intersect_triangle(&m.local_triangle(face as usize), &local, *max_t)
+                    .is_some_and(|(t, ..)| kept(&rays[i], t))
+                {
+                    *max_t = done;
+                    hit[i] = true;
+                }
+            });
+        });
+        for (curves, bvh) in iter::zip(&*scene.curves, &*self.curves) {
+            if !kind.sees(&curves.material) {
+                continue;
+            }
+            for (i, ray) in rays.iter().enumerate() {
+                bvh.intersect(ray, &mut max_t[i], &mut |segment, max_t| {
+                    let kept = |t| kept(ray, t);
+                    if intersect_curve(curves, segment as usize, ray, *max_t, &kept).is_some() {
+                        *max_t = done;
+                        hit[i] = true;
+                    }
+                });
+            }
+        }
+    }
+
+    fn mesh_moved(&mut self, scene: &Scene<'m>, _mesh: usize) {
+        let (bvhs, shutter) = (&*self.bvhs, scene.camera.shutter);
+        self.top_level.refit(&|mesh| {
+            mesh_bounding_box(&scene.meshes[mesh as usize], &bvhs[mesh as usize], shutter)
+        });
+    }
+
+    fn validate(&self, scratch: &mut Mem<'_>, scene: &Scene<'m>) -> Result<(), Invalid> {
+        for (mesh, (m, bvh)) in iter::zip(&*scene.meshes, &*self.bvhs).enumerate() {
+            let free = scratch.free();
+            scratch
+                .with_scratch(free, |_, scratch| bvh.validate(scratch, m.iter().len()))
+                .map_err(|reason| Invalid { mesh: Some(mesh), reason })?;
+        }
+        let free = scratch.free();
+        scratch
+            .with_scratch(free, |_, scratch| self.top_level.validate(scratch, scene.meshes.len()))
+            .map_err(|reason| Invalid { mesh: None, reason })
+    }
+}
+
+/// Hits this close to the origin of a ray are ignored by all primitives, so
+/// that a ray starting exactly on a surface never hits it at `t = 0`.
+/// Secondary rays also start a bit off the surface, see
+/// [`RenderSettings::shadow_bias`](scene::RenderSettings::shadow_bias).
+const T_MIN: f64 = 1e-9;
+
+/// Whether a hit at `t` counts: further than [`T_MIN`] and closer than
+/// `max_t`, the same for every primitive.
+fn in_range(t: f64, max_t: f64) -> bool {
+    T_MIN < t && t < max_t
+}
+
+fn intersect_with<'a, T, I>(
+    ray: &Ray,
+    kind: RayKind,
+    res: &mut Option<Hit<'a>>,
+    max_t: &mut f64,
+    kept: &impl Fn(f64) -> bool,
+    objects: I,
+) where
+    T: RenderObject<'a>,
+    I: IntoIterator<Item = T>,
+{
+    for o in objects {
+        if !kind.sees(o.material()) {
+            continue;
+        }
+        if let Some((t, n)) = o.intersect(ray, *max_t, kept) {
+            *max_t = t;
+            *res = Some(Hit { t, n, material: o.material(), barycentric: None })
+        }
+    }
+}
+
+trait RenderObject<'a> {
+    /// The nearest hit closer than `max_t` which is `kept` by the clip plane.
+    fn intersect(&self, ray: &Ray, max_t: f64, kept: &impl Fn(f64) -> bool) -> Option<(f64, v64)>;
+    fn material(&self) -> &'a Material;
+}
+
+impl<'a> RenderObject<'a> for &'a Sphere {
+    fn intersect(&self, ray: &Ray, max_t: f64, kept: &impl Fn(f64) -> bool) -> Option<(f64, v64)> {
+        let center = self.center + self.velocity * ray.time();
+        let o = ray.origin() - center;
+        let k = dot(ray.dir(), o);
+        let c = dot(o, o) - self.radius.powi(2);
+
+        let disc = k * k - c;
+        if disc < 0.0 {
+            return None;
+        }
+
+        let t1 = -k - geom::sqrt(disc);
+        let t2 = -k + geom::sqrt(disc);
+        let t = [t1, t2].into_iter().find(|&t| T_MIN < t && kept(t))?;
+        if !in_range(t, max_t) {
+            return None;
+        }
+
+        let point = ray.at(t);
+        let normal = point - center;
+        Some((t, normal))
+    }
+    fn material(&self) -> &'a Material {
+        &self.material
+    }
+}
+
+impl<'a> RenderObject<'a> for &'a Plane {
+    fn intersect(&self, ray: &Ray, max_t: f64, kept: &impl Fn(f64) -> bool) -> Option<(f64, v64)> {
+        let o = ray.origin() - self.normal.origin();
+        let t = -dot(o, self.normal.dir()) / dot(ray.dir(), self.normal.dir());
+        if !(in_range(t, max_t) && kept(t)) {
+            return None;
+        }
+        Some((t, self.normal.dir()))
+    }
+    fn material(&self) -> &'a Material {
+        &self.material
+    }
+}
+
+impl<'a> RenderObject<'a> for &'a Torus {
+    fn intersect(&self, ray: &Ray, max_t: f64, kept: &impl Fn(f64) -> bool) -> Option<(f64, v64)> {
+        // Starting from where the ray enters the bounding sphere keeps the
+        // coefficients, and so the rounding errors, small.
+        let (r2, tube2) = (self.radius.powi(2), self.tube.powi(2));
+        let bound = self.radius.abs() + self.tube.abs();
+        let o = ray.origin() - self.center;
+        let k = dot(ray.dir(), o);
+        let disc = k * k - (dot(o, o) - bound * bound);
+        if disc < 0.0 {
+            return None;
+        }
+        let t0 = (-k - geom::sqrt(disc)).max(0.0);
+        let t1 = (-k + geom::sqrt(disc)).min(max_t);
+        if t0 >= t1 {
+            return None;
+        }
+
+        // With `p = o + t d`, the torus is
+        // `(|p|^2 + R^2 - r^2)^2 = 4 R^2 (|p|^2 - (p . axis)^2)`.
+        let (o, d) = (o + ray.dir() * t0, ray.dir());
+        let (b, c) = (dot(o, d), dot(o, o));
+        let (e, f) = (dot(o, self.axis), dot(d, self.axis));
+        let k = c + r2 - tube2;
+        let coefficients = [
+            k * k - 4.0 * r2 * (c - e * e),
+            4.0 * b * k - 8.0 * r2 * (b - e * f),
+            4.0 * b * b + 2.0 * k - 4.0 * r2 * (1.0 - f * f),
+            4.0 * b,
+            1.0,
+        ];
+        let roots = geom::quartic_roots(coefficients, T_MIN - t0, t1 - t0);
+        let t = roots.iter().map(|&t| t0 + t).find(|&t| in_range(t, max_t) && kept(t))?;
+
+        let p = ray.at(t) - self.center;
+        let ring = p - self.axis * dot(p, self.axis);
+        // Only a torus whose tube is thicker than its radius reaches the axis,
+        // where the ring direction is undefined and the normal is along it.
+        let n = if ring.norm() > 0.0 { p - ring.to_unit() * self.radius } else { p };
+        Some((t, n))
+    }
+    fn material(&self) -> &'a Material {
+        &self.material
+    }
+}
+
+impl<'a> RenderObject<'a> for &'a Quadric {
+    fn intersect(&self, ray: &Ray, max_t: f64, kept: &impl Fn(f64) -> bool) -> Option<(f64, v64)> {
+        let (o, d) = (ray.origin() - self.center, ray.dir());
+        let a = quadratic_form(self, d, d);
+        let b = 2.0 * quadratic_form(self, o, d) + dot(self.linear, d);
+        let c = quadratic_form(self, o, o) + dot(self.linear, o) + self.constant;
+        let roots = geom::quadratic_roots([c, b, a], T_MIN, max_t);
+        let t = roots.iter().copied().find(|&t| kept(t))?;
+
+        let [x, y, z] = (ray.at(t) - self.center).xyz();
+        let (s, m) = (self.square, self.mixed);
+        let gradient = v64(
+            2.0 * s.x * x + m.x * y + m.y * z,
+            2.0 * s.y * y + m.x * x + m.z * z,
+            2.0 * s.z * z + m.y * x + m.z * y,
+        );
+        Some((t, gradient + self.linear))
+    }
+    fn material(&self) -> &'a Material {
+        &self.material
+    }
+}
+
+/// The symmetric bilinear form of the second degree terms of the quadric.
+fn quadratic_form(q: &Quadric, u: v64, v: v64) -> f64 {
+    let (s, m) = (q.square, q.mixed);
+    s.x * u.x * v.x
+        + s.y * u.y * v.y
+        + s.z * u.z * v.z
+        + 0.5 * m.x * (u.x * v.y + u.y * v.x)
+        + 0.5 * m.y * (u.x * v.z + u.z * v.x)
+        + 0.5 * m.z * (u.y * v.z + u.z * v.y)
+}
+
+fn intersect_triangle(tr: &Triangle, ray: &Ray, max_t: f64) -> Option<(f64, v64, [f64; 3])> {
+    let ab = tr.v[1] - tr.v[0];
+    let ac = tr.v[2] - tr.v[0];
+    let n = cross(ab, ac);
+    // a + alpha ab + beta ac = ray.origin + t * ray.direction
+    let t = dot(tr.v[0] - ray.origin(), n) / dot(ray.dir(), n);
+    if !in_range(t, max_t) {
+        return None;
+    }
+    let point = ray.at(t);
+    let local_coords: [f64; 3] = {
+        let ort_ac = cross(ac, n);
+        let ort_ab = cross(ab, n);
+        let point = point - tr.v[0];
+        let alpha = dot(point, ort_ac) / dot(ab, ort_ac);
+        let beta = dot(point, ort_ab) / dot(ac, ort_ab);
+        let gamma = 1.0 - (alpha + beta);
+        [gamma, alpha, beta]
+    };
+    if local_coords.map(|it| 0.0 < it && it < 1.0) != [true; 3] {
+        return None;
+    }
+    let mut n = v64::ZERO;
+    for (&ni, c) in iter::zip(&tr.n, local_coords) {
+        n = n + ni * c;
+    }
+    Some((t, n, local_coords))
+}
+
+fn triangle_bounding_box(t: Triangle) -> BoundingBox {
+    BoundingBox::from_points(&t.v)
+}
+
+/// The box of the faces of a mesh where it is now, from the box of its BVH,
+/// stretched to where the mesh moves by the time the `shutter` closes.
+fn mesh_bounding_box(mesh: &Mesh<'_>, bvh: &Bvh<'_>, shutter: f64) -> BoundingBox {
+    let end = mesh.offset + mesh.velocity * shutter;
+    match bvh.bounds() {
+        Some(bb) => BoundingBox::from_points(&[
+            bb.lo() + mesh.offset,
+            bb.hi() + mesh.offset,
+            bb.lo() + end,
+            bb.hi() + end,
+        ]),
+        None => BoundingBox::from_points(&[mesh.offset, end]),
+    }
+}
+
+/// Curves which bend this many times more than their radius are split into
+/// pieces which are straight enough to be taken for cylinders.
+const CURVE_FLATNESS: f64 = 20.0;
+
+/// Intersects the tube around a segment of `curves`. Works in the frame of
+/// the ray, where it starts at the origin and goes along z, so that a hit is
+/// a piece of the curve passing within the radius of the z axis.
+fn intersect_curve(
+    curves: &Curves<'_>,
+    segment: usize,
+    ray: &Ray,
+    max_t: f64,
+    kept: &impl Fn(f64) -> bool,
+) -> Option<(f64, v64)> {
+    let d = ray.dir();
+    let x = cross(d, if d.x.abs() < 0.5 { v64(1.0, 0.0, 0.0) } else { v64(0.0, 1.0, 0.0) });
+    let x = x.to_unit();
+    let y = cross(d, x);
+    let cp = curves.segments[segment];
+    let local = cp.map(|p| {
+        let p = p - ray.origin();
+        v64(dot(p, x), dot(p, y), dot(p, d))
+    });
+
+    // How far the control points are from a straight line bounds how far the
+    // curve is from its chord, a quarter of that with each split.
+    let radius = curves.radius.abs();
+    let bend = (0..2).map(|i| (local[i] - local[i + 1] * 2.0 + local[i + 2]).norm());
+    let mut excess = bend.fold(0.0, f64::max) * CURVE_FLATNESS / radius;
+    let mut depth = 0;
+    while excess > 1.0 && depth < 10 {
+        excess /= 4.0;
+        depth += 1;
+    }
+
+    let mut max_t = max_t;
+    let mut hit = None;
+    curve_recur(local, [0.0, 1.0], depth, radius, &mut max_t, &mut hit, kept);
+    let u = hit?;
+    // The hit is only as precise as the pieces are straight, which would show
+    // as rings if not for dropping the part of the normal along the curve.
+    let mut n = ray.at(max_t) - bezier(&cp, u);
+    let tangent = bezier_tangent(&cp, u);
+    if tangent.norm_squared() > 0.0 {
+        n = n - tangent * (dot(n, tangent) / tangent.norm_squared());
+    }
+    Some((max_t, n))
+}
+
+fn curve_recur(
+    cp: [v64; 4],
+    [u0, u1]: [f64; 2],
+    depth: u32,
+    radius: f64,
+    max_t: &mut f64,
+    hit: &mut Option<f64>,
+    kept: &impl Fn(f64) -> bool,
+) {
+    let bb = BoundingBox::from_points(&cp).grow(radius);
+    let [lo, hi] = [bb.lo(), bb.hi()];
+    if lo.x > 0.0 || hi.x < 0.0 || lo.y > 0.0 || hi.y < 0.0 || hi.z < T_MIN || lo.z > *max_t {
+        return;
+    }
+    if depth > 0 {
+        let mid = (u0 + u1) / 2.0;
+        let [l, r] = split_bezier(cp);
+        curve_recur(l, [u0, mid], depth - 1, radius, max_t, hit, kept);
+        curve_recur(r, [mid, u1], depth - 1, radius, max_t, hit, kept);
+        return;
+    }
+
+    // The point of the chord closest to the ray, and the curve there.
+    let chord = cp[3] - cp[0];
+    let len2 = chord.x * chord.x + chord.y * chord.y;
+    let w = if len2 > 0.0 {
+        (-(cp[0].x * chord.x + cp[0].y * chord.y) / len2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let p = bezier(&cp, w);
+    let d2 = p.x * p.x + p.y * p.y;
+    if d2 > radius * radius {
+        return;
+    }
+    // Where the ray enters the tube, or leaves it when starting inside.
+    let half = geom::sqrt(radius * radius - d2);
+    let Some(t) = [p.z - half, p.z + half].into_iter().find(|&t| T_MIN < t && kept(t)) else {
+        return;
+    };
+    if in_range(t, *max_t) {
+        *max_t = t;
+        *hit = Some(u0 + w * (u1 - u0));
+    }
+}
+
+fn bezier(cp: &[v64; 4], u: f64) -> v64 {
+    let s = 1.0 - u;
+    cp[0] * (s * s * s)
+        + cp[1] * (3.0 * s * s * u)
+        + cp[2] * (3.0 * s * u * u)
+        + cp[3] * (u * u * u)
+}
+
+fn bezier_tangent(cp: &[v64; 4], u: f64) -> v64 {
+    let s = 1.0 - u;
+    (cp[1] - cp[0]) * (3.0 * s * s)
+        + (cp[2] - cp[1]) * (6.0 * s * u)
+        + (cp[3] - cp[2]) * (3.0 * u * u)
+}
+
+/// De Casteljau's split of a curve into its halves.
+fn split_bezier(cp: [v64; 4]) -> [[v64; 4]; 2] {
+    let mid = |a: v64, b: v64| (a + b) / 2.0;
+    let (a, b, c) = (mid(cp[0], cp[1]), mid(cp[1], cp[2]), mid(cp[2], cp[3]));
+    let (d, e) = (mid(a, b), mid(b, c));
+    let m = mid(d, e);
+    [[cp[0], a, d, m], [m, e, c, cp[3]]]
+}
+
+fn curve_bounding_box(cp: &[v64; 4], radius: f64) -> BoundingBox {
+    BoundingBox::from_points(cp).grow(radius.abs())
+}
+
+#[test]
+fn test_t_range() {
+    let sphere = &Sphere { center: v64::ZERO, radius: 1.0, ..Default::default() };
+    // All three surfaces touch at `(0, 0, -1)`.
+    let normal = Ray::new(v64(0.0, 0.0, -1.0), v64(0.0, 0.0, -1.0));
+    let plane = &Plane { normal, ..Plane::default() };
+    let triangle = Triangle {
+        v: [v64(-1.0, -1.0, -1.0), v64(1.0, -1.0, -1.0), v64(0.0, 1.0, -1.0)],
+        n: [v64(0.0, 0.0, -1.0); 3],
+    };
+    let kept = |_| true;
+    let hit = |ray: &Ray, max_t: f64| {
+        [
+            sphere.intersect(ray, max_t, &kept).map(|it| it.0),
+            plane.intersect(ray, max_t, &kept).map(|it| it.0),
+            intersect_triangle(&triangle, ray, max_t).map(|it| it.0),
+        ]
+    };
+
+    // Starting on the surface and leaving it, there is nothing to hit.
+    let on_surface = Ray::new(v64(0.0, 0.0, -1.0), v64(0.0, 0.0, -1.0));
+    assert_eq!(hit(&on_surface, f64::INFINITY), [None; 3]);
+
+    // Hits exactly at `max_t` don't count, slightly before do.
+    let ray = Ray::new(v64(0.0, 0.0, -2.0), v64(0.0, 0.0, 1.0));
+    assert_eq!(hit(&ray, 1.0), [None; 3]);
+    assert_eq!(hit(&ray, 1.0 + 1e-6), [Some(1.0); 3]);
+
+    // From the surface into the sphere, the far side is hit.
+    let inward = Ray::new(v64(0.0, 0.0, -1.0), v64(0.0, 0.0, 1.0));
+    assert_eq!(hit(&inward, f64::INFINITY)[0], Some(2.0));
+}
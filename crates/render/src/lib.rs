@@ -1,11 +1,15 @@
 #![no_std]
+pub mod backend;
 pub mod rgb;
 mod render;
 
-use bvh::{BoundingBox, Bvh};
-use geom::{cross, v64, Ray};
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering::SeqCst};
+
+use backend::{Backend, Builtin, Bvhs, Intersect, RayKind};
+use bvh::Bvh;
+use geom::{cross, dot, splitmix64, v64, Ray, Rng};
 use mem::{Mem, Oom};
-use scene::{Color, Triangle};
+use scene::{Blobs, Color, Integrator, Light, LightKind, RenderSettings, Scene, Target, Tonemap};
 
 /// {0}
 #[derive(Debug, displaydoc::Display)]
@@ -17,6 +21,28 @@ enum ErrorRepr<'a> {
     ParseSceneError(scene::ParseSceneError<'a>),
     /// oom while constructing bhv
     BhvConstructionError(Oom),
+    /// {0}
+    InvalidAccel(backend::Invalid),
+}
+
+impl<'a> Error<'a> {
+    /// Line of the scene file the error is about, if any.
+    pub fn line(&self) -> Option<u32> {
+        match &self.0 {
+            ErrorRepr::ParseSceneError(err) => err.line(),
+            ErrorRepr::BhvConstructionError(_) | ErrorRepr::InvalidAccel(_) => None,
+        }
+    }
+
+    /// Whether the error is about running out of `mem` rather than the scene
+    /// being invalid.
+    pub fn is_oom(&self) -> bool {
+        match &self.0 {
+            ErrorRepr::ParseSceneError(err) => err.is_oom(),
+            ErrorRepr::BhvConstructionError(_) => true,
+            ErrorRepr::InvalidAccel(_) => false,
+        }
+    }
 }
 
 impl<'a> From<ErrorRepr<'a>> for Error<'a> {
@@ -25,90 +51,1160 @@ impl<'a> From<ErrorRepr<'a>> for Error<'a> {
     }
 }
 
-type ThreadPool<'t> = dyn Fn(&(dyn Fn() + Sync)) + 't;
+/// Calls the closure on each of several threads and waits for all of them,
+/// `threads::Threads::in_parallel` is one implementation.
+///
+/// The closure pulls work from a shared queue until it's empty, so calling it
+/// just once, on the current thread, is enough: see [`serial`]. Hosts without
+/// threads, like `wasm32-unknown-unknown` in web workers, which don't share
+/// memory, can instead split an image between several renders with
+/// [`Options::tiles`].
+pub type ThreadPool<'t> = dyn Fn(&(dyn Fn() + Sync)) + 't;
+
+/// The [`ThreadPool`] without threads, which renders everything on the
+/// current one.
+pub fn serial(f: &(dyn Fn() + Sync)) {
+    f()
+}
+
+/// Knobs which are not part of the scene description.
+///
+/// The `Option` fields, when set, override the corresponding values from the
+/// scene's `settings` block.
+#[derive(Default)]
+pub struct Options<'a> {
+    pub dither: rgb::Dither,
+    pub samples: Option<u32>,
+    pub bounces: Option<u32>,
+    /// Setting the exposure switches auto-exposure off.
+    pub exposure: Option<f64>,
+    pub auto_exposure: Option<bool>,
+    pub temperature: Option<f64>,
+    pub tint: Option<f64>,
+    pub tonemap: Option<Tonemap>,
+    pub gamma: Option<f64>,
+    pub integrator: Option<Integrator>,
+    pub seed: Option<u64>,
+    pub shadow_bias: Option<f64>,
+    /// Comma-separated light groups to render with, the lights of the other
+    /// groups are switched off. Lights without a group are always on.
+    pub lights: Option<&'a str>,
+    /// Comma-separated patterns of the names of the objects to render, where
+    /// `*` matches anything. Patterns starting with `-` hide the objects they
+    /// match instead, and if there are only those, the rest are shown.
+    pub objects: Option<&'a str>,
+    /// The contents of the files `data_bin` keys of meshes refer to, see
+    /// [`Scene::blobs`].
+    pub blobs: Option<&'a Blobs<'a>>,
+    /// Monotonic clock, in nanoseconds, used to fill the durations in
+    /// [`Stats`]. This crate is `no_std` and can't read the time itself.
+    pub clock: Option<fn() -> u64>,
+    /// Polled by the workers before each row. Once it returns `true`, rows
+    /// which are already being rendered are finished, and the rest are skipped,
+    /// which is how Ctrl-C, time budgets and restarts stop the CPU work.
+    pub cancel: Option<&'a (dyn Fn() -> bool + Sync)>,
+    /// Called from the workers after each finished row with the number of rows
+    /// done so far and the total. Calls from different threads may arrive out
+    /// of order.
+    pub progress: Option<&'a (dyn Fn(u32, u32) + Sync)>,
+    /// Renders only some of the tiles, leaving the rest of the pixels black.
+    pub tiles: Option<Tiles>,
+    /// Which rows are handed out to the workers first.
+    pub order: Order,
+    /// How many rows a worker takes at once. Larger chunks mean less
+    /// contention between the workers, smaller ones balance the load better.
+    /// It's raised as needed to split the image into at most 256 bands, and
+    /// by default, it's the smallest value that does.
+    pub chunk: Option<u32>,
+    /// Checks the acceleration structures after building them, and fails
+    /// with an error instead of rendering if they are broken. Slow, for
+    /// debugging a [`Backend`].
+    pub validate_accel: bool,
+}
+
+/// The order in which bands of rows are rendered. The image is the same
+/// regardless, but previews and streamed output show the interesting parts
+/// sooner.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Order {
+    #[default]
+    TopDown,
+    /// From the middle of the image outwards, alternating up and down, as the
+    /// subject is usually in the center.
+    CenterOut,
+    /// Every eighth band first, then the ones in between, so that a coarse
+    /// version of the whole image appears early.
+    Interleaved,
+    /// The bands which look the most expensive first, judging by how much of
+    /// the BVHs a few probe rays through each band visit. The cheap bands
+    /// left for the end even out the load, so that no thread is still busy
+    /// with a small but expensive part of the image when the others are
+    /// done.
+    CostliestFirst,
+}
+
+impl Order {
+    pub fn from_name(name: &str) -> Option<Order> {
+        let res = match name {
+            "top-down" => Order::TopDown,
+            "center-out" => Order::CenterOut,
+            "interleaved" => Order::Interleaved,
+            "costliest-first" => Order::CostliestFirst,
+            _ => return None,
+        };
+        Some(res)
+    }
+}
+
+/// One of `count` disjoint subsets of [`Tiles::SIZE`] squares of the image, so
+/// that several processes can split a render between them.
+///
+/// Tiles are dealt out round robin in row-major order, which spreads the
+/// expensive parts of the image evenly.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Tiles {
+    /// Zero-based, less than `count`.
+    pub index: u32,
+    pub count: u32,
+}
+
+impl Tiles {
+    pub const SIZE: u32 = 64;
+
+    /// Which of the `count` subsets the pixel at `[x, y]` of an image `width`
+    /// pixels wide belongs to.
+    pub fn owner(count: u32, width: u32, [x, y]: rgb::Idx) -> Tiles {
+        let per_row = width.div_ceil(Tiles::SIZE);
+        let tile = (y / Tiles::SIZE) as u64 * per_row as u64 + (x / Tiles::SIZE) as u64;
+        Tiles { index: (tile % count as u64) as u32, count }
+    }
+
+    pub fn contains(&self, width: u32, idx: rgb::Idx) -> bool {
+        Tiles::owner(self.count, width, idx) == *self
+    }
+}
+
+/// Where the time went and how much work was done.
+///
+/// Durations are in nanoseconds and are zero unless [`Options::clock`] is set.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct Stats {
+    pub parse: u64,
+    pub bvh_build: u64,
+    pub trace: u64,
+    /// Total number of rays cast: camera rays, shadow rays and reflections.
+    pub rays: u64,
+}
+
+impl Options<'_> {
+    fn settings(&self, scene: &RenderSettings) -> RenderSettings {
+        RenderSettings {
+            samples: self.samples.unwrap_or(scene.samples).max(1),
+            bounces: self.bounces.unwrap_or(scene.bounces),
+            min_survival: scene.min_survival,
+            glossy_splits: scene.glossy_splits,
+            exposure: self.exposure.unwrap_or(scene.exposure),
+            auto_exposure: self.exposure.is_none()
+                && self.auto_exposure.unwrap_or(scene.auto_exposure),
+            temperature: self.temperature.unwrap_or(scene.temperature),
+            tint: self.tint.unwrap_or(scene.tint),
+            tonemap: self.tonemap.unwrap_or(scene.tonemap),
+            gamma: self.gamma.unwrap_or(scene.gamma),
+            vignette: scene.vignette,
+            grain: scene.grain,
+            integrator: self.integrator.unwrap_or(scene.integrator),
+            seed: self.seed.unwrap_or(scene.seed),
+            shadow_bias: self.shadow_bias.or(scene.shadow_bias),
+            ao_rays: scene.ao_rays,
+            ao_distance: scene.ao_distance,
+        }
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.is_some_and(|it| it())
+    }
+
+    fn row_done(&self, rows_done: &AtomicU32, total: u32) {
+        if let Some(progress) = self.progress {
+            progress(rows_done.fetch_add(1, SeqCst) + 1, total);
+        }
+    }
+
+    fn skipped(&self, width: u32, idx: rgb::Idx) -> bool {
+        self.tiles.is_some_and(|it| !it.contains(width, idx))
+    }
+
+    fn now(&self) -> u64 {
+        self.clock.map_or(0, |clock| clock())
+    }
+}
 
 pub fn render<'a>(
     crt: &'a str,
     mem: &mut [u8],
+    opts: &Options<'_>,
     in_parallel: &ThreadPool<'_>,
     buf: &mut rgb::Buf<'_>,
-) -> Result<(), Error<'a>> {
-    Mem::with(mem, |mem| render_impl(crt, mem, in_parallel, buf))
+) -> Result<Stats, Error<'a>> {
+    with_renderer(crt, mem, opts, |r| r.render(in_parallel, buf))
 }
 
-fn render_impl<'a, 'm>(
+/// Like [`render`], but instead of filling a buffer hands completed pieces of
+/// rows to `sink` as soon as they are ready.
+///
+/// Rows are rendered roughly in [`Options::order`], but the spans of different
+/// rows arrive in arbitrary order from different threads. Pixels are in linear RGB,
+/// use [`rgb::quantize`] to get the final colors.
+pub fn render_rows<'a>(
     crt: &'a str,
-    mem: &mut Mem<'m>,
+    mem: &mut [u8],
+    opts: &Options<'_>,
     in_parallel: &ThreadPool<'_>,
-    buf: &mut rgb::Buf<'_>,
-) -> Result<(), Error<'a>> {
-    let scene = scene::Scene::parse(mem, crt).map_err(ErrorRepr::ParseSceneError)?;
-    let bhvs =
-        mem.alloc_array_default(scene.meshes.len()).map_err(ErrorRepr::BhvConstructionError)?;
-    for (i, m) in scene.meshes.iter().enumerate() {
-        let mut bbs = m.iter().map(triangle_bounding_box);
-        bhvs[i] = Bvh::build(mem, &mut bbs).map_err(ErrorRepr::BhvConstructionError)?;
+    dim: rgb::Idx,
+    sink: &(dyn Fn(rgb::Span<'_, Color>) + Sync),
+) -> Result<Stats, Error<'a>> {
+    with_renderer(crt, mem, opts, |r| r.render_rows(in_parallel, dim, sink))
+}
+
+/// Parses the scene and builds BVHs once, and hands the result to `f`, which
+/// can render it several times, for example, to make an animation.
+pub fn with_renderer<'a, T>(
+    crt: &'a str,
+    mem: &mut [u8],
+    opts: &Options<'_>,
+    f: impl FnOnce(&mut Renderer<'_, '_>) -> T,
+) -> Result<T, Error<'a>> {
+    with_backend::<Builtin, T>(crt, mem, opts, f)
+}
+
+/// Like [`with_renderer`], but finds intersections with the backend `B`
+/// instead of the built-in BVHs.
+pub fn with_backend<'a, B: Backend, T>(
+    crt: &'a str,
+    mem: &mut [u8],
+    opts: &Options<'_>,
+    f: impl for<'r, 'm> FnOnce(&mut Renderer<'r, 'm, B::Built<'m>>) -> T,
+) -> Result<T, Error<'a>> {
+    Mem::with(mem, |mem| {
+        let mut stats = Stats::default();
+        let (scene, backend) = prepare::<B>(crt, mem, opts, &mut stats)?;
+        let extent = extent(&scene);
+        let mut renderer = Renderer { scene, backend, opts, stats, extent, exposure: 0.0 };
+        renderer.expose();
+        Ok(f(&mut renderer))
+    })
+}
+
+/// A scene ready to be rendered, see [`with_renderer`].
+pub struct Renderer<'r, 'm, B = Bvhs<'m>> {
+    scene: Scene<'m>,
+    backend: B,
+    opts: &'r Options<'r>,
+    /// Preparation timings, included in the stats of each render.
+    stats: Stats,
+    /// See [`extent`].
+    extent: f64,
+    /// [`RenderSettings::exposure`], or what [`auto_exposure`] picked for the
+    /// current camera and time.
+    exposure: f64,
+}
+
+impl<'r, 'm, B: Intersect<'m>> Renderer<'r, 'm, B> {
+    pub fn scene(&self) -> &Scene<'m> {
+        &self.scene
     }
 
-    let camera = Camera::new(&scene.camera);
+    /// Whether [`Options::cancel`] asked to stop, so the last render is
+    /// incomplete.
+    pub fn cancelled(&self) -> bool {
+        self.opts.cancelled()
+    }
+
+    /// Moves animated objects to where they are at `time`, letting the
+    /// backend know about the meshes that moved.
+    pub fn set_time(&mut self, time: f64) {
+        self.scene.at_time(time);
+        for track in self.scene.tracks.iter() {
+            if let Target::MeshOffset(i) = track.target {
+                self.backend.mesh_moved(&self.scene, i as usize);
+            }
+        }
+        self.extent = extent(&self.scene);
+        self.expose();
+    }
+
+    /// Looks at the scene from elsewhere, for interactive viewers. Animated
+    /// cameras move back on the next [`Renderer::set_time`].
+    pub fn set_camera(&mut self, camera: scene::Camera) {
+        let shutter = self.scene.camera.shutter;
+        self.scene.camera = camera;
+        if camera.shutter != shutter {
+            // Moving meshes are bounded by how far they go while the shutter
+            // is open.
+            for (i, mesh) in self.scene.meshes.iter().enumerate() {
+                if mesh.velocity != v64::ZERO {
+                    self.backend.mesh_moved(&self.scene, i);
+                }
+            }
+        }
+        self.expose();
+    }
+
+    fn expose(&mut self) {
+        let settings = self.opts.settings(&self.scene.settings);
+        self.exposure = if settings.auto_exposure {
+            auto_exposure(&self.scene, &self.backend, &settings, self.extent)
+        } else {
+            settings.exposure
+        };
+    }
+
+    fn settings(&self) -> RenderSettings {
+        RenderSettings { exposure: self.exposure, ..self.opts.settings(&self.scene.settings) }
+    }
+
+    fn frame(&self, dim: rgb::Idx) -> Frame<'_, 'm, B> {
+        let settings = self.settings();
+        Frame::new(&self.scene, &self.backend, settings, self.opts.dither, self.extent, dim)
+    }
+
+    pub fn render(&self, in_parallel: &ThreadPool<'_>, buf: &mut rgb::Buf<'_>) -> Stats {
+        let opts = self.opts;
+        let mut stats = self.stats;
+        let frame = self.frame(buf.dim());
+        let [width, height] = buf.dim();
+
+        trace::emit(trace::Event::Start(trace::Phase::Render));
+        let start = opts.now();
+        let rays = AtomicU64::new(0);
+        let rows_done = AtomicU32::new(0);
+        let band_height = rgb::band_height(height, opts.chunk);
+        let bands = buf.partition(opts.order, opts.chunk, &|band| frame.cost(band, band_height));
+        in_parallel(&|| {
+            let mut n_rays = 0;
+            while let Some(band) = bands.next_band() {
+                for row in band.rows() {
+                    if opts.cancelled() {
+                        break;
+                    }
+                    for (x, pixel) in row.buf.iter_mut().enumerate() {
+                        let idx = [x as u32, row.y];
+                        *pixel = if opts.skipped(width, idx) {
+                            rgb::Color::default()
+                        } else {
+                            frame.pixel(idx, &mut n_rays)
+                        };
+                    }
+                    opts.row_done(&rows_done, height);
+                }
+            }
+            rays.fetch_add(n_rays, SeqCst);
+        });
+        stats.trace = opts.now() - start;
+        trace::emit(trace::Event::End(trace::Phase::Render));
+        stats.rays = rays.into_inner();
+        stats
+    }
 
-    let dim = buf.dim();
-    let rows = buf.partition();
-    in_parallel(&|| {
-        while let Some(row) = rows.next_row() {
-            let y = row.y;
-            for x in 0..dim[0] {
-                let [dx, dy] = to_scree_space(dim, [x, y]);
-                let ray = camera.cast(dx, dy);
-                let color = render::render(&scene, &bhvs, &ray);
-                row.buf[x as usize] = to_rgb(&color);
+    /// See [`render_rows`].
+    pub fn render_rows(
+        &self,
+        in_parallel: &ThreadPool<'_>,
+        dim: rgb::Idx,
+        sink: &(dyn Fn(rgb::Span<'_, Color>) + Sync),
+    ) -> Stats {
+        let opts = self.opts;
+        let mut stats = self.stats;
+        let frame = self.frame(dim);
+
+        trace::emit(trace::Event::Start(trace::Phase::Render));
+        let start = opts.now();
+        let rays = AtomicU64::new(0);
+        let band_height = rgb::band_height(dim[1], opts.chunk);
+        let n_bands = dim[1].div_ceil(band_height) as usize;
+        let bands = rgb::Schedule::new(opts.order, n_bands, &|band| frame.cost(band, band_height));
+        let rows_done = AtomicU32::new(0);
+        in_parallel(&|| {
+            let mut n_rays = 0;
+            let mut span = [Color::default(); SPAN];
+            'bands: while let Some(band) = bands.next() {
+                let y0 = band as u32 * band_height;
+                for y in y0..(y0 + band_height).min(dim[1]) {
+                    if opts.cancelled() {
+                        break 'bands;
+                    }
+                    frame.row(opts, y, &mut span, &mut n_rays, &mut |span| sink(span));
+                    opts.row_done(&rows_done, dim[1]);
+                }
             }
+            rays.fetch_add(n_rays, SeqCst);
+        });
+        stats.trace = opts.now() - start;
+        trace::emit(trace::Event::End(trace::Phase::Render));
+        stats.rays = rays.into_inner();
+        stats
+    }
+
+    /// Like [`Renderer::render_rows`], but instead of averaging the samples
+    /// into pixels, hands every camera ray to `f` along with the pixel and the
+    /// radiance seen along the ray, in linear RGB before tonemapping.
+    pub fn for_each_sample(
+        &self,
+        in_parallel: &ThreadPool<'_>,
+        dim: rgb::Idx,
+        f: &(dyn Fn(rgb::Idx, &Ray, Color) + Sync),
+    ) -> Stats {
+        let opts = self.opts;
+        let mut stats = self.stats;
+        let frame = self.frame(dim);
+
+        trace::emit(trace::Event::Start(trace::Phase::Render));
+        let start = opts.now();
+        let rays = AtomicU64::new(0);
+        let band_height = rgb::band_height(dim[1], opts.chunk);
+        let n_bands = dim[1].div_ceil(band_height) as usize;
+        let bands = rgb::Schedule::new(opts.order, n_bands, &|band| frame.cost(band, band_height));
+        let rows_done = AtomicU32::new(0);
+        in_parallel(&|| {
+            let mut n_rays = 0;
+            'bands: while let Some(band) = bands.next() {
+                let y0 = band as u32 * band_height;
+                for y in y0..(y0 + band_height).min(dim[1]) {
+                    if opts.cancelled() {
+                        break 'bands;
+                    }
+                    for x in 0..dim[0] {
+                        if !opts.skipped(dim[0], [x, y]) {
+                            frame.samples([x, y], &mut n_rays, |ray, color| f([x, y], ray, color));
+                        }
+                    }
+                    opts.row_done(&rows_done, dim[1]);
+                }
+            }
+            rays.fetch_add(n_rays, SeqCst);
+        });
+        stats.trace = opts.now() - start;
+        trace::emit(trace::Event::End(trace::Phase::Render));
+        stats.rays = rays.into_inner();
+        stats
+    }
+
+    /// The radiance seen along an arbitrary `ray`, as the integrator computes
+    /// it for camera rays, for hosts which cast rays of their own. Counts the
+    /// rays cast in `rays`.
+    ///
+    /// Random choices, like the directions of ambient occlusion rays, are
+    /// drawn from `rng`: averaging several samples of a ray needs a generator
+    /// per sample, like `Rng::new(seed, pixel, sample)` does for the renders.
+    pub fn trace_ray(&self, ray: &Ray, rng: &mut Rng, rays: &mut u64) -> Color {
+        let settings = self.settings();
+        let bias = render::Bias::new(&settings, self.extent);
+        render::render(&self.scene, &self.backend, &settings, bias, ray, rng, rays)
+    }
+
+    /// Maps radiance from [`Renderer::trace_ray`] or
+    /// [`Renderer::for_each_sample`] to a pixel color, like the renders do.
+    pub fn tonemap(&self, color: Color) -> Color {
+        tonemap(&self.settings(), color)
+    }
+
+    /// Lights the corners of the faces of the `mesh`-th mesh, for exporting
+    /// the lighting to be shown without ray tracing, writing the colors of
+    /// the corners of face `i` to `out[i]`.
+    ///
+    /// The colors are those of [`Integrator::Whitted`] without the reflections
+    /// and highlights, which depend on the view, tonemapped like a render.
+    pub fn bake(&self, mesh: usize, out: &mut [[Color; 3]]) -> Stats {
+        let opts = self.opts;
+        let mut stats = self.stats;
+        let settings = self.settings();
+        let bias = render::Bias::new(&settings, self.extent);
+        let (scene, backend) = (&self.scene, &self.backend);
+        let mesh = &scene.meshes[mesh];
+        assert_eq!(out.len(), mesh.f.len());
+
+        let start = opts.now();
+        for (idx, colors) in out.iter_mut().enumerate() {
+            let t = mesh.triangle(idx);
+            *colors = [0, 1, 2].map(|i| {
+                let mut n = t.n[i].to_unit();
+                // There is no ray to face, so the lit side is baked, the back
+                // if all lights are behind the surface.
+                let behind = |light: &Light<'_>| dot(light.ray_from(t.v[i]).0.dir(), n) < 0.0;
+                if mesh.material.double_sided
+                    && !scene.lights.is_empty()
+                    && scene.lights.iter().all(behind)
+                {
+                    n = -n;
+                }
+                let p = t.v[i] + n * bias.at(0.0);
+                let color =
+                    render::shade(scene, backend, &mesh.material, p, 0.0, n, None, &mut stats.rays);
+                tonemap(&settings, color + mesh.material.emission)
+            });
         }
-    });
+        stats.trace = opts.now() - start;
+        stats
+    }
 
-    Ok(())
+    /// Starts a render of `dim` pixels to be advanced with
+    /// [`Renderer::render_step`].
+    pub fn job(&self, dim: rgb::Idx) -> Job {
+        let frame = self.frame(dim);
+        let band_height = rgb::band_height(dim[1], self.opts.chunk);
+        let n_bands = dim[1].div_ceil(band_height) as usize;
+        let bands =
+            rgb::Schedule::new(self.opts.order, n_bands, &|band| frame.cost(band, band_height));
+        Job { dim, band_height, bands, rows_done: 0, stats: self.stats }
+    }
+
+    /// Renders the next band of rows of `job` on the current thread, handing
+    /// the spans to `sink` like [`render_rows`] does.
+    ///
+    /// A host with a single thread, like an event loop, calls this between
+    /// handling events until it returns [`Progress::Done`].
+    pub fn render_step(
+        &self,
+        job: &mut Job,
+        sink: &mut dyn FnMut(rgb::Span<'_, Color>),
+    ) -> Progress {
+        let opts = self.opts;
+        let dim = job.dim;
+        let band = if opts.cancelled() { None } else { job.bands.next() };
+        let Some(band) = band else {
+            return Progress::Done(job.stats);
+        };
+        let frame = self.frame(dim);
+        let start = opts.now();
+        let mut span = [Color::default(); SPAN];
+        let y0 = band as u32 * job.band_height;
+        for y in y0..(y0 + job.band_height).min(dim[1]) {
+            frame.row(opts, y, &mut span, &mut job.stats.rays, sink);
+            job.rows_done += 1;
+        }
+        job.stats.trace += opts.now() - start;
+        Progress::Rows { done: job.rows_done, total: dim[1] }
+    }
+}
+
+/// A render in progress, see [`Renderer::job`].
+pub struct Job {
+    dim: rgb::Idx,
+    band_height: u32,
+    bands: rgb::Schedule,
+    rows_done: u32,
+    stats: Stats,
 }
 
-fn triangle_bounding_box(t: Triangle) -> BoundingBox {
-    BoundingBox::from_points(&t.v)
+/// What is left after a [`Renderer::render_step`].
+#[derive(Clone, Copy, Debug)]
+pub enum Progress {
+    /// `done` of `total` rows are rendered, there are more steps to go.
+    Rows { done: u32, total: u32 },
+    /// All rows are rendered, or [`Options::cancel`] asked to stop.
+    Done(Stats),
 }
 
-fn to_scree_space(res: [u32; 2], idx: rgb::Idx) -> [f64; 2] {
-    let f = |d: u32, x: u32| {
+/// Rows are rendered and handed out in spans this wide, so that spans never
+/// straddle tiles.
+const SPAN: usize = Tiles::SIZE as usize;
+
+/// Upper bound on the `mem` [`render`] needs for the scene, computed without
+/// parsing it.
+pub fn mem_estimate(crt: &str) -> usize {
+    let (mut n_meshes, mut n_curves) = (0, 0);
+    let mut meshes = BvhsEstimate::default();
+    let mut curves = BvhsEstimate::default();
+    let scene = Scene::mem_estimate(
+        crt,
+        &mut |n| {
+            n_meshes += 1;
+            meshes.add(n);
+        },
+        &mut |n| {
+            n_curves += 1;
+            curves.add(n);
+        },
+    );
+    // Then the top-level BVH over the meshes, then the BVHs of the curves.
+    meshes.add(n_meshes);
+    let peak = meshes.peak.max(meshes.total + curves.peak);
+    let arrays = (n_meshes + n_curves) * size_of::<Bvh<'_>>() + 2 * align_of::<Bvh<'_>>();
+    scene + arrays + peak.max(meshes.total + curves.total)
+}
+
+/// Memory for BVHs built one after another, see [`mem_estimate`].
+#[derive(Default)]
+struct BvhsEstimate {
+    /// All the built BVHs.
+    total: usize,
+    /// The most memory in use while building any of them.
+    peak: usize,
+}
+
+impl BvhsEstimate {
+    fn add(&mut self, n_faces: usize) {
+        let (bvh, scratch) = Bvh::mem_estimate(n_faces);
+        // `Bvh::build` gives half of the free memory to the scratch space.
+        self.peak = self.peak.max(self.total + 2 * bvh.max(scratch));
+        self.total += bvh;
+    }
+}
+
+/// The largest magnitude of a coordinate of the bounded objects and the
+/// camera, for [`RenderSettings::shadow_bias`].
+fn extent(scene: &Scene<'_>) -> f64 {
+    let mut points = [scene.camera.pos, v64::ZERO, v64::ZERO];
+    if let Some(bounds) = scene.stats().bounds {
+        points[1..].copy_from_slice(&bounds);
+    }
+    let lights = scene.lights.iter().filter(|it| matches!(it.kind, LightKind::Point));
+    let lights = lights.map(|it| it.pos);
+    points.into_iter().chain(lights).flat_map(|it| it.xyz()).fold(0.0, |acc, it| acc.max(it.abs()))
+}
+
+fn prepare<'a, 'm, B: Backend>(
+    crt: &'a str,
+    mem: &mut Mem<'m>,
+    opts: &Options<'_>,
+    stats: &mut Stats,
+) -> Result<(Scene<'m>, B::Built<'m>), Error<'a>> {
+    let start = opts.now();
+    let blobs = opts.blobs.unwrap_or(&|_, _| ());
+    let mut scene = Scene::parse_with_blobs(mem, crt, blobs).map_err(ErrorRepr::ParseSceneError)?;
+    if let Some(groups) = opts.lights {
+        for light in scene.lights.iter_mut() {
+            if !light.group.is_empty() && !groups.split(',').any(|it| it == light.group) {
+                light.color = Color::default();
+            }
+        }
+    }
+    if let Some(patterns) = opts.objects {
+        scene.retain_objects(&|name| shown(patterns, name));
+    }
+    let parsed = opts.now();
+    stats.parse = parsed - start;
+    trace::emit(trace::Event::Start(trace::Phase::BvhBuild));
+    let backend = B::build(mem, &scene).map_err(ErrorRepr::BhvConstructionError);
+    trace::emit(trace::Event::End(trace::Phase::BvhBuild));
+    let backend = backend?;
+    stats.bvh_build = opts.now() - parsed;
+    if opts.validate_accel {
+        let free = mem.free();
+        mem.with_scratch(free, |_, scratch| backend.validate(scratch, &scene))
+            .map_err(ErrorRepr::InvalidAccel)?;
+    }
+    Ok((scene, backend))
+}
+
+/// Whether the object called `name` is rendered with the `patterns` of
+/// [`Options::objects`].
+fn shown(patterns: &str, name: &str) -> bool {
+    let mut included = false;
+    let mut any_included = false;
+    for pattern in patterns.split(',') {
+        match pattern.strip_prefix('-') {
+            Some(pattern) if glob(pattern, name) => return false,
+            Some(_) => (),
+            None => {
+                any_included = true;
+                included |= glob(pattern, name);
+            }
+        }
+    }
+    included || !any_included
+}
+
+/// Whether `s` matches `pattern`, where `*` stands for any number of bytes.
+fn glob(pattern: &str, s: &str) -> bool {
+    let (pattern, s) = (pattern.as_bytes(), s.as_bytes());
+    let (mut p, mut i) = (0, 0);
+    // Where the last `*` was, and where in `s` its match currently ends.
+    let mut star = None;
+    while i < s.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, i));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == s[i] {
+            p += 1;
+            i += 1;
+        } else if let Some((star_p, star_i)) = star {
+            // Lets the `*` match one more byte and tries again.
+            star = Some((star_p, star_i + 1));
+            p = star_p + 1;
+            i = star_i + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&it| it == b'*')
+}
+
+/// Everything needed to compute the color of a single pixel.
+struct Frame<'f, 'm, B> {
+    scene: &'f Scene<'m>,
+    backend: &'f B,
+    camera: Camera,
+    dim: rgb::Idx,
+    dither: rgb::Dither,
+    settings: RenderSettings,
+    bias: render::Bias,
+}
+
+impl<'f, 'm, B: Intersect<'m>> Frame<'f, 'm, B> {
+    fn new(
+        scene: &'f Scene<'m>,
+        backend: &'f B,
+        settings: RenderSettings,
+        dither: rgb::Dither,
+        extent: f64,
+        dim: rgb::Idx,
+    ) -> Frame<'f, 'm, B> {
+        let camera = Camera::new(&scene.camera);
+        let bias = render::Bias::new(&settings, extent);
+        Frame { scene, backend, camera, dim, dither, settings, bias }
+    }
+
+    /// Renders row `y` in [`SPAN`]-wide pieces, leaving out the tiles which
+    /// are not ours.
+    fn row(
+        &self,
+        opts: &Options<'_>,
+        y: u32,
+        span: &mut [Color; SPAN],
+        rays: &mut u64,
+        sink: &mut dyn FnMut(rgb::Span<'_, Color>),
+    ) {
+        let width = self.dim[0];
+        for x0 in (0..width).step_by(SPAN) {
+            let n = (width - x0).min(SPAN as u32);
+            let skipped = opts.skipped(width, [x0, y]);
+            for (i, pixel) in span[..n as usize].iter_mut().enumerate() {
+                *pixel = if skipped {
+                    Color::default()
+                } else {
+                    self.radiance([x0 + i as u32, y], rays)
+                };
+            }
+            sink(rgb::Span { y, x: x0, pixels: &span[..n as usize] });
+        }
+    }
+
+    /// Roughly how long the `band`-th band of rows takes to render: BVH nodes
+    /// visited by camera rays spread along the middle row.
+    fn cost(&self, band: usize, band_height: u32) -> u32 {
+        const PROBES: u32 = 8;
+        let y = (band as u32 * band_height + band_height / 2).min(self.dim[1] - 1);
+        let mut visited = 0;
+        for i in 0..PROBES {
+            let x = self.dim[0] as f64 * (2 * i + 1) as f64 / (2 * PROBES) as f64;
+            let [dx, dy] = to_scree_space(self.dim, [x, y as f64 + 0.5]);
+            let ray = self.camera.cast(dx, dy);
+            let kind = RayKind::Camera;
+            self.backend.closest_hit_counted(self.scene, &ray, f64::INFINITY, kind, &mut visited);
+        }
+        visited
+    }
+
+    fn pixel(&self, idx: rgb::Idx, rays: &mut u64) -> rgb::Color {
+        rgb::quantize(&self.radiance(idx, rays), idx, self.dither)
+    }
+
+    /// Averages the samples of the pixel. A pure function of the pixel index,
+    /// see [`rgb::Schedule`] for why.
+    fn radiance(&self, [x, y]: rgb::Idx, rays: &mut u64) -> Color {
+        let n = self.settings.samples;
+        let mut acc = Color::default();
+        self.samples([x, y], rays, |_, color| acc = acc + color);
+        let mut color = acc / n as f64;
+        if self.settings.integrator == Integrator::FalseColor {
+            // Post-processing would shift the colors of the ramp, which
+            // already takes the exposure into account.
+            return color;
+        }
+        let vignette = self.settings.vignette;
+        if vignette > 0.0 {
+            // Squared distance from the center, one in the corners.
+            let [dx, dy] = to_scree_space(self.dim, [x as f64 + 0.5, y as f64 + 0.5]);
+            color = color * (1.0 - vignette * (dx * dx + dy * dy) / 2.0).max(0.0);
+        }
+        color = self.tonemap(color);
+        let grain = self.settings.grain;
+        if grain > 0.0 {
+            // A stream of its own, so that the grain doesn't follow the
+            // sample positions.
+            let noise = grain * (2.0 * Rng::new(!self.settings.seed, [x, y], 0).next_f64() - 1.0);
+            color = color * (1.0 + noise);
+        }
+        color
+    }
+
+    /// Traces the camera rays of the samples of the pixel, handing each ray
+    /// and its radiance to `f`.
+    fn samples(&self, [x, y]: rgb::Idx, rays: &mut u64, mut f: impl FnMut(&Ray, Color)) {
+        let n = self.settings.samples;
+        // A single sample stays at the pixel corner, so that non-antialiased
+        // images don't depend on the seed.
+        let shift = if n == 1 { [0.0; 2] } else { pixel_shift(self.settings.seed, [x, y]) };
+        for i in 0..n {
+            let [ox, oy] = sample_offset(i, shift);
+            let [dx, dy] = to_scree_space(self.dim, [x as f64 + ox, y as f64 + oy]);
+            let mut rng = Rng::new(self.settings.seed, [x, y], i);
+            let mut ray = self.camera.cast_through_lens(dx, dy, &mut rng);
+            if self.camera.shutter > 0.0 {
+                // A random time in the `i`-th of equal parts of the shutter
+                // interval, so that the samples cover all of it.
+                let time = (f64::from(i) + rng.next_f64()) / f64::from(n);
+                ray = ray.at_time(time * self.camera.shutter);
+            }
+            let (scene, backend, settings) = (self.scene, self.backend, &self.settings);
+            let color = render::render(scene, backend, settings, self.bias, &ray, &mut rng, rays);
+            f(&ray, color);
+        }
+    }
+
+    fn tonemap(&self, color: Color) -> Color {
+        tonemap(&self.settings, color)
+    }
+}
+
+/// The exposure which brings the log-average luminance of the scene to middle
+/// gray, plus [`RenderSettings::exposure`], judged from the radiance of a
+/// coarse grid of camera rays. Scenes which are black or blow up to infinity
+/// keep the exposure as is.
+fn auto_exposure<'m>(
+    scene: &Scene<'m>,
+    backend: &impl Intersect<'m>,
+    settings: &RenderSettings,
+    extent: f64,
+) -> f64 {
+    const PROBES: u32 = 32;
+    const MIDDLE_GRAY: f64 = 0.18;
+    let camera = Camera::new(&scene.camera);
+    let bias = render::Bias::new(settings, extent);
+    let mut histogram = rgb::Histogram::default();
+    let mut row = [Color::default(); PROBES as usize];
+    let mut rays = 0;
+    for y in 0..PROBES {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let [dx, dy] = to_scree_space([PROBES; 2], [x as f64 + 0.5, y as f64 + 0.5]);
+            let mut rng = Rng::new(settings.seed, [x as u32, y], 0);
+            let ray = camera.cast(dx, dy);
+            *pixel = render::render(scene, backend, settings, bias, &ray, &mut rng, &mut rays);
+        }
+        histogram.add(&row);
+    }
+    let average = histogram.log_average();
+    if !(average.is_finite() && average > 0.0) {
+        return settings.exposure;
+    }
+    settings.exposure + render::log2(MIDDLE_GRAY / average)
+}
+
+fn tonemap(settings: &RenderSettings, color: Color) -> Color {
+    let RenderSettings { exposure, temperature, tint, gamma, .. } = *settings;
+    let stops = |it: f64| if it == 0.0 { 1.0 } else { geom::powf(2.0, it).min(f64::MAX) };
+    let gains = [
+        exposure + temperature / 2.0 + tint / 4.0,
+        exposure - tint / 4.0,
+        exposure - temperature / 2.0 + tint / 4.0,
+    ]
+    .map(stops);
+    let f = |value: f64, gain: f64| {
+        // Saturates rather than overflows, Reinhard turns infinity into NaN.
+        let value = (value * gain).min(f64::MAX);
+        let value = match settings.tonemap {
+            Tonemap::None => value,
+            Tonemap::Reinhard => value / (1.0 + value),
+        };
+        if gamma == 1.0 {
+            value
+        } else {
+            geom::powf(value, 1.0 / gamma)
+        }
+    };
+    Color::new(f(color.r, gains[0]), f(color.g, gains[1]), f(color.b, gains[2]))
+}
+
+/// Position of the `i`-th sample within a pixel, from the R2 low-discrepancy
+/// sequence, cyclically shifted by `shift`.
+fn sample_offset(i: u32, [sx, sy]: [f64; 2]) -> [f64; 2] {
+    const A1: f64 = 0.7548776662466927;
+    const A2: f64 = 0.5698402909980532;
+    let frac = |x: f64| x - x as u64 as f64;
+    [frac(sx + i as f64 * A1), frac(sy + i as f64 * A2)]
+}
+
+/// Per-pixel random shift of the sample pattern, which turns aliasing into
+/// noise. Depends only on the seed and the pixel, never on thread scheduling.
+fn pixel_shift(seed: u64, [x, y]: rgb::Idx) -> [f64; 2] {
+    let h = splitmix64(seed ^ (u64::from(y) << 32 | u64::from(x)));
+    let unit = |bits: u64| (bits >> 11) as f64 / (1u64 << 53) as f64;
+    [unit(h), unit(splitmix64(h))]
+}
+
+fn to_scree_space(res: [u32; 2], idx: [f64; 2]) -> [f64; 2] {
+    let f = |d: u32, x: f64| {
         let d = d as f64;
-        let x = x as f64;
         (2.0 * x - d) / d
     };
     [f(res[0], idx[0]), -f(res[1], idx[1])]
 }
 
-fn to_rgb(color: &Color) -> rgb::Color {
-    fn f(value: f64) -> u8 {
-        (value * 255.0).clamp(0.0, 255.0).round() as u8
-    }
-    rgb::Color { r: f(color.r), g: f(color.g), b: f(color.b) }
-}
-
 pub(crate) struct Camera {
     pos: v64,
     center: v64,
     dx: v64,
     dy: v64,
+    /// Unit vector along the gaze.
+    gaze: v64,
+    /// Half of the aperture along `dx` and `dy`, zero for a pinhole.
+    lens: [v64; 2],
+    focal_distance: f64,
+    shutter: f64,
 }
 
 impl Camera {
+    /// Degenerate cameras, which the parser warns about, look along `z` if
+    /// `pos` is the same as `look_at`, and pick another `up` if it's zero or
+    /// parallel to the gaze, rather than cast NaN rays.
     pub(crate) fn new(cfg: &scene::Camera) -> Camera {
-        let gaze = Ray::from_to(cfg.pos, cfg.look_at);
+        let mut gaze = Ray::from_to(cfg.pos, cfg.look_at);
+        if !is_finite(gaze.dir()) {
+            gaze = Ray::new(cfg.pos, v64(0.0, 0.0, 1.0));
+        }
         let center = gaze.at(cfg.focus);
-        let right = cross(gaze.dir(), cfg.up).to_unit();
+        let mut right = cross(gaze.dir(), cfg.up).to_unit();
+        if !is_finite(right) {
+            // The axis furthest from the gaze.
+            let [x, y, z] = gaze.dir().xyz().map(f64::abs);
+            let up = if y <= x && y <= z {
+                v64(0.0, -1.0, 0.0)
+            } else if x <= z {
+                v64(1.0, 0.0, 0.0)
+            } else {
+                v64(0.0, 0.0, 1.0)
+            };
+            right = cross(gaze.dir(), up).to_unit();
+        }
         let up = cross(right, gaze.dir()).to_unit();
         let dx = right * cfg.width / 2.0;
         let dy = -up * cfg.height / 2.0;
-        Camera { pos: cfg.pos, center, dx, dy }
+        let radius = cfg.aperture / 2.0;
+        let lens = [right * radius, -up * radius];
+        let focal_distance = cfg.focal_distance.unwrap_or((cfg.look_at - cfg.pos).norm());
+        let shutter = cfg.shutter;
+        Camera { pos: cfg.pos, center, dx, dy, gaze: gaze.dir(), lens, focal_distance, shutter }
     }
 
     pub(crate) fn cast(&self, dx: f64, dy: f64) -> Ray {
         let to = self.center + self.dx * dx + self.dy * dy;
         Ray::from_to(self.pos, to)
     }
+
+    /// Like [`Camera::cast`], but from a random point of the lens, towards
+    /// where the pinhole ray crosses the plane in focus.
+    fn cast_through_lens(&self, dx: f64, dy: f64, rng: &mut Rng) -> Ray {
+        let ray = self.cast(dx, dy);
+        if self.lens == [v64::ZERO; 2] {
+            return ray;
+        }
+        // Degenerate cameras stay pinholes.
+        let t = self.focal_distance / dot(ray.dir(), self.gaze);
+        if !(t.is_finite() && t > 0.0) {
+            return ray;
+        }
+        let [u, v] = rng.in_unit_disk();
+        let from = self.pos + self.lens[0] * u + self.lens[1] * v;
+        let res = Ray::from_to(from, ray.at(t));
+        if is_finite(res.dir()) {
+            res
+        } else {
+            ray
+        }
+    }
+}
+
+fn is_finite(v: v64) -> bool {
+    v.xyz().iter().all(|it| it.is_finite())
+}
+
+#[test]
+fn test_degenerate_camera() {
+    let cfg = scene::Camera {
+        pos: v64(0.0, 0.0, -10.0),
+        look_at: v64::ZERO,
+        up: v64(0.0, -1.0, 0.0),
+        focus: 1.0,
+        width: 4.0,
+        height: 3.0,
+        ..Default::default()
+    };
+    let corners = |cfg: &scene::Camera| {
+        let camera = Camera::new(cfg);
+        [[-1.0, -1.0], [1.0, 1.0], [0.0, 0.0]].map(|[dx, dy]| camera.cast(dx, dy).dir())
+    };
+    let expected = corners(&cfg);
+    for up in [v64::ZERO, v64(0.0, 0.0, 1.0), v64(0.0, 0.0, -2.0)] {
+        let rays = corners(&scene::Camera { up, ..cfg });
+        assert!(rays.iter().all(|&it| is_finite(it)));
+        assert!(rays[2] == expected[2]);
+    }
+    let rays = corners(&scene::Camera { look_at: cfg.pos, ..cfg });
+    assert!(rays.iter().all(|&it| is_finite(it)));
+    assert!(rays == expected);
+}
+
+#[test]
+fn test_objects() {
+    assert!(glob("*", ""));
+    assert!(glob("wall*", "wall_left"));
+    assert!(glob("*_l*t", "wall_left"));
+    assert!(!glob("wall", "wall_left"));
+    assert!(!glob("*_right", "wall_left"));
+
+    assert!(shown("-floor", ""));
+    assert!(shown("-floor", "teapot"));
+    assert!(!shown("-floor", "floor"));
+    assert!(shown("tea*,-*cup", "teapot"));
+    assert!(!shown("tea*,-*cup", "teacup"));
+    assert!(!shown("tea*", ""));
+}
+
+#[test]
+fn test_sample_offset() {
+    assert_eq!(sample_offset(0, [0.0; 2]), [0.0; 2]);
+    for seed in 0..4 {
+        let shift = pixel_shift(seed, [seed as u32, 7]);
+        let offsets = [0, 1, 2, 3, 4, 5, 6, 7].map(|i| sample_offset(i, shift));
+        for (i, a) in offsets.iter().enumerate() {
+            assert!(a.iter().all(|it| (0.0..1.0).contains(it)));
+            assert!(offsets[..i].iter().all(|b| a != b));
+        }
+    }
+}
+
+#[test]
+fn test_mirror() {
+    let crt = concat!(
+        "background #808080\n",
+        "plane { pos 0,0,1 normal 0,0,-1 material { reflectance 0.5 } }\n",
+    );
+    let mut mem = [0; 1 << 16];
+    let ray = Ray::new(v64::ZERO, v64(0.0, 0.0, 1.0));
+    // The black mirror shows half of the background behind the camera, when
+    // there are bounces left for the reflection. That costs a ray on top of
+    // the camera ray, there are no lights to cast shadow rays to.
+    for (bounces, share, n_rays) in [(0, 0.0, 1), (3, 0.5, 2)] {
+        let opts = Options { bounces: Some(bounces), ..Options::default() };
+        let (color, background, rays) = with_renderer(crt, &mut mem, &opts, |r| {
+            let mut rays = 0;
+            (r.trace_ray(&ray, &mut Rng::new(0, [0, 0], 0), &mut rays), r.scene().background, rays)
+        })
+        .unwrap();
+        assert_eq!(color.g, background.g * share);
+        assert_eq!(rays, n_rays);
+    }
+}
+
+#[test]
+fn test_stacked_shadow_catchers() {
+    macro_rules! catcher {
+        ($z:literal) => {
+            concat!("plane { pos 0,0,", $z, " normal 0,0,-1 material { shadow_catcher true } }\n")
+        };
+    }
+    let crt = concat!(
+        "background #808080\n",
+        "settings { bounces 4 }\n",
+        catcher!(1),
+        catcher!(2),
+        catcher!(3),
+        catcher!(4),
+        catcher!(5),
+        catcher!(6),
+        catcher!(7),
+        catcher!(8),
+    );
+    let mut mem = [0; 1 << 16];
+    let ray = Ray::new(v64::ZERO, v64(0.0, 0.0, 1.0));
+    let (color, background, rays) = with_renderer(crt, &mut mem, &Options::default(), |r| {
+        let mut rays = 0;
+        (r.trace_ray(&ray, &mut Rng::new(0, [0, 0], 0), &mut rays), r.scene().background, rays)
+    })
+    .unwrap();
+    assert_eq!([color.r, color.g, color.b], [background.r, background.g, background.b]);
+    // Without lights, a ray per catcher: the first one, and one for each of
+    // the bounces.
+    assert_eq!(rays, 5);
+}
+
+#[test]
+fn test_trace_ray_samples() {
+    // The wall at the origin of the AO rays blocks half of the sky.
+    let crt = concat!(
+        "settings { integrator ao ao_rays 1 }\n",
+        "plane { pos 0,0,1 normal 0,0,-1 }\n",
+        "plane { pos 0.1,0,0 normal -1,0,0 }\n",
+    );
+    let mut mem = [0; 1 << 16];
+    let ray = Ray::new(v64::ZERO, v64(0.0, 0.0, 1.0));
+    let grays = with_renderer(crt, &mut mem, &Options::default(), |r| {
+        (0..64).map(|i| r.trace_ray(&ray, &mut Rng::new(1, [0, 0], i), &mut 0).g).sum::<f64>()
+    })
+    .unwrap();
+    assert!(16.0 < grays && grays < 48.0, "{grays}");
+}
+
+#[test]
+fn test_light_groups() {
+    let crt = concat!(
+        "plane { pos 0,0,1 normal 0,0,-1 }\n",
+        "light { pos 0,0,0 color #FFFFFF group key }\n",
+        "light { pos 0,0,0 color #FFFFFF group fill }\n",
+    );
+    let mut mem = [0; 1 << 16];
+    let ray = Ray::new(v64::ZERO, v64(0.0, 0.0, 1.0));
+    // A shadow ray per light which is on, on top of the camera ray.
+    for (lights, n_rays) in [(None, 3), (Some("key"), 2), (Some(""), 1)] {
+        let opts = Options { lights, ..Options::default() };
+        let rays = with_renderer(crt, &mut mem, &opts, |r| {
+            let mut rays = 0;
+            r.trace_ray(&ray, &mut Rng::new(0, [0, 0], 0), &mut rays);
+            rays
+        })
+        .unwrap();
+        assert_eq!(rays, n_rays);
+    }
+}
+
+#[test]
+fn test_any_hit_stream() {
+    let crt = concat!(
+        "sphere { pos 2,0,0 radius 1 }\n",
+        "plane { pos 0,-3,0 normal 0,1,0 }\n",
+        "clip { pos 0,0,2 normal 0,0,1 }\n",
+        "mesh { translate -2,0,0 data {\n",
+        "v -1,-1,0\nv 1,-1,0\nv 0,1,0\nv 0,0,3\nvn 0,0,1\n",
+        "f 1/1 2/1 3/1\nf 1/1 2/1 4/1\n",
+        "} }\n",
+        "curves { radius 0.2 data { c 0,2,-1 1,3,0 -1,3,1 0,2,2 } }\n",
+    );
+    let mut mem = [0; 1 << 16];
+    with_renderer(crt, &mut mem, &Options::default(), |r| {
+        let mut rng = Rng::new(0, [0, 0], 0);
+        for _ in 0..100 {
+            let batch: [Ray; 16] = core::array::from_fn(|_| {
+                Ray::from_to(rng.in_unit_ball() * 6.0, rng.in_unit_ball() * 2.0)
+            });
+            let mut max_t = [(); 16].map(|()| 8.0 * rng.next_f64());
+            let expected: [bool; 16] = core::array::from_fn(|i| {
+                r.backend.any_hit(&r.scene, &batch[i], max_t[i], RayKind::Shadow)
+            });
+            let mut hit = [false; 16];
+            r.backend.any_hit_stream(&r.scene, &batch, &mut max_t, RayKind::Shadow, &mut hit);
+            assert_eq!(hit, expected);
+        }
+    })
+    .unwrap();
 }
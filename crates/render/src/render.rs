@@ -1,162 +1,458 @@
-use core::iter;
+use geom::{dot, v64, Ray, Rng};
+use scene::{Color, Integrator, Material, RenderSettings, Scene};
 
-use bvh::Bvh;
-use geom::{cross, dot, v64, Ray};
-use scene::{Color, Material, Mesh, Plane, Scene, Sphere, Triangle};
+use crate::backend::{Hit, Intersect, RayKind};
 
-pub(crate) fn render(scene: &Scene, bvhs: &[Bvh<'_>], ray: &Ray) -> Color {
-    let mut res = scene.background;
-    if let Some(i) = intersect(&scene, bvhs, &ray) {
-        let ambient_color = i.material.color;
-        res = ambient_color;
-
-        let mut p = ray.at(i.t);
-        p = p + i.n * 0.0001;
+/// How far off the surface secondary rays start, see
+/// [`RenderSettings::shadow_bias`].
+#[derive(Clone, Copy)]
+pub(crate) struct Bias {
+    fixed: Option<f64>,
+    /// The largest coordinate in the scene.
+    extent: f64,
+}
 
-        let lr = Ray::from_to(p, scene.light.pos);
+impl Bias {
+    pub(crate) fn new(settings: &RenderSettings, extent: f64) -> Bias {
+        Bias { fixed: settings.shadow_bias, extent }
+    }
 
-        let obscured = match intersect(&scene, bvhs, &lr) {
-            None => false,
-            Some(i) => i.t.powi(2) < (scene.light.pos - p).norm_squared(),
-        };
+    /// The bias at a point `t` along a ray.
+    pub(crate) fn at(self, t: f64) -> f64 {
+        // The rounding error of a hit point grows with its coordinates, which
+        // are within the extent, and with the distance along the ray. The
+        // factor leaves ample room for the error of the intersection tests.
+        self.fixed.unwrap_or(1e-6 * (self.extent + t))
+    }
+}
 
-        if !obscured {
-            let k = (dot(lr.dir(), i.n)).max(0.0) * i.material.diffuse;
-            let diffuse_color = i.material.color * scene.light.color * k;
-            res = res + diffuse_color;
+/// Computes the color seen along `ray`, counting the rays cast in `rays`.
+pub(crate) fn render<'m>(
+    scene: &Scene<'m>,
+    backend: &impl Intersect<'m>,
+    settings: &RenderSettings,
+    bias: Bias,
+    ray: &Ray,
+    rng: &mut Rng,
+    rays: &mut u64,
+) -> Color {
+    match settings.integrator {
+        Integrator::Whitted => {
+            whitted(scene, backend, settings, bias, ray, settings.bounces, 1.0, rng, rays)
+        }
+        Integrator::Path => path(scene, backend, settings, bias, ray, rng, rays),
+        Integrator::Normals => match intersect(scene, backend, ray, RayKind::Camera, rays) {
+            None => scene.background,
+            Some(i) => {
+                let [x, y, z] = i.n.xyz().map(|it| ((it + 1.0) / 2.0).max(0.0));
+                Color::new(x, y, z)
+            }
+        },
+        Integrator::Depth => match intersect(scene, backend, ray, RayKind::Camera, rays) {
+            None => scene.background,
+            Some(i) => {
+                let scale = (scene.camera.look_at - scene.camera.pos).norm().max(f64::MIN_POSITIVE);
+                let gray = scale / (scale + i.t);
+                Color::new(gray, gray, gray)
+            }
+        },
+        Integrator::Occlusion => match intersect(scene, backend, ray, RayKind::Camera, rays) {
+            None => scene.background,
+            Some(i) => {
+                let gray = 1.0 - occlusion(scene, backend, settings, bias, ray, &i, rng, rays);
+                Color::new(gray, gray, gray)
+            }
+        },
+        Integrator::BvhHeatmap => {
+            *rays += 1;
+            let mut visited = 0;
+            backend.closest_hit_counted(scene, ray, f64::INFINITY, RayKind::Camera, &mut visited);
+            heat(geom::sqrt((f64::from(visited) / 256.0).min(1.0)))
+        }
+        Integrator::Wireframe => match intersect(scene, backend, ray, RayKind::Camera, rays) {
+            None => scene.background,
+            Some(i) => {
+                let on_edge = i.barycentric.is_some_and(|it| it.iter().any(|&w| w < 0.02));
+                // `max` also maps the NaN of degenerate normals to zero.
+                let facing = dot(i.n, ray.dir()).abs().max(0.0);
+                let gray = if on_edge { 1.0 } else { 0.1 + 0.3 * facing };
+                Color::new(gray, gray, gray)
+            }
+        },
+        Integrator::FalseColor => {
+            let color =
+                whitted(scene, backend, settings, bias, ray, settings.bounces, 1.0, rng, rays);
+            false_color(color, settings.exposure)
         }
     }
-    res
 }
 
-struct Intersection<'a> {
-    t: f64,
-    n: v64,
-    material: &'a Material,
+/// The fraction of the rays from hit `i` towards the sky which something
+/// blocks within [`RenderSettings::ao_distance`].
+#[allow(clippy::too_many_arguments)]
+fn occlusion<'m>(
+    scene: &Scene<'m>,
+    backend: &impl Intersect<'m>,
+    settings: &RenderSettings,
+    bias: Bias,
+    ray: &Ray,
+    i: &Hit<'_>,
+    rng: &mut Rng,
+    rays: &mut u64,
+) -> f64 {
+    // Both sides of the surface count, whichever is seen.
+    let n = if dot(i.n, ray.dir()) > 0.0 { -i.n } else { i.n };
+    let p = ray.at(i.t) + n * bias.at(i.t);
+    let max_t = settings.ao_distance.unwrap_or(f64::INFINITY);
+    let ao_rays = (0..settings.ao_rays).map(|_| {
+        // With the odds of the cosine, as for diffuse bounces of paths.
+        let dir = n + rng.in_unit_ball().to_unit();
+        let dir = if dot(dir, n) > 0.0 { dir } else { n };
+        ((), Ray::new(p, dir).at_time(ray.time()), max_t)
+    });
+    let mut blocked = 0;
+    shadow_stream(scene, backend, ao_rays, rays, |(), _, hit| blocked += u32::from(hit));
+    f64::from(blocked) / f64::from(settings.ao_rays.max(1))
 }
 
-fn intersect<'a>(scene: &'a Scene, bvhs: &'a [Bvh<'_>], ray: &Ray) -> Option<Intersection<'a>> {
-    let mut res: Option<Intersection> = None;
-    intersect_with(ray, &mut res, &*scene.spheres);
-    intersect_with(ray, &mut res, &*scene.planes);
-    intersect_with(ray, &mut res, iter::zip(&*scene.meshes, bvhs));
-    if let Some(i) = &mut res {
-        i.n = i.n.to_unit()
+/// From blue at `0.0` through green to red at `1.0`.
+fn heat(x: f64) -> Color {
+    if x < 0.5 {
+        Color::new(0.0, 2.0 * x, 1.0 - 2.0 * x)
+    } else {
+        Color::new(2.0 * x - 1.0, 2.0 - 2.0 * x, 0.0)
     }
-    res
 }
 
-fn intersect_with<'a, T, I>(ray: &Ray, res: &mut Option<Intersection<'a>>, objects: I)
-where
-    T: RenderObject<'a>,
-    I: IntoIterator<Item = T>,
-{
-    let max_t = res.as_ref().map(|it| it.t).unwrap_or(f64::INFINITY);
-    for o in objects {
-        if let Some((t, n)) = o.intersect(&ray, max_t) {
-            *res = Some(Intersection { t, n, material: o.material() })
-        }
+fn false_color(color: Color, exposure: f64) -> Color {
+    const MIDDLE_GRAY: f64 = 0.18;
+    const UNDER: f64 = -6.0;
+    // White is log2(1 / 0.18) stops over middle gray.
+    const OVER: f64 = 2.47;
+    let luminance = color.luminance();
+    if luminance <= 0.0 {
+        return Color::default();
+    }
+    let stops = log2(luminance / MIDDLE_GRAY) + exposure;
+    if stops >= OVER {
+        return Color::new(1.0, 1.0, 1.0);
     }
+    heat(((stops - UNDER) / (OVER - UNDER)).max(0.0))
 }
 
-trait RenderObject<'a> {
-    fn intersect(&self, ray: &Ray, max_t: f64) -> Option<(f64, v64)>;
-    fn material(&self) -> &'a Material;
+/// `f64::log2` is not in `core`. Exact at powers of two and linear in
+/// between, which is close enough for a color ramp.
+pub(crate) fn log2(x: f64) -> f64 {
+    let (mut x, mut res) = (x, 0.0);
+    while x >= 2.0 && res < 64.0 {
+        x /= 2.0;
+        res += 1.0;
+    }
+    while x < 1.0 && res > -64.0 {
+        x *= 2.0;
+        res -= 1.0;
+    }
+    res + (x - 1.0)
 }
 
-impl<'a> RenderObject<'a> for &'a Sphere {
-    fn intersect(&self, ray: &Ray, max_t: f64) -> Option<(f64, v64)> {
-        let o = ray.origin() - self.center;
-        let k = dot(ray.dir(), o);
-        let c = dot(o, o) - self.radius.powi(2);
+/// `bounces` are the reflections left, and `weight` is how much the color
+/// seen along `ray` contributes to the pixel.
+#[allow(clippy::too_many_arguments)]
+fn whitted<'m>(
+    scene: &Scene<'m>,
+    backend: &impl Intersect<'m>,
+    settings: &RenderSettings,
+    bias: Bias,
+    ray: &Ray,
+    bounces: u32,
+    weight: f64,
+    rng: &mut Rng,
+    rays: &mut u64,
+) -> Color {
+    let mut res = scene.background;
+    let first = bounces == settings.bounces;
+    let kind = if first { RayKind::Camera } else { RayKind::Reflection };
+    if let Some(i) = intersect(scene, backend, ray, kind, rays) {
+        if i.material.shadow_catcher {
+            let (behind, light) = catch_shadow(scene, backend, bias, ray, &i, rays);
+            // Going on behind the catcher takes a bounce, as with paths, or
+            // a ray could pass between catchers forever.
+            let color = match bounces.checked_sub(1) {
+                Some(bounces) => {
+                    whitted(scene, backend, settings, bias, &behind, bounces, weight, rng, rays)
+                }
+                None => scene.background,
+            };
+            return color * light;
+        }
+        let mut p = ray.at(i.t);
+        p = p + i.n * bias.at(i.t);
+
+        res = shade(scene, backend, i.material, p, ray.time(), i.n, Some(ray.dir()), rays);
 
-        let disc = k * k - c;
-        if disc < 0.0 {
-            return None;
+        let reflectance = i.material.reflectance.clamp(0.0, 1.0);
+        if reflectance > 0.0 && bounces > 0 {
+            let weight = weight * reflectance;
+            // Russian roulette: faint reflections are traced only some of the
+            // time, and count for more when they are.
+            let survival = weight.max(settings.min_survival).min(1.0);
+            let mut reflected = Color::default();
+            if survival >= 1.0 || rng.next_f64() < survival {
+                let mirrored = ray.dir() - i.n * (2.0 * dot(ray.dir(), i.n));
+                let roughness = i.material.roughness.clamp(0.0, 1.0);
+                let n = if roughness > 0.0 && first { settings.glossy_splits } else { 1 };
+                for _ in 0..n {
+                    let dir = glossy(mirrored, i.n, roughness, rng);
+                    let ray = Ray::new(p, dir).at_time(ray.time());
+                    let weight = weight / f64::from(n);
+                    reflected = reflected
+                        + whitted(
+                            scene,
+                            backend,
+                            settings,
+                            bias,
+                            &ray,
+                            bounces - 1,
+                            weight,
+                            rng,
+                            rays,
+                        );
+                }
+                reflected = reflected / (f64::from(n) * survival);
+            }
+            res = res * (1.0 - reflectance) + reflected * reflectance;
         }
+        // Glows, but without lighting anything else, which takes paths.
+        res = res + i.material.emission;
+    }
+    res
+}
 
-        let t = {
-            let t1 = -k - disc.sqrt();
-            let t2 = -k + disc.sqrt();
-            if t1 > 0.0 {
-                t1
-            } else if t2 > 0.0 {
-                t2
+/// One path from `ray` on, see [`Integrator::Path`]. Each hit adds the light
+/// of the scene's lights, and the light of the emissive surfaces and of the
+/// background reaches it through the bounces. The ambient term is left out,
+/// as it stands in for the light which bounces off other surfaces.
+fn path<'m>(
+    scene: &Scene<'m>,
+    backend: &impl Intersect<'m>,
+    settings: &RenderSettings,
+    bias: Bias,
+    ray: &Ray,
+    rng: &mut Rng,
+    rays: &mut u64,
+) -> Color {
+    let mut res = Color::default();
+    // How much of the light coming along `ray` reaches the camera.
+    let mut weight = Color::new(1.0, 1.0, 1.0);
+    let mut ray = *ray;
+    for bounce in 0..=settings.bounces {
+        let kind = if bounce == 0 { RayKind::Camera } else { RayKind::Reflection };
+        let Some(i) = intersect(scene, backend, &ray, kind, rays) else {
+            return res + weight * scene.background;
+        };
+        if i.material.shadow_catcher {
+            let (behind, light) = catch_shadow(scene, backend, bias, &ray, &i, rays);
+            weight = weight * light;
+            ray = behind;
+            continue;
+        }
+        if dot(i.n, ray.dir()) > 0.0 {
+            // The back of a single-sided surface, dark as with Whitted.
+            break;
+        }
+        let p = ray.at(i.t) + i.n * bias.at(i.t);
+        res = res + weight * i.material.emission;
+
+        // Either reflects or scatters, with the odds of how much the surface
+        // does of each, which averages to their mix.
+        let reflectance = i.material.reflectance.clamp(0.0, 1.0);
+        let dir = if reflectance > 0.0 && rng.next_f64() < reflectance {
+            let mirrored = ray.dir() - i.n * (2.0 * dot(ray.dir(), i.n));
+            glossy(mirrored, i.n, i.material.roughness.clamp(0.0, 1.0), rng)
+        } else {
+            let direct =
+                lights(scene, backend, i.material, p, ray.time(), i.n, Some(ray.dir()), rays);
+            res = res + weight * direct;
+            weight = weight * i.material.color * i.material.diffuse.max(0.0);
+            // A random point on the unit sphere around the tip of the normal
+            // is a direction with the odds of the cosine, which diffuse
+            // surfaces weigh the incoming light by.
+            let dir = i.n + rng.in_unit_ball().to_unit();
+            if dot(dir, i.n) > 0.0 {
+                dir
             } else {
-                return None;
+                i.n
             }
         };
-        if t >= max_t {
-            return None;
-        }
 
-        let point = ray.at(t);
-        let normal = point - self.center;
-        Some((t, normal))
-    }
-    fn material(&self) -> &'a Material {
-        &self.material
+        let strongest = weight.r.max(weight.g).max(weight.b);
+        if strongest <= 0.0 {
+            break;
+        }
+        // Russian roulette, as with the reflections of Whitted.
+        let survival = strongest.max(settings.min_survival).min(1.0);
+        if survival < 1.0 {
+            if rng.next_f64() >= survival {
+                break;
+            }
+            weight = weight / survival;
+        }
+        ray = Ray::new(p, dir).at_time(ray.time());
     }
+    res
 }
 
-impl<'a> RenderObject<'a> for &'a Plane {
-    fn intersect(&self, ray: &Ray, max_t: f64) -> Option<(f64, v64)> {
-        let o = ray.origin() - self.normal.origin();
-        let t = -dot(o, self.normal.dir()) / dot(ray.dir(), self.normal.dir());
-        if !(0.0 < t && t < max_t) {
-            return None;
+/// The ray which goes on behind the shadow catcher `i` hit by `ray`, and the
+/// fraction of the light of the scene's lights which reaches the hit point,
+/// weighted by how much each of them would light it.
+fn catch_shadow<'m>(
+    scene: &Scene<'m>,
+    backend: &impl Intersect<'m>,
+    bias: Bias,
+    ray: &Ray,
+    i: &Hit<'_>,
+    rays: &mut u64,
+) -> (Ray, f64) {
+    let n = if dot(i.n, ray.dir()) > 0.0 { -i.n } else { i.n };
+    let at = ray.at(i.t);
+    let p = at + n * bias.at(i.t);
+    let (mut lit, mut total) = (0.0, 0.0);
+    let shadows = scene.lights.iter().filter_map(|light| {
+        let (lr, distance) = light.ray_from(p);
+        let lr = lr.at_time(ray.time());
+        let k = dot(lr.dir(), n).max(0.0) * light.color.grayscale();
+        (k > 0.0).then_some((k, lr, distance))
+    });
+    shadow_stream(scene, backend, shadows, rays, |k, _, hit| {
+        total += k;
+        if !hit {
+            lit += k;
         }
-        Some((t, self.normal.dir()))
-    }
-    fn material(&self) -> &'a Material {
-        &self.material
-    }
+    });
+    let light = if total > 0.0 { lit / total } else { 1.0 };
+    (Ray::new(at - n * bias.at(i.t), ray.dir()).at_time(ray.time()), light)
 }
 
-impl<'a, 'm> RenderObject<'a> for (&'a Mesh<'m>, &'a Bvh<'m>) {
-    fn intersect(&self, ray: &Ray, mut max_t: f64) -> Option<(f64, v64)> {
-        let (mesh, bvh) = *self;
-        let mut n = None;
-        bvh.intersect(ray, &mut max_t, &mut |face, max_t| {
-            if let Some((t, nn)) = intersect_triangle(&mesh.triangle(face as usize), ray, *max_t) {
-                *max_t = t;
-                n = Some(nn);
-            }
-        });
-        let n = n?;
-        Some((max_t, n))
+/// `mirrored` moved at random for a surface with the given `roughness` and
+/// unit normal `n`, see [`Material::roughness`].
+fn glossy(mirrored: v64, n: v64, roughness: f64, rng: &mut Rng) -> v64 {
+    if roughness > 0.0 {
+        // Moves the tip of the mirror direction within a ball. The ones which
+        // end up below the surface stay mirrored.
+        let jittered = mirrored.to_unit() + rng.in_unit_ball() * roughness;
+        if dot(jittered, n) > 0.0 {
+            return jittered;
+        }
     }
+    mirrored
+}
 
-    fn material(&self) -> &'a Material {
-        &self.0.material
-    }
+/// The ambient and diffuse terms at point `p`, already nudged off the surface,
+/// with unit normal `n`, and the specular term if the point is seen along
+/// `view`. Unlike reflections and highlights, the first two don't depend on
+/// where the point is seen from. Shadows are cast by the objects where they
+/// are at `time`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn shade<'m>(
+    scene: &Scene<'m>,
+    backend: &impl Intersect<'m>,
+    material: &Material,
+    p: v64,
+    time: f64,
+    n: v64,
+    view: Option<v64>,
+    rays: &mut u64,
+) -> Color {
+    let ambient_color = material.color * scene.ambient * material.ambient.max(0.0);
+    ambient_color + lights(scene, backend, material, p, time, n, view, rays)
 }
 
-fn intersect_triangle(tr: &Triangle, ray: &Ray, max_t: f64) -> Option<(f64, v64)> {
-    let ab = tr.v[1] - tr.v[0];
-    let ac = tr.v[2] - tr.v[0];
-    let n = cross(ab, ac);
-    // a + alpha ab + beta ac = ray.origin + t * ray.direction
-    let t = dot(tr.v[0] - ray.origin(), n) / dot(ray.dir(), n);
-    if !(0.0 <= t && t <= max_t) {
-        return None;
-    }
-    let point = ray.at(t);
-    let local_coords: [f64; 3] = {
-        let ort_ac = cross(ac, n);
-        let ort_ab = cross(ab, n);
-        let point = point - tr.v[0];
-        let alpha = dot(point, ort_ac) / dot(ab, ort_ac);
-        let beta = dot(point, ort_ab) / dot(ac, ort_ab);
-        let gamma = 1.0 - (alpha + beta);
-        [gamma, alpha, beta]
-    };
-    if local_coords.map(|it| 0.0 < it && it < 1.0) != [true; 3] {
-        return None;
+/// The diffuse and specular terms of [`shade`], the light of the scene's
+/// lights.
+#[allow(clippy::too_many_arguments)]
+fn lights<'m>(
+    scene: &Scene<'m>,
+    backend: &impl Intersect<'m>,
+    material: &Material,
+    p: v64,
+    time: f64,
+    n: v64,
+    view: Option<v64>,
+    rays: &mut u64,
+) -> Color {
+    let mut res = Color::default();
+    // The lights of switched off groups are black, and need no shadow rays.
+    let on = scene.lights.iter().filter(|it| it.color.grayscale() > 0.0);
+    let shadows = on.map(|light| {
+        let (lr, distance) = light.ray_from(p);
+        (light, lr.at_time(time), distance)
+    });
+    shadow_stream(scene, backend, shadows, rays, |light, lr, hit| {
+        if hit {
+            return;
+        }
+        let k = (dot(lr.dir(), n)).max(0.0) * material.diffuse.max(0.0);
+        let diffuse_color = material.color * light.color * k;
+        res = res + diffuse_color;
+
+        let specular = material.specular.max(0.0);
+        if let Some(view) = view.filter(|_| specular > 0.0) {
+            // The half-way vector between the directions to the light and to
+            // the eye lines up with the normal where the mirror image of the
+            // light would be.
+            let h = (lr.dir() - view.to_unit()).to_unit();
+            let k = geom::powf(dot(h, n).max(0.0), material.shininess) * specular;
+            res = res + light.color * k;
+        }
+    });
+    res
+}
+
+/// Shadow rays go to the backend in batches of up to this many, see
+/// [`Intersect::any_hit_stream`].
+const STREAM: usize = 16;
+
+/// Casts the shadow `rays`, given with their lengths, and calls `f` with the
+/// item each one came with, the ray and whether it's blocked. Counts the rays
+/// cast in `n_rays`.
+fn shadow_stream<'m, T: Copy>(
+    scene: &Scene<'m>,
+    backend: &impl Intersect<'m>,
+    mut rays: impl Iterator<Item = (T, Ray, f64)>,
+    n_rays: &mut u64,
+    mut f: impl FnMut(T, &Ray, bool),
+) {
+    while let Some(first) = rays.next() {
+        let (mut items, mut batch, mut max_t) =
+            ([first.0; STREAM], [first.1; STREAM], [first.2; STREAM]);
+        let mut n = 1;
+        for (item, ray, t) in rays.by_ref().take(STREAM - 1) {
+            (items[n], batch[n], max_t[n]) = (item, ray, t);
+            n += 1;
+        }
+        *n_rays += n as u64;
+        let mut hit = [false; STREAM];
+        backend.any_hit_stream(scene, &batch[..n], &mut max_t[..n], RayKind::Shadow, &mut hit[..n]);
+        for ((item, ray), hit) in items.into_iter().zip(&batch).zip(hit).take(n) {
+            f(item, ray, hit);
+        }
     }
-    let mut n = v64::ZERO;
-    for i in 0..3 {
-        n = n + tr.n[i] * local_coords[i];
+}
+
+fn intersect<'s, 'm>(
+    scene: &'s Scene<'m>,
+    backend: &'s impl Intersect<'m>,
+    ray: &Ray,
+    kind: RayKind,
+    rays: &mut u64,
+) -> Option<Hit<'s>> {
+    *rays += 1;
+    let mut res = backend.closest_hit(scene, ray, f64::INFINITY, kind);
+    if let Some(i) = &mut res {
+        i.n = i.n.to_unit();
+        if i.material.double_sided && dot(i.n, ray.dir()) > 0.0 {
+            i.n = -i.n;
+        }
     }
-    Some((t, n))
+    res
 }
@@ -1,22 +1,59 @@
 mod color;
+mod histogram;
+mod label;
 
 use core::{
-    marker::PhantomData,
-    ops, slice,
-    sync::atomic::{AtomicU32, Ordering::SeqCst},
+    array, cmp, ops,
+    sync::atomic::{AtomicUsize, Ordering::SeqCst},
 };
 
-pub use self::color::{Color, ParseColorError};
+use spin::Mutex;
+
+pub use self::{
+    color::{Color, ParseColorError},
+    histogram::Histogram,
+    label::Label,
+};
+
+use crate::Order;
 
 pub type Idx = [u32; 2];
 
-pub struct Buf<'m> {
+/// Image buffer, generic over the pixel type.
+///
+/// `Buf` holds the final 8-bit sRGB image, `FBuf` is its floating point
+/// counterpart in linear RGB, used for intermediate results.
+pub struct Buf<'m, C = Color> {
     dim: [u32; 2],
-    buf: &'m mut [Color],
+    buf: &'m mut [C],
+}
+
+pub type FBuf<'m> = Buf<'m, scene::Color>;
+
+/// A horizontal run of finished pixels, starting at `[x, y]`.
+pub struct Span<'a, C = Color> {
+    pub y: u32,
+    pub x: u32,
+    pub pixels: &'a [C],
+}
+
+/// How to hide banding when quantizing floating point colors to 8 bits.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Dither {
+    #[default]
+    None,
+    /// Ordered dithering with an 8x8 Bayer matrix.
+    Bayer,
 }
 
-impl<'m> Buf<'m> {
-    pub fn new(dim @ [dx, dy]: Idx, buf: &'m mut [Color]) -> Buf<'m> {
+/// Pixel types which can be averaged by [`Buf::resize`].
+pub trait Pixel: Copy {
+    fn to_rgb(self) -> [f64; 3];
+    fn from_rgb(rgb: [f64; 3]) -> Self;
+}
+
+impl<'m, C> Buf<'m, C> {
+    pub fn new(dim @ [dx, dy]: Idx, buf: &'m mut [C]) -> Buf<'m, C> {
         assert!(dx * dy == buf.len() as u32);
         Buf { dim, buf }
     }
@@ -24,10 +61,10 @@ impl<'m> Buf<'m> {
         let [dx, dy] = self.dim;
         (0..dy).flat_map(move |y| (0..dx).map(move |x| [x, y]))
     }
-    pub fn buf(&self) -> &[Color] {
+    pub fn buf(&self) -> &[C] {
         &*self.buf
     }
-    pub fn buf_mut(&mut self) -> &mut [Color] {
+    pub fn buf_mut(&mut self) -> &mut [C] {
         &mut *self.buf
     }
     pub fn dim(&self) -> Idx {
@@ -39,13 +76,14 @@ impl<'m> Buf<'m> {
     pub fn height(&self) -> u32 {
         self.dim[1]
     }
-    pub(crate) fn partition(&mut self) -> BufPartition<'_, 'm> {
-        BufPartition {
-            p: PhantomData,
-            buf: self.buf.as_mut_ptr(),
-            dim: self.dim,
-            next_row: AtomicU32::new(0),
-        }
+    /// See [`Schedule::new`] for `cost`.
+    pub(crate) fn partition(
+        &mut self,
+        order: Order,
+        chunk: Option<u32>,
+        cost: &dyn Fn(usize) -> u32,
+    ) -> BufPartition<'_, C> {
+        BufPartition::new(self.dim, self.buf, order, chunk, cost)
     }
     fn linear(&self, idx: Idx) -> Option<usize> {
         if !(idx[0] < self.dim[0] && idx[1] < self.dim[1]) {
@@ -55,8 +93,103 @@ impl<'m> Buf<'m> {
     }
 }
 
-impl<'m> ops::Index<Idx> for Buf<'m> {
-    type Output = Color;
+impl<'m, C: Pixel> Buf<'m, C> {
+    /// Box-filters this image into `dst`, which can be smaller (thumbnails) or
+    /// larger (upscaling a low-res preview) than `self`.
+    ///
+    /// Each destination pixel is the average of the source area it covers,
+    /// weighted by overlap.
+    pub fn resize(&self, dst: &mut Buf<'_, C>) {
+        let [sw, sh] = self.dim;
+        let [dw, dh] = dst.dim;
+        if sw == 0 || sh == 0 {
+            return;
+        }
+        let kx = sw as f64 / dw as f64;
+        let ky = sh as f64 / dh as f64;
+        for [x, y] in dst.by_row() {
+            let (x0, x1) = (x as f64 * kx, (x + 1) as f64 * kx);
+            let (y0, y1) = (y as f64 * ky, (y + 1) as f64 * ky);
+            let mut acc = [0.0; 3];
+            let mut total = 0.0;
+            for sy in (y0 as u32)..(ceil(y1) as u32).min(sh) {
+                let wy = overlap(sy, y0, y1);
+                for sx in (x0 as u32)..(ceil(x1) as u32).min(sw) {
+                    let w = wy * overlap(sx, x0, x1);
+                    let rgb = self[[sx, sy]].to_rgb();
+                    for i in 0..3 {
+                        acc[i] += rgb[i] * w;
+                    }
+                    total += w;
+                }
+            }
+            dst[[x, y]] = C::from_rgb(acc.map(|it| it / total));
+        }
+    }
+}
+
+/// Length of the intersection of `[i, i + 1)` and `[lo, hi)`.
+fn overlap(i: u32, lo: f64, hi: f64) -> f64 {
+    let i = i as f64;
+    (hi.min(i + 1.0) - lo.max(i)).max(0.0)
+}
+
+fn ceil(x: f64) -> f64 {
+    let t = x as u64 as f64;
+    if t < x {
+        t + 1.0
+    } else {
+        t
+    }
+}
+
+/// Encodes a linear color to 8-bit sRGB, dithering according to the position
+/// of the pixel in the image.
+pub fn quantize(color: &scene::Color, [x, y]: Idx, dither: Dither) -> Color {
+    let offset = match dither {
+        Dither::None => 0.0,
+        Dither::Bayer => (BAYER[(y % 8) as usize][(x % 8) as usize] as f64 + 0.5) / 64.0 - 0.5,
+    };
+    let f = |value: f64| {
+        let value = scene::linear_to_srgb(value.clamp(0.0, 1.0));
+        (value * 255.0 + offset).clamp(0.0, 255.0).round() as u8
+    };
+    Color { r: f(color.r), g: f(color.g), b: f(color.b) }
+}
+
+#[rustfmt::skip]
+const BAYER: [[u8; 8]; 8] = [
+    [ 0, 32,  8, 40,  2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44,  4, 36, 14, 46,  6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [ 3, 35, 11, 43,  1, 33,  9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47,  7, 39, 13, 45,  5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+impl Pixel for Color {
+    fn to_rgb(self) -> [f64; 3] {
+        let scene::Color { r, g, b } = self.to_linear();
+        [r, g, b]
+    }
+    fn from_rgb([r, g, b]: [f64; 3]) -> Color {
+        Color::from_linear(&scene::Color::new(r, g, b))
+    }
+}
+
+impl Pixel for scene::Color {
+    fn to_rgb(self) -> [f64; 3] {
+        [self.r, self.g, self.b]
+    }
+    fn from_rgb([r, g, b]: [f64; 3]) -> scene::Color {
+        scene::Color::new(r, g, b)
+    }
+}
+
+impl<'m, C> ops::Index<Idx> for Buf<'m, C> {
+    type Output = C;
 
     fn index(&self, index: Idx) -> &Self::Output {
         let l = self.linear(index).unwrap();
@@ -64,42 +197,175 @@ impl<'m> ops::Index<Idx> for Buf<'m> {
     }
 }
 
-impl<'m> ops::IndexMut<Idx> for Buf<'m> {
+impl<'m, C> ops::IndexMut<Idx> for Buf<'m, C> {
     fn index_mut(&mut self, index: Idx) -> &mut Self::Output {
         let l = self.linear(index).unwrap();
         &mut self.buf_mut()[l]
     }
 }
 
-pub(crate) struct BufPartition<'a, 'm> {
-    p: PhantomData<&'a mut Buf<'m>>,
-    dim: Idx,
-    buf: *mut Color,
-    next_row: AtomicU32,
+/// Splits a buffer into horizontal bands which worker threads claim one at a
+/// time.
+pub(crate) struct BufPartition<'a, C = Color> {
+    width: u32,
+    band_height: u32,
+    /// The schedule hands out each index once, so the locks are never
+    /// contended, they only prove to the compiler that a band goes to a
+    /// single thread.
+    bands: [Mutex<Option<&'a mut [C]>>; MAX_BANDS],
+    schedule: Schedule,
+}
+
+pub(crate) const MAX_BANDS: usize = 256;
+
+/// Hands out the indices of `n` bands to the workers, in the given order.
+///
+/// Which worker gets which band is up to the scheduler, so nothing a worker
+/// computes may depend on it: all randomness of a pixel is seeded by the
+/// pixel's index, and samples are accumulated per pixel, in a fixed order.
+/// This keeps the image identical for any number of threads, order and chunk
+/// size.
+pub(crate) struct Schedule {
+    order: [u8; MAX_BANDS],
+    n: usize,
+    next: AtomicUsize,
 }
 
-unsafe impl Send for BufPartition<'_, '_> {}
-unsafe impl Sync for BufPartition<'_, '_> {}
+pub(crate) struct Band<'a, C = Color> {
+    y: u32,
+    width: u32,
+    buf: &'a mut [C],
+}
 
-pub(crate) struct Row<'a> {
+pub(crate) struct Row<'a, C = Color> {
     pub(crate) y: u32,
-    pub(crate) buf: &'a mut [Color],
+    pub(crate) buf: &'a mut [C],
 }
 
-impl<'a, 'm> BufPartition<'a, 'm> {
-    pub fn next_row(&self) -> Option<Row<'a>> {
-        let y = self.next_row.fetch_add(1, SeqCst);
-        if y >= self.dim[1] {
-            self.next_row.fetch_sub(1, SeqCst);
+impl<'a, C> BufPartition<'a, C> {
+    fn new(
+        [width, height]: Idx,
+        buf: &'a mut [C],
+        order: Order,
+        chunk: Option<u32>,
+        cost: &dyn Fn(usize) -> u32,
+    ) -> BufPartition<'a, C> {
+        let band_height = band_height(height, chunk);
+        let band_len = (band_height * width) as usize;
+        let mut chunks = if band_len == 0 { None } else { Some(buf.chunks_mut(band_len)) };
+        let bands = array::from_fn(|_| Mutex::new(chunks.as_mut().and_then(Iterator::next)));
+        let schedule = Schedule::new(order, height.div_ceil(band_height) as usize, cost);
+        BufPartition { width, band_height, bands, schedule }
+    }
+
+    pub(crate) fn next_band(&self) -> Option<Band<'a, C>> {
+        let i = self.schedule.next()?;
+        let buf = self.bands[i].try_lock()?.take()?;
+        Some(Band { y: i as u32 * self.band_height, width: self.width, buf })
+    }
+}
+
+/// Rows per band: `chunk` if set, but no less than needed for an image of
+/// `height` rows to have at most [`MAX_BANDS`] bands.
+pub(crate) fn band_height(height: u32, chunk: Option<u32>) -> u32 {
+    height.div_ceil(MAX_BANDS as u32).max(chunk.unwrap_or(1)).max(1)
+}
+
+impl Schedule {
+    /// `cost` estimates how long the band with the given index takes, it's
+    /// called for every band for [`Order::CostliestFirst`] only.
+    pub(crate) fn new(order: Order, n: usize, cost: &dyn Fn(usize) -> u32) -> Schedule {
+        assert!(n <= MAX_BANDS);
+        let mut res = Schedule { order: [0; MAX_BANDS], n, next: AtomicUsize::new(0) };
+        let mut len = 0;
+        let mut push = |i: usize| {
+            res.order[len] = i as u8;
+            len += 1;
+        };
+        match order {
+            Order::TopDown => (0..n).for_each(&mut push),
+            Order::CenterOut => {
+                let mid = n / 2;
+                for d in 0..n {
+                    if d <= mid {
+                        push(mid - d);
+                    }
+                    if d > 0 && mid + d < n {
+                        push(mid + d);
+                    }
+                }
+            }
+            Order::Interleaved => {
+                for offset in [0, 4, 2, 6, 1, 5, 3, 7] {
+                    (offset..n).step_by(8).for_each(&mut push);
+                }
+            }
+            Order::CostliestFirst => {
+                (0..n).for_each(&mut push);
+                let costs: [u32; MAX_BANDS] = array::from_fn(|i| if i < n { cost(i) } else { 0 });
+                // Bands of the same cost stay top-down.
+                res.order[..n].sort_unstable_by_key(|&i| (cmp::Reverse(costs[i as usize]), i));
+            }
+        }
+        res
+    }
+
+    pub(crate) fn next(&self) -> Option<usize> {
+        let i = self.next.fetch_add(1, SeqCst);
+        if i >= self.n {
             return None;
         }
-        let start = (y * self.dim[0]) as usize;
-        let end = ((y + 1) * self.dim[0]) as usize;
-        let buf = unsafe {
-            let data = self.buf.add(start);
-            let len = end - start;
-            slice::from_raw_parts_mut(data, len)
-        };
-        Some(Row { y, buf })
+        Some(self.order[i] as usize)
+    }
+}
+
+impl<'a, C> Band<'a, C> {
+    pub(crate) fn rows(self) -> impl Iterator<Item = Row<'a, C>> {
+        let y = self.y;
+        self.buf
+            .chunks_mut(self.width as usize)
+            .enumerate()
+            .map(move |(i, buf)| Row { y: y + i as u32, buf })
+    }
+}
+
+#[test]
+fn test_resize() {
+    let mut src =
+        [Color::new(0, 0, 0), Color::new(100, 0, 0), Color::new(0, 0, 0), Color::new(0, 200, 0)];
+    let src = Buf::new([2, 2], &mut src);
+    let mut dst = [Color::default(); 1];
+    let mut dst = Buf::new([1, 1], &mut dst);
+    src.resize(&mut dst);
+    // Averaging happens in linear space, so the result is brighter than the
+    // naive `(25, 50, 0)`.
+    assert!(dst[[0, 0]] == Color::new(50, 106, 0));
+
+    let mut up = [Color::default(); 16];
+    let mut up = Buf::new([4, 4], &mut up);
+    src.resize(&mut up);
+    assert!(up[[1, 0]] == Color::new(0, 0, 0) && up[[2, 0]] == Color::new(100, 0, 0));
+    assert!(up[[3, 3]] == Color::new(0, 200, 0));
+}
+
+#[test]
+fn test_schedule() {
+    let cost = |i: usize| (i % 3) as u32;
+    for order in [Order::TopDown, Order::CenterOut, Order::Interleaved, Order::CostliestFirst] {
+        for n in [0, 1, 2, 7, 10, MAX_BANDS] {
+            let schedule = Schedule::new(order, n, &cost);
+            let mut seen = [false; MAX_BANDS];
+            while let Some(i) = schedule.next() {
+                assert!(!seen[i]);
+                seen[i] = true;
+            }
+            assert!(seen[..n].iter().all(|&it| it));
+        }
     }
+    let schedule = Schedule::new(Order::CenterOut, 5, &cost);
+    let order: [usize; 5] = array::from_fn(|_| schedule.next().unwrap());
+    assert_eq!(order, [2, 1, 3, 0, 4]);
+    let schedule = Schedule::new(Order::CostliestFirst, 5, &cost);
+    let order: [usize; 5] = array::from_fn(|_| schedule.next().unwrap());
+    assert_eq!(order, [2, 1, 4, 0, 3]);
 }
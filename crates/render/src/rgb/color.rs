@@ -1,5 +1,6 @@
 use core::{fmt, num::ParseIntError, str::FromStr};
 
+/// 8-bit sRGB-encoded color, as stored in image files.
 #[derive(Clone, Copy, Default, PartialEq, Eq)]
 pub struct Color {
     pub r: u8,
@@ -38,12 +39,23 @@ impl Color {
     pub fn new_white() -> Color {
         Color::new(!0, !0, !0)
     }
+
+    /// Decodes to linear RGB.
+    pub fn to_linear(self) -> scene::Color {
+        let [r, g, b] = [self.r, self.g, self.b].map(|it| scene::srgb_to_linear(it as f64 / 255.0));
+        scene::Color::new(r, g, b)
+    }
+
+    /// Encodes a linear color, clamping it to the representable range.
+    pub fn from_linear(color: &scene::Color) -> Color {
+        super::quantize(color, [0, 0], super::Dither::None)
+    }
 }
 
 impl fmt::Display for Color {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let Color { r, g, b } = self;
-        write!(f, "#{r:2x}{g:2x}{b:2x}")
+        write!(f, "#{r:02x}{g:02x}{b:02x}")
     }
 }
 
@@ -0,0 +1,103 @@
+//! Luminance statistics of images, to tell how well they are exposed.
+
+/// Luminance statistics of an image, gathered a row at a time from the colors
+/// which go into image files.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    /// Pixel counts by luminance, in equal steps from `0.0` to `1.0`. Brighter
+    /// pixels count in the last one.
+    pub bins: [u64; Histogram::BINS],
+    pub min: f64,
+    pub max: f64,
+    pub pixels: u64,
+    /// Pixels with a component over `1.0`, which 8-bit formats clip.
+    pub clipped: u64,
+    sum: f64,
+    log_sum: f64,
+    /// Pixels which are not black, which make up the log-average.
+    lit: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram {
+            bins: [0; Histogram::BINS],
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            pixels: 0,
+            clipped: 0,
+            sum: 0.0,
+            log_sum: 0.0,
+            lit: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub const BINS: usize = 64;
+
+    pub fn add(&mut self, row: &[scene::Color]) {
+        for color in row {
+            let luminance = color.luminance();
+            let bin = (luminance * Histogram::BINS as f64) as usize;
+            self.bins[bin.min(Histogram::BINS - 1)] += 1;
+            self.min = self.min.min(luminance);
+            self.max = self.max.max(luminance);
+            self.sum += luminance;
+            if luminance > 0.0 {
+                self.log_sum += crate::render::log2(luminance);
+                self.lit += 1;
+            }
+            self.pixels += 1;
+            if color.r > 1.0 || color.g > 1.0 || color.b > 1.0 {
+                self.clipped += 1;
+            }
+        }
+    }
+
+    /// Mean luminance, zero for an empty image.
+    pub fn average(&self) -> f64 {
+        if self.pixels == 0 {
+            return 0.0;
+        }
+        self.sum / self.pixels as f64
+    }
+
+    /// The geometric mean of the luminance of the pixels which are not black,
+    /// which unlike [`Histogram::average`] isn't dominated by a few very bright
+    /// ones, such as light sources, nor by an empty background. Zero for a
+    /// black image.
+    pub fn log_average(&self) -> f64 {
+        if self.lit == 0 {
+            return 0.0;
+        }
+        geom::powf(2.0, self.log_sum / self.lit as f64)
+    }
+
+    /// The fraction of the pixels which are [`Histogram::clipped`].
+    pub fn clipped_fraction(&self) -> f64 {
+        if self.pixels == 0 {
+            return 0.0;
+        }
+        self.clipped as f64 / self.pixels as f64
+    }
+}
+
+#[test]
+fn test_histogram() {
+    let mut histogram = Histogram::default();
+    let gray = |it| scene::Color::new(it, it, it);
+    histogram.add(&[gray(0.0), gray(0.5), gray(1.0)]);
+    histogram.add(&[scene::Color::new(2.0, 0.0, 0.0)]);
+    assert_eq!(histogram.pixels, 4);
+    assert_eq!(histogram.clipped, 1);
+    assert_eq!(histogram.bins[0], 1);
+    assert_eq!(histogram.bins[Histogram::BINS / 2], 1);
+    assert_eq!(histogram.bins[Histogram::BINS - 1], 1);
+    assert_eq!(histogram.bins.iter().sum::<u64>(), 4);
+    assert_eq!((histogram.min, histogram.max), (0.0, 1.0));
+
+    let mut histogram = Histogram::default();
+    histogram.add(&[gray(0.25), gray(0.0), gray(1.0)]);
+    assert!((histogram.log_average() - 0.5).abs() < 1e-3);
+}
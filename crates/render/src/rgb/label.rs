@@ -0,0 +1,167 @@
+//! Text burnt into images, such as the scene and the frame of review renders,
+//! in a built-in 3x5 pixel font.
+
+use super::{Buf, Idx, Pixel};
+
+/// A line of text in the bottom left corner of an image, white on a darkened
+/// box. Drawn a row at a time, so that it works on rows streamed to a file as
+/// well as on a whole [`Buf`].
+pub struct Label<'a> {
+    text: &'a str,
+    /// Image pixels per font pixel, so that the text stays readable in large
+    /// images.
+    scale: u32,
+    /// Top left corner of the box.
+    origin: Idx,
+    /// Width of the box in font pixels.
+    width: u32,
+}
+
+const GLYPH_HEIGHT: u32 = 5;
+/// A glyph and the space after it.
+const ADVANCE: u32 = 4;
+
+impl<'a> Label<'a> {
+    /// Lowercase letters are drawn as uppercase, and characters the font
+    /// lacks as `?`.
+    pub fn new([_, height]: Idx, text: &'a str) -> Label<'a> {
+        let scale = (height / 200).max(1);
+        let width = ADVANCE * text.chars().count() as u32 + 1;
+        let box_height = (GLYPH_HEIGHT + 2) * scale;
+        let margin = 2 * scale;
+        let origin = [margin, height.saturating_sub(margin + box_height)];
+        Label { text, scale, origin, width }
+    }
+
+    /// Draws the part of the label in the `y`-th row of the image.
+    pub fn row<C: Pixel>(&self, y: u32, row: &mut [C]) {
+        let s = self.scale;
+        let Some(fy) = y.checked_sub(self.origin[1]).map(|it| it / s) else { return };
+        if fy >= GLYPH_HEIGHT + 2 {
+            return;
+        }
+        let x0 = self.origin[0] as usize;
+        let end = (x0 + (self.width * s) as usize).min(row.len());
+        for pixel in row.get_mut(x0..end).unwrap_or_default() {
+            *pixel = C::from_rgb(pixel.to_rgb().map(|it| it * 0.25));
+        }
+        if fy == 0 || fy > GLYPH_HEIGHT {
+            return;
+        }
+        let white = C::from_rgb([1.0; 3]);
+        for (i, c) in self.text.chars().enumerate() {
+            let bits = glyph(c)[(fy - 1) as usize];
+            for col in 0..3 {
+                if bits & (0b100 >> col) != 0 {
+                    let x = x0 + ((1 + ADVANCE * i as u32 + col) * s) as usize;
+                    let end = (x + s as usize).min(row.len());
+                    row.get_mut(x..end).unwrap_or_default().fill(white);
+                }
+            }
+        }
+    }
+}
+
+impl<C: Pixel> Buf<'_, C> {
+    /// Draws `label` over the image.
+    pub fn label(&mut self, label: &Label<'_>) {
+        let width = self.width() as usize;
+        if width == 0 {
+            return;
+        }
+        for (y, row) in self.buf.chunks_mut(width).enumerate() {
+            label.row(y as u32, row);
+        }
+    }
+}
+
+/// Rows of the glyph from the top, with the leftmost pixel in the highest of
+/// the three bits.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        c @ '0'..='9' => DIGITS[c as usize - '0' as usize],
+        c @ 'A'..='Z' => LETTERS[c as usize - 'A' as usize],
+        ' ' => [0; 5],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+#[rustfmt::skip]
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+#[rustfmt::skip]
+const LETTERS: [[u8; 5]; 26] = [
+    [0b010, 0b101, 0b111, 0b101, 0b101],
+    [0b110, 0b101, 0b110, 0b101, 0b110],
+    [0b011, 0b100, 0b100, 0b100, 0b011],
+    [0b110, 0b101, 0b101, 0b101, 0b110],
+    [0b111, 0b100, 0b110, 0b100, 0b111],
+    [0b111, 0b100, 0b110, 0b100, 0b100],
+    [0b011, 0b100, 0b101, 0b101, 0b011],
+    [0b101, 0b101, 0b111, 0b101, 0b101],
+    [0b111, 0b010, 0b010, 0b010, 0b111],
+    [0b001, 0b001, 0b001, 0b101, 0b010],
+    [0b101, 0b101, 0b110, 0b101, 0b101],
+    [0b100, 0b100, 0b100, 0b100, 0b111],
+    [0b101, 0b111, 0b111, 0b101, 0b101],
+    [0b110, 0b101, 0b101, 0b101, 0b101],
+    [0b010, 0b101, 0b101, 0b101, 0b010],
+    [0b110, 0b101, 0b110, 0b100, 0b100],
+    [0b010, 0b101, 0b101, 0b110, 0b011],
+    [0b110, 0b101, 0b110, 0b101, 0b101],
+    [0b011, 0b100, 0b010, 0b001, 0b110],
+    [0b111, 0b010, 0b010, 0b010, 0b010],
+    [0b101, 0b101, 0b101, 0b101, 0b111],
+    [0b101, 0b101, 0b101, 0b101, 0b010],
+    [0b101, 0b101, 0b111, 0b111, 0b101],
+    [0b101, 0b101, 0b010, 0b101, 0b101],
+    [0b101, 0b101, 0b010, 0b010, 0b010],
+    [0b111, 0b001, 0b010, 0b100, 0b111],
+];
+
+#[test]
+fn test_label() {
+    let gray = scene::Color::new(0.5, 0.5, 0.5);
+    let mut pixels = [gray; 20 * 10];
+    let mut buf = Buf::new([20, 10], &mut pixels);
+    buf.label(&Label::new(buf.dim(), "1"));
+    // The box is 5x7 pixels, 2 pixels away from the left and bottom edges,
+    // with a pixel of padding around the glyph.
+    for [x, y] in buf.by_row() {
+        let pixel = buf[[x, y]];
+        let inside = (2..7).contains(&x) && (1..8).contains(&y);
+        let lit = (3..6).contains(&x)
+            && (2..7).contains(&y)
+            && glyph('1')[y as usize - 2] & (0b100 >> (x - 3)) != 0;
+        let expected = if lit {
+            1.0
+        } else if inside {
+            0.125
+        } else {
+            0.5
+        };
+        assert_eq!(pixel.r, expected, "{x} {y}");
+    }
+}
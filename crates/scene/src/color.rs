@@ -1,5 +1,9 @@
 use core::ops;
 
+/// Color in linear RGB, where components are proportional to light intensity.
+///
+/// Hex colors in the scene file are sRGB-encoded and are converted to linear
+/// on parse.
 #[derive(Debug, Clone, Copy, Default)]
 pub struct Color {
     pub r: f64,
@@ -16,6 +20,11 @@ impl Color {
     pub fn grayscale(&self) -> f64 {
         (self.r + self.g + self.b) / 3.0
     }
+
+    /// Perceived brightness, with the Rec. 709 weights.
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
 }
 
 impl ops::Mul for Color {
@@ -49,3 +58,21 @@ impl ops::Add for Color {
         Color::new(self.r + rhs.r, self.g + rhs.g, self.b + rhs.b)
     }
 }
+
+/// Decodes an sRGB-encoded component in `0.0..=1.0` into linear light.
+pub fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        geom::powf((value + 0.055) / 1.055, 2.4)
+    }
+}
+
+/// The inverse of [`srgb_to_linear`].
+pub fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * geom::powf(value, 1.0 / 2.4) - 0.055
+    }
+}
@@ -5,15 +5,30 @@ use core::{
     str::SplitAsciiWhitespace,
 };
 
-use geom::{v64, ParseVectorError, Ray};
+use geom::{cross, dot, split_n, v64, ParseVectorError, Ray};
 use mem::{Mem, Oom};
 
-use crate::{color::Color, Camera, Light, Material, Mesh, MeshFace, Plane, Scene, Sphere};
+use crate::{
+    color::{self, Color},
+    noise::Noise,
+    simplify, Blobs, Camera, Clip, Curves, Integrator, Keyframe, Light, LightKind, Material, Mesh,
+    MeshFace, Name, ObjectKind, Plane, Quadric, RenderSettings, Scene, Sphere, Target, Tonemap,
+    Torus, Track, Visibility,
+};
 
 #[derive(Debug, displaydoc::Display)]
 pub struct ParseSceneError<'a> {
     kind: ErrorKind,
     context: [&'a str; 4],
+    line: Option<u32>,
+}
+
+/// Something which parses fine, but likely isn't what the author meant.
+#[derive(Debug)]
+pub struct Warning<'a> {
+    kind: WarningKind,
+    context: [&'a str; 4],
+    line: Option<u32>,
 }
 
 #[derive(Debug, displaydoc::Display)]
@@ -28,6 +43,10 @@ enum ErrorKind {
     ParseVectorError(ParseVectorError),
     /// invalid scalar: {0}
     ParseFloatError(ParseFloatError),
+    /// invalid integer: {0}
+    ParseIntError(ParseIntError),
+    /// invalid value
+    InvalidValue,
     /// invalid dimensions
     InvalidDim,
     /// invalid key
@@ -40,6 +59,48 @@ enum ErrorKind {
     InvalidFaceIndex(ParseIntError),
     /// mesh face index out of bounds
     FaceIndexOutOfBounds,
+    /// invalid keyframe time, expected `@seconds`
+    InvalidKeyframeTime,
+    /// keyframe times must increase
+    UnorderedKeyframes,
+    /// directional light without a `dir`
+    NoDirection,
+    /// binary data not found
+    UnknownBlob,
+    /// binary data doesn't match the counts of vertices and faces
+    BlobSize,
+}
+
+#[derive(Debug, displaydoc::Display)]
+enum WarningKind {
+    /// radius is not positive
+    NonPositiveRadius,
+    /// normal is zero
+    ZeroNormal,
+    /// axis is zero
+    ZeroAxis,
+    /// focus is not positive
+    NonPositiveFocus,
+    /// dimensions are not positive
+    NonPositiveDim,
+    /// camera looks along its `up` vector, picking another one
+    DegenerateCamera,
+    /// camera looks at its own position, looking along z instead
+    CameraLooksAtItself,
+    /// ambient is negative
+    NegativeAmbient,
+    /// diffuse is negative
+    NegativeDiffuse,
+    /// specular is negative
+    NegativeSpecular,
+    /// reflectance is outside of 0..=1
+    ReflectanceOutOfRange,
+    /// roughness is outside of 0..=1
+    RoughnessOutOfRange,
+    /// at least one sample per pixel is needed, using 1
+    ZeroSamples,
+    /// scene has no camera
+    NoCamera,
 }
 
 #[derive(Debug, displaydoc::Display)]
@@ -53,72 +114,365 @@ enum ParseColorError {
 pub(crate) fn parse<'m, 'i>(
     mem: &mut Mem<'m>,
     input: &'i str,
+    blobs: &Blobs<'_>,
+    warn: &mut dyn FnMut(Warning<'i>),
 ) -> Result<Scene<'m>, ParseSceneError<'i>> {
     let mut n_spheres = 0;
     let mut n_planes = 0;
+    let mut n_tori = 0;
+    let mut n_quadrics = 0;
     let mut n_meshes = 0;
+    let mut n_curves = 0;
+    let mut n_lights = 0;
+    let mut n_keys = 0;
+    let mut n_names = 0;
     for word in input.split_ascii_whitespace() {
         match word {
+            "light" | "directional_light" => n_lights += 1,
             "sphere" => n_spheres += 1,
             "plane" => n_planes += 1,
+            "torus" => n_tori += 1,
+            "quadric" => n_quadrics += 1,
             "mesh" => n_meshes += 1,
+            "curves" => n_curves += 1,
+            "name" => n_names += 1,
+            _ if word.starts_with('@') => n_keys += 1,
             _ => (),
         }
     }
 
-    let spheres = mem
-        .alloc_array_default(n_spheres)
-        .map_err(|oom| ParseSceneError { kind: ErrorKind::Oom(oom), context: [""; 4] })?;
-    let planes = mem
-        .alloc_array_default(n_planes)
-        .map_err(|oom| ParseSceneError { kind: ErrorKind::Oom(oom), context: [""; 4] })?;
-    let meshes = mem
-        .alloc_array_default(n_meshes)
-        .map_err(|oom| ParseSceneError { kind: ErrorKind::Oom(oom), context: [""; 4] })?;
+    let oom = |oom| ParseSceneError { kind: ErrorKind::Oom(oom), context: [""; 4], line: None };
+    let lights = mem.alloc_array_default(n_lights).map_err(oom)?;
+    let spheres = mem.alloc_array_default(n_spheres).map_err(oom)?;
+    let planes = mem.alloc_array_default(n_planes).map_err(oom)?;
+    let tori = mem.alloc_array_default(n_tori).map_err(oom)?;
+    let quadrics = mem.alloc_array_default(n_quadrics).map_err(oom)?;
+    let meshes = mem.alloc_array_default(n_meshes).map_err(oom)?;
+    let curves = mem.alloc_array_default(n_curves).map_err(oom)?;
+    // Each track has at least one `@` key, so this is an upper bound.
+    let tracks = mem.alloc_array_default(n_keys).map_err(oom)?;
+    let names = mem.alloc_array_default(n_names).map_err(oom)?;
     let mut res = Scene {
         background: Default::default(),
         foreground: Default::default(),
+        ambient: Color::new(1.0, 1.0, 1.0),
         camera: Default::default(),
-        light: Default::default(),
+        lights,
+        settings: Default::default(),
         spheres,
         planes,
+        tori,
+        quadrics,
         meshes,
+        curves,
+        tracks: &mut [],
+        names: &mut [],
+        clip: None,
     };
-    let mut p = Parser::new(mem, input);
-    match scene(&mut p, &mut res) {
-        Ok(()) => Ok(res),
-        Err(kind) => Err(ParseSceneError { kind, context: p.context }),
+    let mut p = Parser::new(mem, input, warn, tracks, names);
+    match scene(&mut p, blobs, &mut res) {
+        Ok(()) => {
+            let n_tracks = p.n_tracks;
+            res.tracks = &mut core::mem::take(&mut p.tracks)[..n_tracks];
+            let n_names = p.n_names;
+            res.names = &mut core::mem::take(&mut p.names)[..n_names];
+            Ok(res)
+        }
+        Err(kind) => Err(ParseSceneError { kind, context: p.context, line: Some(p.line()) }),
     }
 }
 
-impl<'a> fmt::Display for ParseSceneError<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "in {}", self.context[0])?;
-        for &ctx in &self.context[1..] {
-            if ctx.is_empty() {
-                break;
+/// Upper bound on the memory [`parse`] allocates for `input`. Reports the
+/// number of faces of each mesh to `mesh_faces` and of segments of each set
+/// of curves to `curve_segments`, so that the caller can account for the
+/// BVHs.
+pub(crate) fn mem_estimate(
+    input: &str,
+    mesh_faces: &mut dyn FnMut(usize),
+    curve_segments: &mut dyn FnMut(usize),
+) -> usize {
+    let mut res = 0;
+    let mut n_spheres = 0;
+    let mut n_planes = 0;
+    let mut n_tori = 0;
+    let mut n_quadrics = 0;
+    let mut n_meshes = 0;
+    let mut n_curves = 0;
+    let mut n_lights = 0;
+    let mut n_names = 0;
+    let mut mesh: Option<MeshEstimate> = None;
+    let mut curves: Option<usize> = None;
+    let mut prev = "";
+    for word in input.split_ascii_whitespace() {
+        match prev {
+            "group" | "name" => res += word.len(),
+            "subdivide" => {
+                if let Some(mesh) = &mut mesh {
+                    let levels = word.parse().unwrap_or(0);
+                    mesh.levels = mesh.levels.max(levels.min(MAX_SUBDIVISIONS));
+                }
+            }
+            // The counts of `data_bin`, the `v` and `f` themselves are
+            // counted as a vertex and a face below.
+            "v" | "f" if word.bytes().all(|it| it.is_ascii_digit()) => {
+                let n = word.parse::<u32>().map_or(0, |it| it as usize);
+                if let Some(mesh) = &mut mesh {
+                    if prev == "v" {
+                        // And the normals computed for them.
+                        res += 2 * array_size::<v64>(n);
+                        mesh.vertices += n;
+                    } else {
+                        res += array_size::<MeshFace>(n);
+                        mesh.faces += n;
+                    }
+                }
+            }
+            "simplify" => {
+                if let Some(mesh) = &mut mesh {
+                    let ratio = word.parse().ok().filter(|it| (0.0..=1.0).contains(it));
+                    if let Some(ratio) = ratio {
+                        mesh.simplify = Some(mesh.simplify.unwrap_or(0.0).max(ratio));
+                    }
+                }
             }
-            write!(f, ".{}", ctx)?
+            _ => (),
         }
+        prev = word;
+        match word {
+            "light" | "directional_light" => n_lights += 1,
+            "sphere" => n_spheres += 1,
+            "plane" => n_planes += 1,
+            "torus" => n_tori += 1,
+            "quadric" => n_quadrics += 1,
+            "name" => n_names += 1,
+            "mesh" | "curves" => {
+                if let Some(mesh) = mesh.take() {
+                    res += mesh.finish(mesh_faces);
+                }
+                if let Some(n) = curves.take() {
+                    curve_segments(n);
+                }
+                if word == "mesh" {
+                    n_meshes += 1;
+                    mesh = Some(MeshEstimate::default());
+                } else {
+                    n_curves += 1;
+                    curves = Some(0);
+                }
+            }
+            "c" => {
+                res += array_size::<[v64; 4]>(1);
+                if let Some(n) = &mut curves {
+                    *n += 1;
+                }
+            }
+            "v" => {
+                res += array_size::<v64>(1);
+                if let Some(mesh) = &mut mesh {
+                    mesh.vertices += 1;
+                }
+            }
+            "vn" => res += array_size::<v64>(1),
+            "displace" => {
+                if let Some(mesh) = &mut mesh {
+                    mesh.displaced = true;
+                }
+            }
+            // A key, maybe the initial value and maybe a new track.
+            _ if word.starts_with('@') => {
+                res += 2 * size_of::<Keyframe>() + array_size::<Track<'_>>(1)
+            }
+            "f" => {
+                res += array_size::<MeshFace>(1);
+                if let Some(mesh) = &mut mesh {
+                    mesh.faces += 1;
+                }
+            }
+            _ => (),
+        }
+    }
+    if let Some(mesh) = mesh {
+        res += mesh.finish(mesh_faces);
+    }
+    if let Some(n) = curves {
+        curve_segments(n);
+    }
+    // Each mesh allocates three arrays, account for their alignment.
+    res += n_meshes * 3 * align_of::<v64>();
+    res += n_curves * align_of::<v64>();
+    res + array_size::<Light<'_>>(n_lights)
+        + array_size::<Sphere>(n_spheres)
+        + array_size::<Plane>(n_planes)
+        + array_size::<Torus>(n_tori)
+        + array_size::<Quadric>(n_quadrics)
+        + array_size::<Mesh<'_>>(n_meshes)
+        + array_size::<Curves<'_>>(n_curves)
+        + array_size::<Name<'_>>(n_names)
+}
+
+/// The part of a mesh [`mem_estimate`] can't account for word by word.
+#[derive(Default)]
+struct MeshEstimate {
+    vertices: usize,
+    faces: usize,
+    levels: u32,
+    displaced: bool,
+    /// The largest `simplify` ratio.
+    simplify: Option<f64>,
+}
+
+impl MeshEstimate {
+    /// Reports the faces of the final mesh and returns the memory for
+    /// simplifying and subdividing it and for recomputing its normals.
+    fn finish(self, mesh_faces: &mut dyn FnMut(usize)) -> usize {
+        let mut res = 0;
+        let (mut vertices, mut faces) = (self.vertices, self.faces);
+        if let Some(ratio) = self.simplify {
+            res += simplify::mem_estimate(vertices, faces);
+            faces = simplify::target(faces, ratio);
+        }
+        for _ in 0..self.levels {
+            // Each face has three edges, so that many new vertices at most.
+            res += array_size::<[u32; 3]>(3 * faces)
+                + array_size::<u32>(3 * faces)
+                + 2 * array_size::<v64>(vertices)
+                + 2 * array_size::<u32>(vertices)
+                + array_size::<v64>(vertices + 3 * faces)
+                + array_size::<MeshFace>(4 * faces);
+            vertices += 3 * faces;
+            faces *= 4;
+        }
+        let smoothed = self.levels > 0 || self.simplify.is_some();
+        let normals = usize::from(smoothed) + usize::from(self.displaced);
+        res += normals * array_size::<v64>(vertices);
+        mesh_faces(faces);
+        res
+    }
+}
+
+fn array_size<T>(n: usize) -> usize {
+    n * size_of::<T>() + align_of::<T>() - 1
+}
+
+impl<'a> ParseSceneError<'a> {
+    /// Line of the scene file where the error was detected.
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+
+    /// Whether the scene is fine, but doesn't fit into the memory given.
+    pub fn is_oom(&self) -> bool {
+        matches!(self.kind, ErrorKind::Oom(_))
+    }
+}
+
+impl<'a> Warning<'a> {
+    /// Line of the scene file the warning is about.
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+}
+
+impl<'a> fmt::Display for ParseSceneError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_location(f, self.line, &self.context)?;
+        write!(f, ": {}", self.kind)
+    }
+}
+
+impl<'a> fmt::Display for Warning<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_location(f, self.line, &self.context)?;
         write!(f, ": {}", self.kind)
     }
 }
 
+fn fmt_location(f: &mut fmt::Formatter<'_>, line: Option<u32>, context: &[&str; 4]) -> fmt::Result {
+    if let Some(line) = line {
+        write!(f, "line {line}, ")?;
+    }
+    write!(f, "in {}", context[0])?;
+    for &ctx in &context[1..] {
+        if ctx.is_empty() {
+            break;
+        }
+        write!(f, ".{}", ctx)?
+    }
+    Ok(())
+}
+
 struct Parser<'m, 'i, 'a> {
     mem: &'a mut Mem<'m>,
+    warn: &'a mut dyn FnMut(Warning<'i>),
+    /// The first `n_tracks` are parsed, the rest are spare.
+    tracks: &'m mut [Track<'m>],
+    n_tracks: usize,
+    /// Likewise, the first `n_names`.
+    names: &'m mut [Name<'m>],
+    n_names: usize,
+    input: &'i str,
+    last: &'i str,
     context: [&'i str; 4],
     depth: usize,
     words: Peekable<SplitAsciiWhitespace<'i>>,
 }
 
 impl<'m, 'i, 'a> Parser<'m, 'i, 'a> {
-    fn new(mem: &'a mut Mem<'m>, input: &'i str) -> Parser<'m, 'i, 'a> {
-        Parser { mem, context: [""; 4], depth: 0, words: input.split_ascii_whitespace().peekable() }
+    fn new(
+        mem: &'a mut Mem<'m>,
+        input: &'i str,
+        warn: &'a mut dyn FnMut(Warning<'i>),
+        tracks: &'m mut [Track<'m>],
+        names: &'m mut [Name<'m>],
+    ) -> Parser<'m, 'i, 'a> {
+        let words = input.split_ascii_whitespace().peekable();
+        Parser {
+            mem,
+            warn,
+            tracks,
+            n_tracks: 0,
+            names,
+            n_names: 0,
+            input,
+            last: &input[..0],
+            context: [""; 4],
+            depth: 0,
+            words,
+        }
+    }
+    fn add_track(&mut self, track: Track<'m>) {
+        self.tracks[self.n_tracks] = track;
+        self.n_tracks += 1;
+    }
+    /// Reads the `name` of an object, which replaces the one given before.
+    fn name(&mut self, kind: ObjectKind, index: u32) -> Result<(), ErrorKind> {
+        let name = self.next()?;
+        let copy = self.mem.alloc_array_default(name.len()).map_err(ErrorKind::Oom)?;
+        copy.copy_from_slice(name.as_bytes());
+        let name = Name { kind, index, name: core::str::from_utf8(copy).unwrap() };
+        match self.names[..self.n_names].last_mut() {
+            Some(last) if (last.kind, last.index) == (kind, index) => *last = name,
+            _ => {
+                self.names[self.n_names] = name;
+                self.n_names += 1;
+            }
+        }
+        Ok(())
     }
     fn next(&mut self) -> Result<&'i str, ErrorKind> {
         let res = self.words.next().ok_or(ErrorKind::UnexpectedEof)?;
+        self.last = res;
         Ok(res)
     }
+    /// Line of the last token consumed.
+    fn line(&self) -> u32 {
+        let offset = self.last.as_ptr() as usize - self.input.as_ptr() as usize;
+        self.input[..offset].bytes().filter(|&it| it == b'\n').count() as u32 + 1
+    }
+    fn warn(&mut self, kind: WarningKind) {
+        let warning = Warning { kind, context: self.context, line: Some(self.line()) };
+        (self.warn)(warning)
+    }
     fn at(&mut self, token: &'static str) -> bool {
         self.words.peek() == Some(&token)
     }
@@ -147,24 +501,81 @@ impl<'m, 'i, 'a> Parser<'m, 'i, 'a> {
     }
 }
 
-fn scene<'m, 'i>(p: &mut Parser<'m, 'i, '_>, res: &mut Scene<'m>) -> Result<(), ErrorKind> {
+fn scene<'m, 'i>(
+    p: &mut Parser<'m, 'i, '_>,
+    blobs: &Blobs<'_>,
+    res: &mut Scene<'m>,
+) -> Result<(), ErrorKind> {
     p.push("scene");
-    let mut spheres = res.spheres.iter_mut();
-    let mut planes = res.planes.iter_mut();
-    let mut meshes = res.meshes.iter_mut();
+    let mut spheres = res.spheres.iter_mut().enumerate();
+    let mut planes = res.planes.iter_mut().enumerate();
+    let mut tori = res.tori.iter_mut().enumerate();
+    let mut quadrics = res.quadrics.iter_mut().enumerate();
+    let mut meshes = res.meshes.iter_mut().enumerate();
+    let mut curves = res.curves.iter_mut().enumerate();
+    let mut lights = res.lights.iter_mut().enumerate();
+    let mut n_lights = 0;
+    let mut has_camera = false;
     while let Ok(w) = p.push_next() {
         match w {
             "background" => res.background = color(p)?,
             "foreground" => res.foreground = color(p)?,
-            "camera" => camera(p, &mut res.camera)?,
-            "sphere" => sphere(p, spheres.next().unwrap())?,
-            "plane" => plane(p, planes.next().unwrap())?,
-            "mesh" => mesh(p, meshes.next().unwrap())?,
-            "light" => light(p, &mut res.light)?,
+            "ambient" => res.ambient = color(p)?,
+            "camera" => {
+                camera(p, &mut res.camera)?;
+                has_camera = true;
+            }
+            "sphere" => {
+                let (i, res) = spheres.next().unwrap();
+                sphere(p, i as u32, res)?
+            }
+            "plane" => {
+                let (i, res) = planes.next().unwrap();
+                plane(p, i as u32, res)?
+            }
+            "torus" => {
+                let (i, res) = tori.next().unwrap();
+                torus(p, i as u32, res)?
+            }
+            "quadric" => {
+                let (i, res) = quadrics.next().unwrap();
+                quadric(p, i as u32, res)?
+            }
+            "clip" => res.clip = Some(clip(p)?),
+            "mesh" => {
+                let (i, res) = meshes.next().unwrap();
+                mesh(p, i as u32, blobs, res)?
+            }
+            "curves" => {
+                let (i, res) = curves.next().unwrap();
+                self::curves(p, i as u32, res)?
+            }
+            "light" | "directional_light" => {
+                let (i, res) = lights.next().unwrap();
+                if w == "directional_light" {
+                    res.kind = LightKind::Directional { dir: v64::ZERO };
+                }
+                light(p, i as u32, res)?;
+                n_lights += 1;
+            }
+            "settings" => settings(p, &mut res.settings)?,
             _ => Err(ErrorKind::InvalidKey)?,
         }
         p.pop();
     }
+    if !has_camera {
+        (p.warn)(Warning { kind: WarningKind::NoCamera, context: p.context, line: None });
+    }
+    let unused =
+        [spheres.len(), planes.len(), tori.len(), quadrics.len(), meshes.len(), curves.len()];
+    // Some of the counted words may have been names of groups or objects.
+    res.lights = &mut core::mem::take(&mut res.lights)[..n_lights];
+    truncate(&mut res.spheres, unused[0]);
+    truncate(&mut res.planes, unused[1]);
+    truncate(&mut res.tori, unused[2]);
+    truncate(&mut res.quadrics, unused[3]);
+    truncate(&mut res.meshes, unused[4]);
+    truncate(&mut res.curves, unused[5]);
     p.pop();
     Ok(())
 }
@@ -178,7 +589,7 @@ fn color<'m, 'i>(p: &mut Parser<'m, 'i, '_>) -> Result<Color, ErrorKind> {
         u8::from_str_radix(it, 16)
             .map_err(|err| ErrorKind::ParseColorError(ParseColorError::InvalidColorValue(err)))
     });
-    let [r, g, b] = [r?, g?, b?].map(|it| (it as f64) / 255.0);
+    let [r, g, b] = [r?, g?, b?].map(|it| color::srgb_to_linear((it as f64) / 255.0));
     let color = Color::new(r, g, b);
     Ok(color)
 }
@@ -187,26 +598,67 @@ fn camera<'m, 'i>(p: &mut Parser<'m, 'i, '_>, res: &mut Camera) -> Result<(), Er
     p.expect("{")?;
     while !p.at("}") {
         match p.push_next()? {
-            "pos" => res.pos = vector(p)?,
-            "look_at" => res.look_at = vector(p)?,
+            "pos" => res.pos = animated(p, Target::CameraPos)?,
+            "look_at" => res.look_at = animated(p, Target::CameraLookAt)?,
             "up" => res.up = vector(p)?,
-            "focus" => res.focus = scalar(p)?,
-            "dim" => (res.width, res.height) = dim(p)?,
+            "focus" => {
+                res.focus = scalar(p)?;
+                if res.focus <= 0.0 {
+                    p.warn(WarningKind::NonPositiveFocus)
+                }
+            }
+            "dim" => {
+                (res.width, res.height) = dim(p)?;
+                if res.width <= 0.0 || res.height <= 0.0 {
+                    p.warn(WarningKind::NonPositiveDim)
+                }
+            }
+            "aperture" => {
+                res.aperture =
+                    Some(finite(p)?).filter(|&it| it >= 0.0).ok_or(ErrorKind::InvalidValue)?
+            }
+            "focal_distance" => {
+                res.focal_distance =
+                    Some(Some(finite(p)?).filter(|&it| it > 0.0).ok_or(ErrorKind::InvalidValue)?)
+            }
+            "shutter" => {
+                res.shutter =
+                    Some(finite(p)?).filter(|&it| it >= 0.0).ok_or(ErrorKind::InvalidValue)?
+            }
             _ => Err(ErrorKind::InvalidKey)?,
         }
         p.pop();
     }
     p.expect("}")?;
+    if res.look_at == res.pos {
+        p.warn(WarningKind::CameraLooksAtItself)
+    } else if cross(res.look_at - res.pos, res.up) == v64::ZERO {
+        p.warn(WarningKind::DegenerateCamera)
+    }
     Ok(())
 }
 
-fn sphere<'m, 'i>(p: &mut Parser<'m, 'i, '_>, res: &mut Sphere) -> Result<(), ErrorKind> {
+/// Drops the `unused` objects at the end.
+fn truncate<T>(objects: &mut &mut [T], unused: usize) {
+    let all = core::mem::take(objects);
+    let n = all.len() - unused;
+    *objects = &mut all[..n];
+}
+
+fn sphere<'m, 'i>(p: &mut Parser<'m, 'i, '_>, idx: u32, res: &mut Sphere) -> Result<(), ErrorKind> {
     p.expect("{")?;
     while !p.at("}") {
         match p.push_next()? {
-            "pos" => res.center = vector(p)?,
-            "radius" => res.radius = scalar(p)?,
+            "pos" => res.center = animated(p, Target::SpherePos(idx))?,
+            "radius" => {
+                res.radius = scalar(p)?;
+                if res.radius <= 0.0 {
+                    p.warn(WarningKind::NonPositiveRadius)
+                }
+            }
+            "velocity" => res.velocity = vector(p)?,
             "material" => material(p, &mut res.material)?,
+            "name" => p.name(ObjectKind::Sphere, idx)?,
             _ => Err(ErrorKind::InvalidKey)?,
         }
         p.pop()
@@ -214,15 +666,21 @@ fn sphere<'m, 'i>(p: &mut Parser<'m, 'i, '_>, res: &mut Sphere) -> Result<(), Er
     p.expect("}")
 }
 
-fn plane<'m, 'i>(p: &mut Parser<'m, 'i, '_>, res: &mut Plane) -> Result<(), ErrorKind> {
+fn plane<'m, 'i>(p: &mut Parser<'m, 'i, '_>, idx: u32, res: &mut Plane) -> Result<(), ErrorKind> {
     p.expect("{")?;
     let mut pos = v64::ZERO;
     let mut dir = v64(0.0, 0.0, 1.0);
     while !p.at("}") {
         match p.push_next()? {
             "pos" => pos = vector(p)?,
-            "normal" => dir = vector(p)?,
+            "normal" => {
+                dir = vector(p)?;
+                if dir == v64::ZERO {
+                    p.warn(WarningKind::ZeroNormal)
+                }
+            }
             "material" => material(p, &mut res.material)?,
+            "name" => p.name(ObjectKind::Plane, idx)?,
             _ => Err(ErrorKind::InvalidKey)?,
         }
         p.pop()
@@ -231,11 +689,121 @@ fn plane<'m, 'i>(p: &mut Parser<'m, 'i, '_>, res: &mut Plane) -> Result<(), Erro
     p.expect("}")
 }
 
-fn mesh<'m, 'i>(p: &mut Parser<'m, 'i, '_>, res: &mut Mesh<'m>) -> Result<(), ErrorKind> {
+fn torus<'m, 'i>(p: &mut Parser<'m, 'i, '_>, idx: u32, res: &mut Torus) -> Result<(), ErrorKind> {
+    p.expect("{")?;
+    while !p.at("}") {
+        match p.push_next()? {
+            "pos" => res.center = vector(p)?,
+            "axis" => {
+                let axis = vector(p)?;
+                if axis == v64::ZERO {
+                    p.warn(WarningKind::ZeroAxis)
+                }
+                res.axis = axis.to_unit();
+            }
+            "radius" => {
+                res.radius = scalar(p)?;
+                if res.radius <= 0.0 {
+                    p.warn(WarningKind::NonPositiveRadius)
+                }
+            }
+            "tube" => {
+                res.tube = scalar(p)?;
+                if res.tube <= 0.0 {
+                    p.warn(WarningKind::NonPositiveRadius)
+                }
+            }
+            "material" => material(p, &mut res.material)?,
+            "name" => p.name(ObjectKind::Torus, idx)?,
+            _ => Err(ErrorKind::InvalidKey)?,
+        }
+        p.pop()
+    }
+    p.expect("}")
+}
+
+fn quadric<'m, 'i>(
+    p: &mut Parser<'m, 'i, '_>,
+    idx: u32,
+    res: &mut Quadric,
+) -> Result<(), ErrorKind> {
     p.expect("{")?;
     while !p.at("}") {
         match p.push_next()? {
+            "pos" => res.center = vector(p)?,
+            "square" => res.square = vector(p)?,
+            "mixed" => res.mixed = vector(p)?,
+            "linear" => res.linear = vector(p)?,
+            "constant" => res.constant = scalar(p)?,
             "material" => material(p, &mut res.material)?,
+            "name" => p.name(ObjectKind::Quadric, idx)?,
+            _ => Err(ErrorKind::InvalidKey)?,
+        }
+        p.pop()
+    }
+    p.expect("}")
+}
+
+fn clip<'m, 'i>(p: &mut Parser<'m, 'i, '_>) -> Result<Clip, ErrorKind> {
+    p.expect("{")?;
+    let mut pos = v64::ZERO;
+    let mut dir = v64(0.0, 0.0, 1.0);
+    let mut cap = None;
+    while !p.at("}") {
+        match p.push_next()? {
+            "pos" => pos = vector(p)?,
+            "normal" => {
+                dir = vector(p)?;
+                if dir == v64::ZERO {
+                    p.warn(WarningKind::ZeroNormal)
+                }
+            }
+            "cap" => material(p, cap.insert(Material::default()))?,
+            _ => Err(ErrorKind::InvalidKey)?,
+        }
+        p.pop()
+    }
+    p.expect("}")?;
+    Ok(Clip { normal: Ray::new(pos, dir), cap })
+}
+
+/// Each step quadruples the faces, so a few are plenty.
+const MAX_SUBDIVISIONS: u32 = 6;
+
+/// Moves the vertices of a mesh along their normals by `amplitude` times the
+/// noise at `frequency` times their position.
+struct Displacement {
+    noise: Noise,
+    amplitude: f64,
+    frequency: f64,
+}
+
+fn mesh<'m, 'i>(
+    p: &mut Parser<'m, 'i, '_>,
+    idx: u32,
+    blobs: &Blobs<'_>,
+    res: &mut Mesh<'m>,
+) -> Result<(), ErrorKind> {
+    let mut displacement = None;
+    let mut levels = 0;
+    let mut ratio = None;
+    p.expect("{")?;
+    while !p.at("}") {
+        match p.push_next()? {
+            "material" => material(p, &mut res.material)?,
+            "name" => p.name(ObjectKind::Mesh, idx)?,
+            "displace" => displacement = Some(displace(p)?),
+            "subdivide" => {
+                levels = integer(p)?;
+                if levels > MAX_SUBDIVISIONS {
+                    Err(ErrorKind::InvalidValue)?
+                }
+            }
+            "simplify" => {
+                ratio = Some(Some(unit(p)?).filter(|&it| it > 0.0).ok_or(ErrorKind::InvalidValue)?)
+            }
+            "translate" => res.offset = animated(p, Target::MeshOffset(idx))?,
+            "velocity" => res.velocity = vector(p)?,
             "data" => {
                 p.expect("{")?;
 
@@ -271,13 +839,316 @@ fn mesh<'m, 'i>(p: &mut Parser<'m, 'i, '_>, res: &mut Mesh<'m>) -> Result<(), Er
 
                 p.expect("}")?;
             }
+            "data_bin" => {
+                let name = blob_name(p.next()?).ok_or(ErrorKind::InvalidValue)?;
+                p.expect("v")?;
+                let n_v = integer(p)? as usize;
+                p.expect("f")?;
+                let n_f = integer(p)? as usize;
+                let mut loaded = None;
+                blobs(name, &mut |blob| loaded = Some(mesh_blob(p.mem, blob, n_v, n_f, res)));
+                loaded.ok_or(ErrorKind::UnknownBlob)??;
+            }
             _ => Err(ErrorKind::InvalidKey)?,
         }
         p.pop()
     }
+    if let Some(ratio) = ratio {
+        simplify::simplify(p.mem, res, ratio).map_err(ErrorKind::Oom)?;
+    }
+    if levels > 0 || displacement.is_some() || ratio.is_some() {
+        orient_faces(res);
+    }
+    for _ in 0..levels {
+        subdivide(p.mem, res)?;
+    }
+    if levels > 0 || ratio.is_some() {
+        let normals = p.mem.alloc_array_default(res.v.len()).map_err(ErrorKind::Oom)?;
+        smooth_normals(res, normals);
+    }
+    if let Some(displacement) = displacement {
+        displace_mesh(p.mem, &displacement, res)?;
+    }
     p.expect("}")
 }
 
+/// `"name"`, quoted.
+fn blob_name(word: &str) -> Option<&str> {
+    word.strip_prefix('"')?.strip_suffix('"')
+}
+
+pub(crate) fn blobs(input: &str) -> impl Iterator<Item = &str> {
+    let mut words = input.split_ascii_whitespace();
+    core::iter::from_fn(move || {
+        words.by_ref().find(|&it| it == "data_bin")?;
+        Some(words.next().and_then(blob_name))
+    })
+    .flatten()
+}
+
+/// Binary mesh data: `n_v` vertices as three little-endian `f32` each, then
+/// `n_f` faces as three zero-based little-endian `u32` vertex indices each.
+/// There are no normals, they are computed from the winding of the faces.
+fn mesh_blob<'m>(
+    mem: &mut Mem<'m>,
+    blob: &[u8],
+    n_v: usize,
+    n_f: usize,
+    res: &mut Mesh<'m>,
+) -> Result<(), ErrorKind> {
+    if n_v.checked_add(n_f).and_then(|it| it.checked_mul(12)) != Some(blob.len()) {
+        Err(ErrorKind::BlobSize)?
+    }
+    let (vertices, faces) = blob.split_at(12 * n_v);
+    let words = |bytes: &[u8]| -> [[u8; 4]; 3] {
+        [0, 1, 2].map(|k| bytes[4 * k..][..4].try_into().unwrap())
+    };
+    res.v = mem.alloc_array_default(n_v).map_err(ErrorKind::Oom)?;
+    for (v, bytes) in res.v.iter_mut().zip(vertices.chunks_exact(12)) {
+        let [x, y, z] = words(bytes).map(|it| f64::from(f32::from_le_bytes(it)));
+        *v = v64(x, y, z);
+    }
+    res.f = mem.alloc_array_default(n_f).map_err(ErrorKind::Oom)?;
+    for (f, bytes) in res.f.iter_mut().zip(faces.chunks_exact(12)) {
+        f.v = words(bytes).map(u32::from_le_bytes);
+        if f.v.iter().any(|&it| it as usize >= n_v) {
+            Err(ErrorKind::FaceIndexOutOfBounds)?
+        }
+    }
+    let normals = mem.alloc_array_default(n_v).map_err(ErrorKind::Oom)?;
+    smooth_normals(res, normals);
+    Ok(())
+}
+
+fn displace<'m, 'i>(p: &mut Parser<'m, 'i, '_>) -> Result<Displacement, ErrorKind> {
+    let mut res = Displacement { noise: Noise::Fbm, amplitude: 0.1, frequency: 1.0 };
+    p.expect("{")?;
+    while !p.at("}") {
+        match p.push_next()? {
+            "noise" => res.noise = Noise::from_name(p.next()?).ok_or(ErrorKind::InvalidValue)?,
+            "amplitude" => res.amplitude = scalar(p)?,
+            "frequency" => res.frequency = scalar(p)?,
+            _ => Err(ErrorKind::InvalidKey)?,
+        }
+        p.pop()
+    }
+    p.expect("}")?;
+    Ok(res)
+}
+
+/// Displaces the vertices along the average of the normals of their corners,
+/// then replaces the normals with ones computed from the displaced faces.
+fn displace_mesh<'m>(
+    mem: &mut Mem<'m>,
+    displacement: &Displacement,
+    mesh: &mut Mesh<'m>,
+) -> Result<(), ErrorKind> {
+    let normals: &mut [v64] = mem.alloc_array_default(mesh.v.len()).map_err(ErrorKind::Oom)?;
+    for face in mesh.f.iter() {
+        for (v, n) in face.v.into_iter().zip(face.n) {
+            normals[v as usize] = normals[v as usize] + mesh.n[n as usize].to_unit();
+        }
+    }
+    for (v, n) in mesh.v.iter_mut().zip(normals.iter()) {
+        let noise = displacement.noise.at(*v * displacement.frequency);
+        let offset = n.to_unit() * (displacement.amplitude * noise);
+        // Vertices without faces or with opposite normals stay put.
+        if offset.xyz().iter().all(|it| it.is_finite()) {
+            *v = *v + offset;
+        }
+    }
+    smooth_normals(mesh, normals);
+    Ok(())
+}
+
+/// Flips the faces whose winding disagrees with the normals of their corners,
+/// so that normals can be recomputed from the winding alone.
+fn orient_faces(mesh: &mut Mesh<'_>) {
+    for face in mesh.f.iter_mut() {
+        let [a, b, c] = face.v.map(|it| mesh.v[it as usize]);
+        let given = face.n.iter().fold(v64::ZERO, |acc, &it| acc + mesh.n[it as usize]);
+        if dot(cross(b - a, c - a), given) < 0.0 {
+            face.v.swap(1, 2);
+            face.n.swap(1, 2);
+        }
+    }
+}
+
+/// One step of Loop subdivision: splits each face in four, with new vertices
+/// on the edges, and moves the old vertices towards their neighbors, so that
+/// repeated steps converge to a smooth surface. Edges with one face, or more
+/// than two, stay sharp creases.
+///
+/// Leaves the faces pointing at normals which don't exist yet, for
+/// [`smooth_normals`] to fill in.
+fn subdivide<'m>(mem: &mut Mem<'m>, mesh: &mut Mesh<'m>) -> Result<(), ErrorKind> {
+    let n_v = mesh.v.len();
+    let n_f = mesh.f.len();
+    // `[lo, hi, corner]` for the edge after each corner of each face, sorted
+    // so that the faces sharing an edge are next to each other.
+    let edges: &mut [[u32; 3]] = mem.alloc_array_default(3 * n_f).map_err(ErrorKind::Oom)?;
+    for (corner, edge) in edges.iter_mut().enumerate() {
+        let v = mesh.f[corner / 3].v;
+        let (a, b) = (v[corner % 3], v[(corner + 1) % 3]);
+        *edge = [a.min(b), a.max(b), corner as u32];
+    }
+    edges.sort_unstable();
+    let same_edge = |l: &[u32; 3], r: &[u32; 3]| l[..2] == r[..2];
+    let n_e = edges.chunk_by(same_edge).count();
+
+    let v: &mut [v64] = mem.alloc_array_default(n_v + n_e).map_err(ErrorKind::Oom)?;
+    let edge_vertex: &mut [u32] = mem.alloc_array_default(3 * n_f).map_err(ErrorKind::Oom)?;
+    let sums: &mut [v64] = mem.alloc_array_default(n_v).map_err(ErrorKind::Oom)?;
+    let crease_sums: &mut [v64] = mem.alloc_array_default(n_v).map_err(ErrorKind::Oom)?;
+    let valences: &mut [u32] = mem.alloc_array_default(n_v).map_err(ErrorKind::Oom)?;
+    let crease_valences: &mut [u32] = mem.alloc_array_default(n_v).map_err(ErrorKind::Oom)?;
+
+    let opposite = |corner: u32| {
+        let corner = corner as usize;
+        mesh.v[mesh.f[corner / 3].v[(corner + 2) % 3] as usize]
+    };
+    for (e, group) in edges.chunk_by(same_edge).enumerate() {
+        let [a, b, _] = group[0].map(|it| it as usize);
+        let (va, vb) = (mesh.v[a], mesh.v[b]);
+        let crease = group.len() != 2;
+        v[n_v + e] = if crease {
+            (va + vb) * 0.5
+        } else {
+            (va + vb) * 0.375 + (opposite(group[0][2]) + opposite(group[1][2])) * 0.125
+        };
+        for &[.., corner] in group {
+            edge_vertex[corner as usize] = (n_v + e) as u32;
+        }
+        if a != b {
+            for (i, neighbor) in [(a, vb), (b, va)] {
+                sums[i] = sums[i] + neighbor;
+                valences[i] += 1;
+                if crease {
+                    crease_sums[i] = crease_sums[i] + neighbor;
+                    crease_valences[i] += 1;
+                }
+            }
+        }
+    }
+    for (i, &old) in mesh.v.iter().enumerate() {
+        v[i] = match (valences[i], crease_valences[i]) {
+            (0, _) => old,
+            (n, 0) => {
+                // Warren's weights.
+                let beta = if n == 3 { 3.0 / 16.0 } else { 3.0 / (8.0 * f64::from(n)) };
+                old * (1.0 - f64::from(n) * beta) + sums[i] * beta
+            }
+            (_, 2) => old * 0.75 + crease_sums[i] * 0.125,
+            // Where creases meet or end, the vertex is a corner.
+            _ => old,
+        };
+    }
+
+    let f: &mut [MeshFace] = mem.alloc_array_default(4 * n_f).map_err(ErrorKind::Oom)?;
+    for (i, (face, children)) in mesh.f.iter().zip(f.chunks_exact_mut(4)).enumerate() {
+        let [a, b, c] = face.v;
+        let [ab, bc, ca] = [0, 1, 2].map(|k| edge_vertex[3 * i + k]);
+        for (v, child) in
+            [[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]].into_iter().zip(children)
+        {
+            *child = MeshFace { v, n: v };
+        }
+    }
+    mesh.v = v;
+    mesh.f = f;
+    Ok(())
+}
+
+/// Replaces the normals of the mesh with ones computed from the winding of
+/// the faces, one per vertex, weighted by the area of the faces around it.
+fn smooth_normals<'m>(mesh: &mut Mesh<'m>, normals: &'m mut [v64]) {
+    normals.fill(v64::ZERO);
+    for face in mesh.f.iter() {
+        let [a, b, c] = face.v.map(|it| mesh.v[it as usize]);
+        let n = cross(b - a, c - a);
+        for v in face.v {
+            normals[v as usize] = normals[v as usize] + n;
+        }
+    }
+    for n in normals.iter_mut() {
+        if n.norm() > 0.0 {
+            *n = n.to_unit();
+        }
+    }
+    for face in mesh.f.iter_mut() {
+        face.n = face.v;
+    }
+    mesh.n = normals;
+}
+
+fn curves<'m, 'i>(
+    p: &mut Parser<'m, 'i, '_>,
+    idx: u32,
+    res: &mut Curves<'m>,
+) -> Result<(), ErrorKind> {
+    let mut bspline = false;
+    p.expect("{")?;
+    while !p.at("}") {
+        match p.push_next()? {
+            "material" => material(p, &mut res.material)?,
+            "name" => p.name(ObjectKind::Curves, idx)?,
+            "radius" => {
+                res.radius = scalar(p)?;
+                if res.radius <= 0.0 {
+                    p.warn(WarningKind::NonPositiveRadius)
+                }
+            }
+            "basis" => {
+                bspline = match p.next()? {
+                    "bezier" => false,
+                    "bspline" => true,
+                    _ => Err(ErrorKind::InvalidValue)?,
+                }
+            }
+            "data" => {
+                p.expect("{")?;
+                let mut words = p.words.clone();
+                let n_c =
+                    words.by_ref().take_while(|&it| it != "}").filter(|&it| it == "c").count();
+                res.segments = p.mem.alloc_array_default(n_c).map_err(ErrorKind::Oom)?;
+                let mut segments = res.segments.iter_mut();
+                while !p.at("}") {
+                    match p.push_next()? {
+                        "c" => {
+                            let segment = segments.next().unwrap();
+                            for point in segment.iter_mut() {
+                                *point = vector(p)?;
+                            }
+                        }
+                        _ => Err(ErrorKind::InvalidKey)?,
+                    }
+                    p.pop()
+                }
+                p.expect("}")?;
+            }
+            _ => Err(ErrorKind::InvalidKey)?,
+        }
+        p.pop()
+    }
+    if bspline {
+        for segment in res.segments.iter_mut() {
+            *segment = bspline_to_bezier(*segment);
+        }
+    }
+    p.expect("}")
+}
+
+/// The Bézier control points of a uniform cubic B-spline segment, so that
+/// consecutive segments sharing three control points join smoothly.
+fn bspline_to_bezier([p0, p1, p2, p3]: [v64; 4]) -> [v64; 4] {
+    [
+        (p0 + p1 * 4.0 + p2) / 6.0,
+        (p1 * 2.0 + p2) / 3.0,
+        (p1 + p2 * 2.0) / 3.0,
+        (p1 + p2 * 4.0 + p3) / 6.0,
+    ]
+}
+
 fn face<'m, 'i>(
     p: &mut Parser<'m, 'i, '_>,
     n_v: u32,
@@ -301,24 +1172,148 @@ fn face<'m, 'i>(
 }
 
 fn material<'m, 'i>(p: &mut Parser<'m, 'i, '_>, res: &mut Material) -> Result<(), ErrorKind> {
+    let mut emission = res.emission;
+    let mut strength = 1.0;
     p.expect("{")?;
     while !p.at("}") {
         match p.push_next()? {
             "color" => res.color = color(p)?,
-            "diffuse" => res.diffuse = scalar(p)?,
+            "ambient" => {
+                res.ambient = finite(p)?;
+                if res.ambient < 0.0 {
+                    p.warn(WarningKind::NegativeAmbient)
+                }
+            }
+            "diffuse" => {
+                res.diffuse = finite(p)?;
+                if res.diffuse < 0.0 {
+                    p.warn(WarningKind::NegativeDiffuse)
+                }
+            }
+            "specular" => {
+                res.specular = finite(p)?;
+                if res.specular < 0.0 {
+                    p.warn(WarningKind::NegativeSpecular)
+                }
+            }
+            "shininess" => {
+                res.shininess =
+                    Some(finite(p)?).filter(|&it| it >= 0.0).ok_or(ErrorKind::InvalidValue)?
+            }
+            "reflectance" => {
+                res.reflectance = scalar(p)?;
+                if !(0.0..=1.0).contains(&res.reflectance) {
+                    p.warn(WarningKind::ReflectanceOutOfRange)
+                }
+            }
+            "roughness" => {
+                res.roughness = scalar(p)?;
+                if !(0.0..=1.0).contains(&res.roughness) {
+                    p.warn(WarningKind::RoughnessOutOfRange)
+                }
+            }
+            "double_sided" => res.double_sided = boolean(p)?,
+            "visible" => res.visible = visibility(p)?,
+            "shadow_catcher" => res.shadow_catcher = boolean(p)?,
+            "emission" => emission = color(p)?,
+            // Colors stop at white, brighter lights need a factor.
+            "emission_strength" => {
+                strength =
+                    Some(finite(p)?).filter(|&it| it >= 0.0).ok_or(ErrorKind::InvalidValue)?
+            }
             _ => Err(ErrorKind::InvalidKey)?,
         }
         p.pop()
     }
+    res.emission = emission * strength;
     p.expect("}")
 }
 
-fn light<'m, 'i>(p: &mut Parser<'m, 'i, '_>, res: &mut Light) -> Result<(), ErrorKind> {
+fn light<'m, 'i>(
+    p: &mut Parser<'m, 'i, '_>,
+    idx: u32,
+    res: &mut Light<'m>,
+) -> Result<(), ErrorKind> {
     p.expect("{")?;
     while !p.at("}") {
         match p.push_next()? {
             "color" => res.color = color(p)?,
-            "pos" => res.pos = vector(p)?,
+            "pos" if matches!(res.kind, LightKind::Point) => {
+                res.pos = animated(p, Target::LightPos(idx))?
+            }
+            "dir" if matches!(res.kind, LightKind::Directional { .. }) => {
+                let dir = vector(p)?;
+                let dir = Some(dir.to_unit())
+                    .filter(|it| it.xyz().iter().all(|it| it.is_finite()))
+                    .ok_or(ErrorKind::InvalidValue)?;
+                res.kind = LightKind::Directional { dir };
+            }
+            "group" => {
+                let name = p.next()?;
+                let copy = p.mem.alloc_array_default(name.len()).map_err(ErrorKind::Oom)?;
+                copy.copy_from_slice(name.as_bytes());
+                res.group = core::str::from_utf8(copy).unwrap();
+            }
+            _ => Err(ErrorKind::InvalidKey)?,
+        }
+        p.pop()
+    }
+    p.expect("}")?;
+    if matches!(res.kind, LightKind::Directional { dir } if dir == v64::ZERO) {
+        Err(ErrorKind::NoDirection)?
+    }
+    Ok(())
+}
+
+fn settings<'m, 'i>(p: &mut Parser<'m, 'i, '_>, res: &mut RenderSettings) -> Result<(), ErrorKind> {
+    p.expect("{")?;
+    while !p.at("}") {
+        match p.push_next()? {
+            "samples" => {
+                res.samples = integer(p)?;
+                if res.samples == 0 {
+                    p.warn(WarningKind::ZeroSamples);
+                    res.samples = 1;
+                }
+            }
+            "bounces" => res.bounces = integer(p)?,
+            "min_survival" => {
+                res.min_survival =
+                    Some(unit(p)?).filter(|&it| it > 0.0).ok_or(ErrorKind::InvalidValue)?
+            }
+            "glossy_splits" => {
+                res.glossy_splits =
+                    Some(integer(p)?).filter(|&it| it > 0).ok_or(ErrorKind::InvalidValue)?
+            }
+            "exposure" => res.exposure = finite(p)?,
+            "auto_exposure" => res.auto_exposure = boolean(p)?,
+            "temperature" => res.temperature = finite(p)?,
+            "tint" => res.tint = finite(p)?,
+            "tonemap" => {
+                res.tonemap = Tonemap::from_name(p.next()?).ok_or(ErrorKind::InvalidValue)?
+            }
+            "gamma" => {
+                res.gamma =
+                    Some(scalar(p)?).filter(|&it| it > 0.0).ok_or(ErrorKind::InvalidValue)?
+            }
+            "vignette" => res.vignette = unit(p)?,
+            "grain" => res.grain = unit(p)?,
+            "seed" => res.seed = p.next()?.parse().map_err(ErrorKind::ParseIntError)?,
+            "shadow_bias" => {
+                res.shadow_bias =
+                    Some(Some(scalar(p)?).filter(|&it| it > 0.0).ok_or(ErrorKind::InvalidValue)?)
+            }
+            "integrator" => {
+                res.integrator = Integrator::from_name(p.next()?).ok_or(ErrorKind::InvalidValue)?
+            }
+            "ao_rays" => {
+                res.ao_rays =
+                    Some(integer(p)?).filter(|&it| it > 0).ok_or(ErrorKind::InvalidValue)?
+            }
+            "ao_distance" => {
+                res.ao_distance =
+                    Some(Some(scalar(p)?).filter(|&it| it > 0.0).ok_or(ErrorKind::InvalidValue)?)
+            }
             _ => Err(ErrorKind::InvalidKey)?,
         }
         p.pop()
@@ -326,12 +1321,79 @@ fn light<'m, 'i>(p: &mut Parser<'m, 'i, '_>, res: &mut Light) -> Result<(), Erro
     p.expect("}")
 }
 
+fn integer<'m, 'i>(p: &mut Parser<'m, 'i, '_>) -> Result<u32, ErrorKind> {
+    let t = p.next()?;
+    let n = t.parse::<u32>().map_err(ErrorKind::ParseIntError)?;
+    Ok(n)
+}
+
+fn boolean<'m, 'i>(p: &mut Parser<'m, 'i, '_>) -> Result<bool, ErrorKind> {
+    match p.next()? {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(ErrorKind::InvalidValue),
+    }
+}
+
+/// A comma-separated list of the rays which see a surface, or `none`.
+fn visibility<'m, 'i>(p: &mut Parser<'m, 'i, '_>) -> Result<Visibility, ErrorKind> {
+    let mut res = Visibility { camera: false, shadow: false, reflection: false };
+    let value = p.next()?;
+    if value != "none" {
+        for it in value.split(',') {
+            match it {
+                "camera" => res.camera = true,
+                "shadow" => res.shadow = true,
+                "reflection" => res.reflection = true,
+                _ => Err(ErrorKind::InvalidValue)?,
+            }
+        }
+    }
+    Ok(res)
+}
+
 fn scalar<'m, 'i>(p: &mut Parser<'m, 'i, '_>) -> Result<f64, ErrorKind> {
     let t = p.next()?;
     let f = t.parse::<f64>().map_err(ErrorKind::ParseFloatError)?;
     Ok(f)
 }
 
+fn finite<'m, 'i>(p: &mut Parser<'m, 'i, '_>) -> Result<f64, ErrorKind> {
+    Some(scalar(p)?).filter(|it| it.is_finite()).ok_or(ErrorKind::InvalidValue)
+}
+
+/// A scalar within `0.0..=1.0`.
+fn unit<'m, 'i>(p: &mut Parser<'m, 'i, '_>) -> Result<f64, ErrorKind> {
+    Some(scalar(p)?).filter(|it| (0.0..=1.0).contains(it)).ok_or(ErrorKind::InvalidValue)
+}
+
+/// A vector, optionally followed by `@time value` keyframes. Returns the
+/// initial value.
+fn animated<'m, 'i>(p: &mut Parser<'m, 'i, '_>, target: Target) -> Result<v64, ErrorKind> {
+    let value = vector(p)?;
+    let mut words = p.words.clone();
+    let mut n_keys = 1;
+    while words.next().is_some_and(|it| it.starts_with('@')) && words.next().is_some() {
+        n_keys += 1;
+    }
+    if n_keys == 1 {
+        return Ok(value);
+    }
+
+    let keys: &mut [Keyframe] = p.mem.alloc_array_default(n_keys).map_err(ErrorKind::Oom)?;
+    keys[0] = Keyframe { time: 0.0, value };
+    for i in 1..n_keys {
+        let time = p.next()?.strip_prefix('@').ok_or(ErrorKind::InvalidKeyframeTime)?;
+        let time = time.parse::<f64>().map_err(|_| ErrorKind::InvalidKeyframeTime)?;
+        if time.is_nan() || time <= keys[i - 1].time {
+            Err(ErrorKind::UnorderedKeyframes)?
+        }
+        keys[i] = Keyframe { time, value: vector(p)? };
+    }
+    p.add_track(Track { target, keys });
+    Ok(value)
+}
+
 fn vector<'m, 'i>(p: &mut Parser<'m, 'i, '_>) -> Result<v64, ErrorKind> {
     let t = p.next()?;
     let v = t.parse::<v64>().map_err(ErrorKind::ParseVectorError)?;
@@ -347,14 +1409,30 @@ fn dim<'m, 'i>(p: &mut Parser<'m, 'i, '_>) -> Result<(f64, f64), ErrorKind> {
     Ok((w?, h?))
 }
 
-fn split_n<const N: usize>(s: &str, p: char) -> Option<[&str; N]> {
-    let mut components = s.split(p);
-    let mut res = [""; N];
-    for i in 0..N {
-        res[i] = components.next()?;
-    }
-    if components.next().is_some() {
-        return None;
+#[test]
+fn test_keyframes() {
+    let mut buf = [0u8; 1 << 14];
+    Mem::with(&mut buf, |mem| {
+        let crt = "sphere { pos 0,0,0 @1 2,0,0 @3 2,4,0 }";
+        let scene = Scene::parse(mem, crt).unwrap();
+        let track = &scene.tracks[0];
+        assert!(matches!(track.target, Target::SpherePos(0)));
+        for (time, expected) in [
+            (-1.0, v64::ZERO),
+            (0.0, v64::ZERO),
+            (0.5, v64(1.0, 0.0, 0.0)),
+            (1.0, v64(2.0, 0.0, 0.0)),
+            (2.5, v64(2.0, 3.0, 0.0)),
+            (3.0, v64(2.0, 4.0, 0.0)),
+            (10.0, v64(2.0, 4.0, 0.0)),
+        ] {
+            assert!(track.value_at(time) == expected, "{time}");
+        }
+    });
+    for crt in ["sphere { pos 0,0,0 @2 1,0,0 @1 2,0,0 }", "sphere { pos 0,0,0 @0 1,0,0 }"] {
+        Mem::with(&mut buf, |mem| {
+            let err = Scene::parse(mem, crt).err().unwrap();
+            assert!(matches!(err.kind, ErrorKind::UnorderedKeyframes), "{crt}");
+        });
     }
-    Some(res)
 }
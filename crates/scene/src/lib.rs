@@ -1,23 +1,45 @@
 #![no_std]
 mod crt;
 mod color;
+mod noise;
+mod simplify;
 
 use geom::{v64, Ray};
 use mem::Mem;
 
-pub use crate::{color::Color, crt::ParseSceneError};
+pub use crate::{
+    color::{linear_to_srgb, srgb_to_linear, Color},
+    crt::{ParseSceneError, Warning},
+};
+
+/// Calls the given function with the contents of the external file a scene
+/// refers to by name, unless there is no such file. This crate is `no_std`
+/// and can't read files, so the host reads the ones listed by
+/// [`Scene::blobs`].
+pub type Blobs<'b> = dyn Fn(&str, &mut dyn FnMut(&[u8])) + Sync + 'b;
 
 pub struct Scene<'m> {
     pub background: Color,
     pub foreground: Color,
+    /// The flat fill which lights every surface regardless of the lights,
+    /// scaled by [`Material::ambient`]. White by default, black disables it.
+    pub ambient: Color,
     pub camera: Camera,
-    pub light: Light,
+    pub lights: &'m mut [Light<'m>],
+    pub settings: RenderSettings,
     pub spheres: &'m mut [Sphere],
     pub planes: &'m mut [Plane],
+    pub tori: &'m mut [Torus],
+    pub quadrics: &'m mut [Quadric],
     pub meshes: &'m mut [Mesh<'m>],
+    pub curves: &'m mut [Curves<'m>],
+    pub tracks: &'m mut [Track<'m>],
+    /// The objects given a `name`, in the order of the objects of each kind.
+    pub names: &'m mut [Name<'m>],
+    pub clip: Option<Clip>,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone, Copy)]
 pub struct Camera {
     pub pos: v64,
     pub look_at: v64,
@@ -25,18 +47,161 @@ pub struct Camera {
     pub focus: f64,
     pub width: f64,
     pub height: f64,
+    /// Diameter of the lens, `0.0` for a pinhole camera which has everything
+    /// in focus. The wider, the blurrier the objects away from
+    /// [`Camera::focal_distance`], which takes several samples per pixel.
+    pub aperture: f64,
+    /// How far along the gaze objects are sharp, the distance to `look_at` if
+    /// `None`.
+    pub focal_distance: Option<f64>,
+    /// How long the shutter stays open from the time of the frame, in the
+    /// units of the keyframes. Objects with a velocity move meanwhile and
+    /// blur along the way, which takes several samples per pixel.
+    pub shutter: f64,
+}
+
+/// Summary of what is in a scene, see [`Scene::stats`].
+#[derive(Default, Debug)]
+pub struct SceneStats {
+    pub spheres: usize,
+    pub planes: usize,
+    pub tori: usize,
+    pub quadrics: usize,
+    pub meshes: usize,
+    pub curves: usize,
+    pub triangles: usize,
+    pub curve_segments: usize,
+    pub vertices: usize,
+    pub animated_properties: usize,
+    /// Corners of the box containing all finite objects, `None` if there are
+    /// only planes and quadrics.
+    pub bounds: Option<[v64; 2]>,
+}
+
+/// How the scene should be rendered, as opposed to what is in it.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings {
+    /// Number of rays traced per pixel.
+    pub samples: u32,
+    /// Maximum number of reflections followed for a single ray.
+    pub bounces: u32,
+    /// The smallest probability with which a reflection is traced. Each one is
+    /// traced with the probability of its contribution to the pixel, but no
+    /// less than this, and weighted up when it is, to stay correct on
+    /// average. `1.0` traces all of them.
+    pub min_survival: f64,
+    /// Rays traced for each glossy reflection seen directly from the camera,
+    /// which is where the noise of rough surfaces shows the most.
+    pub glossy_splits: u32,
+    /// Brightness adjustment in stops applied before tone mapping, each stop
+    /// doubles the radiance.
+    pub exposure: f64,
+    /// Picks the exposure from what the camera sees, so that the log-average
+    /// luminance comes out middle gray. [`RenderSettings::exposure`] is added
+    /// on top, to make the image brighter or darker than that.
+    pub auto_exposure: bool,
+    /// White balance applied before tone mapping: positive values warm the
+    /// image up by shifting it towards red, negative ones cool it down
+    /// towards blue. `1.0` is a stop of difference between the two.
+    pub temperature: f64,
+    /// White balance across the temperature: positive values shift the image
+    /// towards magenta, negative ones towards green, by half a stop for `1.0`.
+    pub tint: f64,
+    pub tonemap: Tonemap,
+    /// Display gamma applied on top of tone mapping, `1.0` leaves colors as
+    /// is.
+    pub gamma: f64,
+    /// How much darker the image gets towards the edges, from `0.0` for not
+    /// at all to `1.0` for black corners.
+    pub vignette: f64,
+    /// How much the brightness of each pixel varies at random, like the grain
+    /// of film, from `0.0` for not at all to `1.0` for anywhere between black
+    /// and twice as bright. The same seed gives the same grain.
+    pub grain: f64,
+    pub integrator: Integrator,
+    /// Seeds the randomness of sampling, the same seed always gives the same
+    /// image.
+    pub seed: u64,
+    /// How far from the surface shadow and reflection rays start, so that
+    /// they don't hit the surface they start from because of rounding.
+    /// `None` picks it from the size of the scene and the distance to the
+    /// hit.
+    pub shadow_bias: Option<f64>,
+    /// Rays cast from each hit by [`Integrator::Occlusion`].
+    pub ao_rays: u32,
+    /// How far away surfaces still block the sky for
+    /// [`Integrator::Occlusion`], `None` for any distance.
+    pub ao_distance: Option<f64>,
+}
+
+/// How to squeeze unbounded radiance into the displayable `0.0..=1.0` range.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Tonemap {
+    /// Leave radiance as is, 8-bit formats clip everything above `1.0`.
+    #[default]
+    None,
+    /// `c / (1 + c)`, which compresses highlights instead of clipping them.
+    Reinhard,
+}
+
+/// What gets computed for each ray.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Integrator {
+    /// Direct lighting with hard shadows and mirror reflections.
+    #[default]
+    Whitted,
+    /// Monte Carlo path tracing: light bounces off diffuse surfaces too, and
+    /// [`Material::emission`] lights the scene. Takes many samples to
+    /// converge.
+    Path,
+    /// Surface normals mapped to colors, for debugging geometry.
+    Normals,
+    /// Distance to the first hit, white up close and darker further away,
+    /// half way at the distance the camera looks at.
+    Depth,
+    /// Ambient occlusion: white where nothing is nearby, darker the more of
+    /// the sky is blocked, see [`RenderSettings::ao_rays`]. Needs no lights.
+    Occlusion,
+    /// How many BVH nodes the camera ray visits, from blue for none through
+    /// green to red for 256 or more, to find badly fitting trees.
+    BvhHeatmap,
+    /// Mesh triangles in gray with their edges in white, to see the
+    /// tessellation.
+    Wireframe,
+    /// The shaded image as a light meter sees it: luminance in stops from
+    /// middle gray, after [`RenderSettings::exposure`], from blue six stops
+    /// under through green to red just under white, and white where it
+    /// clips. Black is black.
+    FalseColor,
 }
 
 #[derive(Default)]
-pub struct Light {
+pub struct Light<'m> {
+    pub kind: LightKind,
     pub pos: v64,
     pub color: Color,
+    /// Name of the group of lights this one belongs to, which renders can
+    /// switch on and off as a whole. Empty for lights without a group.
+    pub group: &'m str,
+}
+
+#[derive(Default, Clone, Copy, Debug)]
+pub enum LightKind {
+    /// Shines in all directions from [`Light::pos`].
+    #[default]
+    Point,
+    /// Infinitely far away, like the sun, so that it shines along the same
+    /// unit `dir` everywhere. The position is unused.
+    Directional { dir: v64 },
 }
 
 #[derive(Default, Clone)]
 pub struct Sphere {
     pub center: v64,
     pub radius: f64,
+    /// How far the center moves per unit of time while the shutter is open,
+    /// see [`Camera::shutter`].
+    pub velocity: v64,
     pub material: Material,
 }
 
@@ -46,10 +211,56 @@ pub struct Plane {
     pub material: Material,
 }
 
+/// A ring around `axis`, the points at `tube` distance from the circle of
+/// `radius` around `center`.
+#[derive(Clone)]
+pub struct Torus {
+    pub center: v64,
+    /// Of unit length.
+    pub axis: v64,
+    pub radius: f64,
+    pub tube: f64,
+    pub material: Material,
+}
+
+/// The surface where a polynomial of second degree in the coordinates
+/// relative to `center` is zero, like a cylinder, a cone or a paraboloid.
+/// Unbounded unless the polynomial makes it so, like for an ellipsoid. The
+/// outside, where the normals point, is where the polynomial is positive.
+#[derive(Default, Clone)]
+pub struct Quadric {
+    pub center: v64,
+    /// Coefficients of `x^2`, `y^2` and `z^2`.
+    pub square: v64,
+    /// Coefficients of `xy`, `xz` and `yz`.
+    pub mixed: v64,
+    /// Coefficients of `x`, `y` and `z`.
+    pub linear: v64,
+    pub constant: f64,
+    pub material: Material,
+}
+
+/// A plane cutting away everything on the side its normal points to, for
+/// cutaway views.
+#[derive(Clone)]
+pub struct Clip {
+    pub normal: Ray,
+    /// Closes the cuts through solid objects with a flat surface of this
+    /// material, instead of showing their inside.
+    pub cap: Option<Material>,
+}
+
+#[derive(Default)]
 pub struct Mesh<'m> {
     pub v: &'m mut [v64],
     pub n: &'m mut [v64],
     pub f: &'m mut [MeshFace],
+    /// Added to all vertices, so that the mesh can be moved around without
+    /// rewriting its data.
+    pub offset: v64,
+    /// How far the mesh moves per unit of time while the shutter is open,
+    /// see [`Camera::shutter`].
+    pub velocity: v64,
     pub material: Material,
 }
 
@@ -64,17 +275,381 @@ pub struct Triangle {
     pub n: [v64; 3],
 }
 
-#[derive(Default, Clone)]
+/// Tubes of `radius` around cubic Bézier curves, for hair, grass and wires,
+/// which would take a lot of triangles.
+#[derive(Default)]
+pub struct Curves<'m> {
+    /// The four control points of each curve, the tube passes through the
+    /// first and the last one.
+    pub segments: &'m mut [[v64; 4]],
+    pub radius: f64,
+    pub material: Material,
+}
+
+/// A property which changes over time, written in the scene file as a list of
+/// `@time value` pairs after the initial value.
+#[derive(Default)]
+pub struct Track<'m> {
+    pub target: Target,
+    /// Sorted by time, the first key is at `0.0`.
+    pub keys: &'m mut [Keyframe],
+}
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct Keyframe {
+    pub time: f64,
+    pub value: v64,
+}
+
+/// Which property a [`Track`] animates.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Target {
+    #[default]
+    CameraPos,
+    CameraLookAt,
+    /// Position of the light with the given index.
+    LightPos(u32),
+    /// Center of the sphere with the given index.
+    SpherePos(u32),
+    /// Offset of the mesh with the given index.
+    MeshOffset(u32),
+}
+
+/// A name given to an object, see [`Scene::retain_objects`].
+#[derive(Default, Clone, Copy, Debug)]
+pub struct Name<'m> {
+    pub kind: ObjectKind,
+    /// Index of the object among those of its kind.
+    pub index: u32,
+    pub name: &'m str,
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ObjectKind {
+    #[default]
+    Sphere,
+    Plane,
+    Torus,
+    Quadric,
+    Mesh,
+    Curves,
+}
+
+#[derive(Clone)]
 pub struct Material {
     pub color: Color,
+    /// How much of [`Scene::ambient`] the surface picks up, in its own color.
+    pub ambient: f64,
     pub diffuse: f64,
+    /// Brightness of the highlights, which take the color of the light
+    /// rather than of the surface.
     pub specular: f64,
+    /// Blinn-Phong exponent: the higher, the smaller and sharper the
+    /// highlights.
+    pub shininess: f64,
     pub reflectance: f64,
+    /// Blurs reflections, from `0.0` for a perfect mirror to `1.0` for
+    /// reflections scattered all over the hemisphere. Each sample picks a
+    /// random direction, so glossy surfaces need several samples per pixel.
+    pub roughness: f64,
+    /// Shades the back of the surface like the front, by turning the normal
+    /// towards the ray. Otherwise, planes and triangles are lit only from the
+    /// side their normal points to.
+    pub double_sided: bool,
+    pub visible: Visibility,
+    /// Light given off by the surface. [`Integrator::Whitted`] shows the
+    /// surface glowing, but only [`Integrator::Path`] lets it light other
+    /// surfaces.
+    pub emission: Color,
+    /// Shows what is behind the surface instead of the surface itself,
+    /// darkened by the shadows falling on it, to put the shadows of the scene
+    /// onto the background. Its other properties are ignored.
+    pub shadow_catcher: bool,
+}
+
+/// Which rays see a surface. Hidden from the camera, it can still cast
+/// shadows and show in reflections, and the other way around.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Visibility {
+    pub camera: bool,
+    pub shadow: bool,
+    pub reflection: bool,
 }
 
 impl<'m> Scene<'m> {
+    /// Warnings go to [`trace`]. Meshes with binary data fail to parse, see
+    /// [`Scene::parse_with_blobs`].
     pub fn parse<'a>(mem: &mut Mem<'m>, s: &'a str) -> Result<Scene<'m>, ParseSceneError<'a>> {
-        crt::parse(mem, s)
+        Scene::parse_with_blobs(mem, s, &|_, _| ())
+    }
+
+    /// Like [`Scene::parse`], but reads the binary data of meshes from
+    /// `blobs`.
+    pub fn parse_with_blobs<'a>(
+        mem: &mut Mem<'m>,
+        s: &'a str,
+        blobs: &Blobs<'_>,
+    ) -> Result<Scene<'m>, ParseSceneError<'a>> {
+        let mut warn = |w: Warning<'a>| {
+            trace::emit(trace::Event::Warning { line: w.line(), message: &w });
+        };
+        Scene::parse_with_warnings(mem, s, blobs, &mut warn)
+    }
+
+    /// Like [`Scene::parse_with_blobs`], but also reports suspicious values
+    /// to `warn`.
+    pub fn parse_with_warnings<'a>(
+        mem: &mut Mem<'m>,
+        s: &'a str,
+        blobs: &Blobs<'_>,
+        warn: &mut dyn FnMut(Warning<'a>),
+    ) -> Result<Scene<'m>, ParseSceneError<'a>> {
+        trace::emit(trace::Event::Start(trace::Phase::Parse));
+        let res = crt::parse(mem, s, blobs, warn);
+        trace::emit(trace::Event::End(trace::Phase::Parse));
+        res
+    }
+
+    pub fn stats(&self) -> SceneStats {
+        let mut bounds: Option<[v64; 2]> = None;
+        let mut add = |lo: v64, hi: v64| {
+            let zip =
+                |f: fn(f64, f64) -> f64, a: v64, b: v64| v64(f(a.x, b.x), f(a.y, b.y), f(a.z, b.z));
+            bounds = Some(match bounds {
+                None => [lo, hi],
+                Some([l, h]) => [zip(f64::min, l, lo), zip(f64::max, h, hi)],
+            });
+        };
+        for s in self.spheres.iter() {
+            let r = v64(s.radius, s.radius, s.radius);
+            add(s.center - r, s.center + r);
+        }
+        for t in self.tori.iter() {
+            let r = t.radius.abs() + t.tube.abs();
+            add(t.center - v64(r, r, r), t.center + v64(r, r, r));
+        }
+        for m in self.meshes.iter() {
+            for &v in m.v.iter() {
+                add(v + m.offset, v + m.offset);
+            }
+        }
+        // A Bézier curve stays within the hull of its control points.
+        for c in self.curves.iter() {
+            let r = c.radius.abs();
+            let r = v64(r, r, r);
+            for &p in c.segments.iter().flatten() {
+                add(p - r, p + r);
+            }
+        }
+        SceneStats {
+            spheres: self.spheres.len(),
+            planes: self.planes.len(),
+            tori: self.tori.len(),
+            quadrics: self.quadrics.len(),
+            meshes: self.meshes.len(),
+            curves: self.curves.len(),
+            triangles: self.meshes.iter().map(|it| it.f.len()).sum(),
+            curve_segments: self.curves.iter().map(|it| it.segments.len()).sum(),
+            vertices: self.meshes.iter().map(|it| it.v.len()).sum(),
+            animated_properties: self.tracks.len(),
+            bounds,
+        }
+    }
+
+    /// Moves animated objects to where they are at `time`, in seconds.
+    pub fn at_time(&mut self, time: f64) {
+        for track in self.tracks.iter() {
+            let value = track.value_at(time);
+            match track.target {
+                Target::CameraPos => self.camera.pos = value,
+                Target::CameraLookAt => self.camera.look_at = value,
+                Target::LightPos(i) => self.lights[i as usize].pos = value,
+                Target::SpherePos(i) => self.spheres[i as usize].center = value,
+                Target::MeshOffset(i) => self.meshes[i as usize].offset = value,
+            }
+        }
+    }
+
+    /// Removes the objects for which `keep` returns `false`, given their
+    /// names, `""` for unnamed ones, along with their names and tracks.
+    pub fn retain_objects(&mut self, keep: &dyn Fn(&str) -> bool) {
+        let (names, tracks) = (&mut *self.names, &mut *self.tracks);
+        retain(&mut self.spheres, ObjectKind::Sphere, names, tracks, keep);
+        retain(&mut self.planes, ObjectKind::Plane, names, tracks, keep);
+        retain(&mut self.tori, ObjectKind::Torus, names, tracks, keep);
+        retain(&mut self.quadrics, ObjectKind::Quadric, names, tracks, keep);
+        retain(&mut self.meshes, ObjectKind::Mesh, names, tracks, keep);
+        retain(&mut self.curves, ObjectKind::Curves, names, tracks, keep);
+        compact(&mut self.names, |it| !it.name.is_empty());
+        compact(&mut self.tracks, |it| !it.keys.is_empty());
+    }
+
+    /// Upper bound on the memory [`Scene::parse`] needs for `s`. Reports the
+    /// number of faces of each mesh to `mesh_faces`, and the number of
+    /// segments of each set of curves to `curve_segments`.
+    pub fn mem_estimate(
+        s: &str,
+        mesh_faces: &mut dyn FnMut(usize),
+        curve_segments: &mut dyn FnMut(usize),
+    ) -> usize {
+        crt::mem_estimate(s, mesh_faces, curve_segments)
+    }
+
+    /// The names of the files whose contents [`Scene::parse_with_blobs`]
+    /// needs for `s`, possibly repeated.
+    pub fn blobs(s: &str) -> impl Iterator<Item = &str> {
+        crt::blobs(s)
+    }
+}
+
+/// Removes the objects of one kind which `keep` rejects, and renumbers the
+/// names and tracks of the rest. Those of the removed objects are emptied,
+/// which no parsed name or track is, for the caller to drop.
+fn retain<T>(
+    objects: &mut &mut [T],
+    kind: ObjectKind,
+    names: &mut [Name<'_>],
+    tracks: &mut [Track<'_>],
+    keep: &dyn Fn(&str) -> bool,
+) {
+    // Both are parsed along with their objects, so they come in order.
+    let mut names = names.iter_mut().filter(|it| it.kind == kind).peekable();
+    let mut tracks = (tracks.iter_mut())
+        .filter(|it| it.target.object().is_some_and(|(k, _)| k == kind))
+        .peekable();
+    let mut n = 0;
+    for i in 0..objects.len() as u32 {
+        let name = names.next_if(|it| it.index == i);
+        let kept = keep(name.as_ref().map_or("", |it| it.name));
+        if let Some(name) = name {
+            if kept {
+                name.index = n;
+            } else {
+                name.name = "";
+            }
+        }
+        while let Some(track) = tracks.next_if(|it| it.target.object() == Some((kind, i))) {
+            if kept {
+                track.target = match track.target {
+                    Target::SpherePos(_) => Target::SpherePos(n),
+                    Target::MeshOffset(_) => Target::MeshOffset(n),
+                    target => target,
+                };
+            } else {
+                track.keys = &mut [];
+            }
+        }
+        if kept {
+            objects.swap(n as usize, i as usize);
+            n += 1;
+        }
+    }
+    let all = core::mem::take(objects);
+    *objects = &mut all[..n as usize];
+}
+
+/// Moves the items `keep` accepts to the front, in order, and drops the rest.
+fn compact<T>(items: &mut &mut [T], keep: impl Fn(&T) -> bool) {
+    let mut n = 0;
+    for i in 0..items.len() {
+        if keep(&items[i]) {
+            items.swap(n, i);
+            n += 1;
+        }
+    }
+    let all = core::mem::take(items);
+    *items = &mut all[..n];
+}
+
+impl Target {
+    /// The object this moves and its index, if it's not the camera or a light.
+    fn object(self) -> Option<(ObjectKind, u32)> {
+        match self {
+            Target::SpherePos(i) => Some((ObjectKind::Sphere, i)),
+            Target::MeshOffset(i) => Some((ObjectKind::Mesh, i)),
+            Target::CameraPos | Target::CameraLookAt | Target::LightPos(_) => None,
+        }
+    }
+}
+
+impl Light<'_> {
+    /// The ray from `p` towards the light and the distance to the light along
+    /// it, infinite for directional lights.
+    pub fn ray_from(&self, p: v64) -> (Ray, f64) {
+        match self.kind {
+            LightKind::Point => (Ray::from_to(p, self.pos), (self.pos - p).norm()),
+            LightKind::Directional { dir } => (Ray::new(p, -dir), f64::INFINITY),
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            color: Color::default(),
+            ambient: 1.0,
+            diffuse: 0.0,
+            specular: 0.0,
+            shininess: 32.0,
+            reflectance: 0.0,
+            roughness: 0.0,
+            double_sided: false,
+            visible: Visibility { camera: true, shadow: true, reflection: true },
+            emission: Color::default(),
+            shadow_catcher: false,
+        }
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            samples: 1,
+            bounces: 4,
+            min_survival: 1.0,
+            glossy_splits: 1,
+            exposure: 0.0,
+            auto_exposure: false,
+            temperature: 0.0,
+            tint: 0.0,
+            tonemap: Tonemap::default(),
+            gamma: 1.0,
+            vignette: 0.0,
+            grain: 0.0,
+            integrator: Integrator::default(),
+            seed: 0x5eed,
+            shadow_bias: None,
+            ao_rays: 16,
+            ao_distance: None,
+        }
+    }
+}
+
+impl Tonemap {
+    pub fn from_name(name: &str) -> Option<Tonemap> {
+        let res = match name {
+            "none" => Tonemap::None,
+            "reinhard" => Tonemap::Reinhard,
+            _ => return None,
+        };
+        Some(res)
+    }
+}
+
+impl Integrator {
+    pub fn from_name(name: &str) -> Option<Integrator> {
+        let res = match name {
+            "whitted" => Integrator::Whitted,
+            "path" => Integrator::Path,
+            "normals" => Integrator::Normals,
+            "depth" => Integrator::Depth,
+            "ao" => Integrator::Occlusion,
+            "bvh-heatmap" => Integrator::BvhHeatmap,
+            "wireframe" => Integrator::Wireframe,
+            "false-color" => Integrator::FalseColor,
+            _ => return None,
+        };
+        Some(res)
     }
 }
 
@@ -84,22 +659,54 @@ impl Default for Plane {
     }
 }
 
-impl<'m> Default for Mesh<'m> {
-    fn default() -> Mesh<'m> {
-        Mesh { v: &mut [], n: &mut [], f: &mut [], material: Material::default() }
+impl Default for Torus {
+    fn default() -> Torus {
+        Torus {
+            center: v64::ZERO,
+            axis: v64(0.0, 1.0, 0.0),
+            radius: 1.0,
+            tube: 0.25,
+            material: Default::default(),
+        }
+    }
+}
+
+impl<'m> Track<'m> {
+    /// Linearly interpolates between the keys around `time`.
+    pub fn value_at(&self, time: f64) -> v64 {
+        let i = self.keys.partition_point(|it| it.time <= time);
+        match (i.checked_sub(1).map(|i| &self.keys[i]), self.keys.get(i)) {
+            (Some(a), Some(b)) => {
+                let k = (time - a.time) / (b.time - a.time);
+                a.value + (b.value - a.value) * k
+            }
+            (Some(it), None) | (None, Some(it)) => it.value,
+            (None, None) => v64::ZERO,
+        }
     }
 }
 
 impl<'m> Mesh<'m> {
-    pub fn iter(&self) -> impl Iterator<Item = Triangle> + ExactSizeIterator + '_ {
-        self.f.iter().map(|f| Triangle {
-            v: f.v.map(|it| self.v[it as usize]),
-            n: f.n.map(|it| self.n[it as usize]),
-        })
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = Triangle> + '_ {
+        (0..self.f.len()).map(|idx| self.triangle(idx))
     }
 
     pub fn triangle(&self, idx: usize) -> Triangle {
+        let mut res = self.local_triangle(idx);
+        res.v = res.v.map(|it| it + self.offset);
+        res
+    }
+
+    /// Like [`Mesh::triangle`], without the [`Mesh::offset`].
+    pub fn local_triangle(&self, idx: usize) -> Triangle {
         let f = &self.f[idx];
         Triangle { v: f.v.map(|it| self.v[it as usize]), n: f.n.map(|it| self.n[it as usize]) }
     }
 }
+
+impl MeshFace {
+    /// Indices of the corners in [`Mesh::v`].
+    pub fn v(&self) -> [u32; 3] {
+        self.v
+    }
+}
@@ -0,0 +1,121 @@
+//! Gradient noise, for procedural detail which depends only on the position,
+//! so that it comes out the same in every render.
+
+use geom::{dot, v64};
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Noise {
+    /// Perlin noise: smooth bumps, about one unit apart.
+    Perlin,
+    /// Fractal Brownian motion: octaves of Perlin noise, each with twice the
+    /// frequency and half the amplitude of the previous one.
+    Fbm,
+}
+
+const OCTAVES: u32 = 5;
+
+/// The midpoints of the edges of a cube, which keep Perlin noise free of the
+/// axis aligned streaks of random gradients.
+const GRADIENTS: [v64; 12] = [
+    v64(1.0, 1.0, 0.0),
+    v64(-1.0, 1.0, 0.0),
+    v64(1.0, -1.0, 0.0),
+    v64(-1.0, -1.0, 0.0),
+    v64(1.0, 0.0, 1.0),
+    v64(-1.0, 0.0, 1.0),
+    v64(1.0, 0.0, -1.0),
+    v64(-1.0, 0.0, -1.0),
+    v64(0.0, 1.0, 1.0),
+    v64(0.0, -1.0, 1.0),
+    v64(0.0, 1.0, -1.0),
+    v64(0.0, -1.0, -1.0),
+];
+
+impl Noise {
+    pub(crate) fn from_name(name: &str) -> Option<Noise> {
+        let res = match name {
+            "perlin" => Noise::Perlin,
+            "fbm" => Noise::Fbm,
+            _ => return None,
+        };
+        Some(res)
+    }
+
+    /// The noise at `p`, roughly within `-1.0..=1.0`.
+    pub(crate) fn at(self, p: v64) -> f64 {
+        match self {
+            Noise::Perlin => perlin(p),
+            Noise::Fbm => {
+                let (mut res, mut total, mut amplitude) = (0.0, 0.0, 1.0);
+                for octave in 0..OCTAVES {
+                    res +=
+                        perlin(p / amplitude + v64(0.5, 0.5, 0.5) * f64::from(octave)) * amplitude;
+                    total += amplitude;
+                    amplitude /= 2.0;
+                }
+                res / total
+            }
+        }
+    }
+}
+
+fn perlin(p: v64) -> f64 {
+    let cell = p.xyz().map(floor);
+    let [fx, fy, fz] = [p.x - cell[0], p.y - cell[1], p.z - cell[2]];
+    let [i, j, k] = cell.map(|it| it as i64);
+    let mut res = 0.0;
+    for corner in 0..8 {
+        let [dx, dy, dz] = [corner & 1, corner >> 1 & 1, corner >> 2 & 1];
+        let g = GRADIENTS[hash([i.wrapping_add(dx), j.wrapping_add(dy), k.wrapping_add(dz)]) % 12];
+        let d = v64(fx - dx as f64, fy - dy as f64, fz - dz as f64);
+        res += weight(fx, dx) * weight(fy, dy) * weight(fz, dz) * dot(g, d);
+    }
+    res
+}
+
+/// How much the corner at `0` or `1` contributes at `f` within the cell.
+fn weight(f: f64, corner: i64) -> f64 {
+    let fade = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+    if corner == 0 {
+        1.0 - fade
+    } else {
+        fade
+    }
+}
+
+fn hash(cell: [i64; 3]) -> usize {
+    let [i, j, k] = cell.map(|it| it as u64);
+    let mut h = i.wrapping_mul(0x9e3779b97f4a7c15)
+        ^ j.wrapping_mul(0xc2b2ae3d27d4eb4f)
+        ^ k.wrapping_mul(0x165667b19e3779f9);
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+    (h ^ (h >> 31)) as usize
+}
+
+/// `f64::floor` is not in `core`.
+fn floor(x: f64) -> f64 {
+    let t = x as i64 as f64;
+    if t > x {
+        t - 1.0
+    } else {
+        t
+    }
+}
+
+#[test]
+fn test_noise() {
+    for noise in [Noise::Perlin, Noise::Fbm] {
+        let mut prev = noise.at(v64(-3.0, 0.25, 7.5));
+        for i in 1..1000 {
+            let p = v64(-3.0 + 0.01 * f64::from(i), 0.25, 7.5);
+            let value = noise.at(p);
+            assert!(value.abs() <= 1.5, "{noise:?} at {p}: {value}");
+            assert!((value - prev).abs() < 0.2, "{noise:?} jumps at {p}");
+            prev = value;
+        }
+    }
+    // Zero at the corners of the cells, where all the gradients are dotted
+    // with zero offsets.
+    assert_eq!(Noise::Perlin.at(v64(2.0, -5.0, 0.0)), 0.0);
+}
@@ -0,0 +1,327 @@
+//! Mesh simplification by edge collapse, with the quadric error metrics of
+//! Garland and Heckbert, "Surface Simplification Using Quadric Error
+//! Metrics".
+
+use geom::{cross, dot, v64};
+use mem::{Mem, Oom};
+
+use crate::Mesh;
+
+/// How much more moving off a boundary edge costs than moving off a face, so
+/// that the outline of open meshes survives.
+const BOUNDARY_WEIGHT: f64 = 1000.0;
+
+/// The sum of squared distances to a set of planes: the symmetric 4x4 matrix
+/// of a quadratic form over `[x, y, z, 1]`, its upper triangle by rows.
+#[derive(Default, Clone, Copy, Debug)]
+struct Qem([f64; 10]);
+
+impl Qem {
+    /// The plane through `p` with unit normal `n`, counted `weight` times.
+    fn plane(n: v64, p: v64, weight: f64) -> Qem {
+        let [a, b, c] = n.xyz();
+        let d = -dot(n, p);
+        let q = [a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d];
+        Qem(q.map(|it| it * weight))
+    }
+
+    fn add(self, rhs: Qem) -> Qem {
+        let mut res = self;
+        for (l, r) in res.0.iter_mut().zip(rhs.0) {
+            *l += r;
+        }
+        res
+    }
+
+    fn error(&self, p: v64) -> f64 {
+        let [q0, q1, q2, q3, q4, q5, q6, q7, q8, q9] = self.0;
+        let [x, y, z] = p.xyz();
+        x * (q0 * x + 2.0 * (q1 * y + q2 * z + q3))
+            + y * (q4 * y + 2.0 * (q5 * z + q6))
+            + z * (q7 * z + 2.0 * q8)
+            + q9
+    }
+
+    /// The point with the least error, unless it isn't unique, as in flat
+    /// regions and along creases.
+    fn minimum(&self) -> Option<v64> {
+        let [q0, q1, q2, q3, q4, q5, q6, q7, q8, _] = self.0;
+        let rows = [v64(q0, q1, q2), v64(q1, q4, q5), v64(q2, q5, q7)];
+        let b = v64(-q3, -q6, -q8);
+        // Cramer's rule, with the columns of the symmetric matrix as rows.
+        let det = |[r0, r1, r2]: [v64; 3]| dot(r0, cross(r1, r2));
+        let d = det(rows);
+        let scale = rows.iter().map(|it| it.norm()).product::<f64>();
+        if d.abs() <= 1e-6 * scale || !d.is_finite() {
+            return None;
+        }
+        let [r0, r1, r2] = rows;
+        Some(v64(det([b, r1, r2]), det([r0, b, r2]), det([r0, r1, b])) / d)
+    }
+}
+
+/// An edge, and where its vertices go if it collapses.
+#[derive(Default, Clone, Copy)]
+struct Edge {
+    v: [u32; 2],
+    /// One of the faces along the edge.
+    face: u32,
+    pos: v64,
+    cost: f64,
+}
+
+/// How many faces simplifying `faces` by `ratio` leaves at most.
+pub(crate) fn target(faces: usize, ratio: f64) -> usize {
+    (ratio * faces as f64) as usize
+}
+
+/// Upper bound on the scratch memory [`simplify`] needs.
+pub(crate) fn mem_estimate(vertices: usize, faces: usize) -> usize {
+    let per_pass = array_size::<u32>(vertices + 1)
+        + array_size::<u32>(3 * faces)
+        + array_size::<Edge>(3 * faces)
+        + array_size::<bool>(vertices);
+    2 * array_size::<u32>(vertices) + array_size::<Qem>(vertices) + per_pass
+}
+
+fn array_size<T>(n: usize) -> usize {
+    n * size_of::<T>() + align_of::<T>() - 1
+}
+
+/// Collapses edges, the cheapest first, until at most `ratio` of the faces
+/// are left, and drops the vertices which are no longer used. Vertices at
+/// the same position are merged first, so that edges connect faces which
+/// don't share indices, as in meshes converted from STL.
+///
+/// Everything happens in place and in scratch memory. The faces keep
+/// pointing at the old normals, for the caller to recompute.
+pub(crate) fn simplify(mem: &mut Mem<'_>, mesh: &mut Mesh<'_>, ratio: f64) -> Result<(), Oom> {
+    let target = target(mesh.f.len(), ratio);
+    let free = mem.free();
+    mem.with_scratch(free, |_, scratch| {
+        let n_v = mesh.v.len();
+        let index: &mut [u32] = scratch.alloc_array(n_v, |i| i as u32)?;
+        let order: &mut [u32] = scratch.alloc_array(n_v, |i| i as u32)?;
+        weld(mesh, order, index);
+
+        let qems: &mut [Qem] = scratch.alloc_array_default(n_v)?;
+        for face in mesh.f.iter() {
+            let [a, b, c] = face.v.map(|it| mesh.v[it as usize]);
+            let n = cross(b - a, c - a);
+            if n.norm() > 0.0 {
+                let qem = Qem::plane(n.to_unit(), a, n.norm() / 2.0);
+                for v in face.v {
+                    qems[v as usize] = qems[v as usize].add(qem);
+                }
+            }
+        }
+
+        // Collapses which fold faces over are skipped, unless nothing else is
+        // left to collapse.
+        let (mut first, mut careful) = (true, true);
+        while mesh.f.len() > target {
+            let free = scratch.free();
+            let collapsed = scratch
+                .with_scratch(free, |_, pass| collapse(pass, mesh, qems, target, first, careful))?;
+            first = false;
+            if collapsed == 0 && !careful {
+                break;
+            }
+            careful = collapsed > 0;
+        }
+        compact(mesh, index);
+        Ok(())
+    })
+}
+
+/// Points the faces at the first of the vertices at each position.
+fn weld(mesh: &mut Mesh<'_>, order: &mut [u32], index: &mut [u32]) {
+    // Adding zero turns `-0.0` into `0.0`.
+    let key = |i: &u32| mesh.v[*i as usize].xyz().map(|it| (it + 0.0).to_bits());
+    order.sort_unstable_by_key(key);
+    for run in order.chunk_by(|l, r| key(l) == key(r)) {
+        for &i in run {
+            index[i as usize] = run[0];
+        }
+    }
+    for face in mesh.f.iter_mut() {
+        face.v = face.v.map(|it| index[it as usize]);
+    }
+}
+
+/// One pass of collapses, of edges with no vertices in common with the faces
+/// around the edges already collapsed in this pass, so that the costs stay
+/// up to date. Returns the number of edges collapsed.
+fn collapse(
+    mem: &mut Mem<'_>,
+    mesh: &mut Mesh<'_>,
+    qems: &mut [Qem],
+    target: usize,
+    first: bool,
+    careful: bool,
+) -> Result<usize, Oom> {
+    let (n_v, n_f) = (mesh.v.len(), mesh.f.len());
+
+    // The faces around each vertex `v` are `around[start[v]..start[v + 1]]`.
+    let start: &mut [u32] = mem.alloc_array_default(n_v + 1)?;
+    for face in mesh.f.iter() {
+        for v in face.v {
+            start[v as usize + 1] += 1;
+        }
+    }
+    for v in 0..n_v {
+        start[v + 1] += start[v];
+    }
+    let around: &mut [u32] = mem.alloc_array_default(3 * n_f)?;
+    for (i, face) in mesh.f.iter().enumerate() {
+        for v in face.v {
+            around[start[v as usize] as usize] = i as u32;
+            start[v as usize] += 1;
+        }
+    }
+    start.copy_within(..n_v, 1);
+    start[0] = 0;
+    let around = &*around;
+    let faces_around = |v: u32| &around[start[v as usize] as usize..start[v as usize + 1] as usize];
+
+    let edges: &mut [Edge] = mem.alloc_array_default(3 * n_f)?;
+    let mut n_e = 0;
+    for (i, face) in mesh.f.iter().enumerate() {
+        for k in 0..3 {
+            let (a, b) = (face.v[k], face.v[(k + 1) % 3]);
+            if a != b {
+                edges[n_e] = Edge { v: [a.min(b), a.max(b)], face: i as u32, ..Edge::default() };
+                n_e += 1;
+            }
+        }
+    }
+    let edges = &mut edges[..n_e];
+    edges.sort_unstable_by_key(|it| it.v);
+    let mut n_e = 0;
+    for i in 0..edges.len() {
+        let edge = edges[i];
+        if n_e > 0 && edges[n_e - 1].v == edge.v {
+            continue;
+        }
+        let boundary = edges.get(i + 1).is_none_or(|it| it.v != edge.v);
+        if first && boundary {
+            // The plane through the edge, perpendicular to its face.
+            let [a, b] = edge.v.map(|it| mesh.v[it as usize]);
+            let [p, q, r] = mesh.f[edge.face as usize].v.map(|it| mesh.v[it as usize]);
+            let n = cross(b - a, cross(q - p, r - p));
+            if n.norm() > 0.0 {
+                let qem = Qem::plane(n.to_unit(), a, BOUNDARY_WEIGHT * (b - a).norm_squared());
+                for v in edge.v {
+                    qems[v as usize] = qems[v as usize].add(qem);
+                }
+            }
+        }
+        edges[n_e] = edge;
+        n_e += 1;
+    }
+    let edges = &mut edges[..n_e];
+    for edge in edges.iter_mut() {
+        let [a, b] = edge.v;
+        let qem = qems[a as usize].add(qems[b as usize]);
+        let [pa, pb] = [a, b].map(|it| mesh.v[it as usize]);
+        let candidates = [Some(pa), Some(pb), Some((pa + pb) * 0.5), qem.minimum()];
+        (edge.pos, edge.cost) = (candidates.into_iter().flatten())
+            .map(|it| (it, qem.error(it)))
+            .min_by(|l, r| l.1.total_cmp(&r.1))
+            .unwrap();
+    }
+    edges.sort_unstable_by(|l, r| l.cost.total_cmp(&r.cost));
+
+    let touched: &mut [bool] = mem.alloc_array_default(n_v)?;
+    let (mut removed, mut res) = (0, 0);
+    for edge in edges.iter() {
+        if n_f - removed <= target {
+            break;
+        }
+        let [a, b] = edge.v;
+        if touched[a as usize] || touched[b as usize] {
+            continue;
+        }
+        if careful && folds(mesh, edge, faces_around(a).iter().chain(faces_around(b))) {
+            continue;
+        }
+        removed += faces_around(a).iter().filter(|&&f| mesh.f[f as usize].v.contains(&b)).count();
+        mesh.v[a as usize] = edge.pos;
+        qems[a as usize] = qems[a as usize].add(qems[b as usize]);
+        for &f in faces_around(b) {
+            let face = &mut mesh.f[f as usize];
+            face.v = face.v.map(|it| if it == b { a } else { it });
+        }
+        for &f in faces_around(a).iter().chain(faces_around(b)) {
+            for v in mesh.f[f as usize].v {
+                touched[v as usize] = true;
+            }
+        }
+        res += 1;
+    }
+
+    // Drops the faces collapsed into lines.
+    let mut n_f = 0;
+    for i in 0..mesh.f.len() {
+        let [a, b, c] = mesh.f[i].v;
+        if a != b && b != c && c != a {
+            mesh.f.swap(n_f, i);
+            n_f += 1;
+        }
+    }
+    let f = core::mem::take(&mut mesh.f);
+    mesh.f = &mut f[..n_f];
+    Ok(res)
+}
+
+/// Whether collapsing `edge` turns any of `faces` around it upside down.
+fn folds<'a>(mesh: &Mesh<'_>, edge: &Edge, faces: impl Iterator<Item = &'a u32>) -> bool {
+    faces.map(|&f| mesh.f[f as usize].v).any(|v| {
+        if v.contains(&edge.v[0]) && v.contains(&edge.v[1]) {
+            return false;
+        }
+        let before = v.map(|it| mesh.v[it as usize]);
+        let after = v.map(|it| if edge.v.contains(&it) { edge.pos } else { mesh.v[it as usize] });
+        let normal = |[a, b, c]: [v64; 3]| cross(b - a, c - a);
+        dot(normal(before), normal(after)) <= 0.0
+    })
+}
+
+/// Drops the vertices no face uses, `index` is scratch space.
+fn compact(mesh: &mut Mesh<'_>, index: &mut [u32]) {
+    index.fill(u32::MAX);
+    for face in mesh.f.iter() {
+        for v in face.v {
+            index[v as usize] = 0;
+        }
+    }
+    let mut n_v = 0;
+    for (i, index) in index.iter_mut().enumerate() {
+        if *index != u32::MAX {
+            *index = n_v as u32;
+            mesh.v[n_v] = mesh.v[i];
+            n_v += 1;
+        }
+    }
+    for face in mesh.f.iter_mut() {
+        face.v = face.v.map(|it| index[it as usize]);
+    }
+    let v = core::mem::take(&mut mesh.v);
+    mesh.v = &mut v[..n_v];
+}
+
+#[test]
+fn test_qem() {
+    // The corner of a cube, as seen by the three faces meeting there.
+    let corner = v64(1.0, 2.0, 3.0);
+    let mut qem = Qem::default();
+    for n in [v64(1.0, 0.0, 0.0), v64(0.0, 1.0, 0.0), v64(0.0, 0.0, 1.0)] {
+        qem = qem.add(Qem::plane(n, corner, 1.0));
+    }
+    let min = qem.minimum().unwrap();
+    assert!((min - corner).norm() < 1e-9);
+    assert!(qem.error(min).abs() < 1e-9);
+    assert!((qem.error(v64(1.0, 2.0, 5.0)) - 4.0).abs() < 1e-9);
+    // A single plane has no unique closest point.
+    assert!(Qem::plane(v64(0.0, 0.0, 1.0), corner, 1.0).minimum().is_none());
+}
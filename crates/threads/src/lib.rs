@@ -0,0 +1,372 @@
+//! A fixed number of threads for the `in_parallel` argument of
+//! `render::render` and friends:
+//!
+//! ```
+//! # use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+//! let threads = threads::Threads::with_max_threads().unwrap();
+//! let in_parallel = |f: &(dyn Fn() + Sync)| threads.in_parallel(f);
+//! # let calls = AtomicUsize::new(0);
+//! # in_parallel(&|| { calls.fetch_add(1, SeqCst); });
+//! # assert_eq!(calls.into_inner(), threads.n_threads());
+//! ```
+//!
+//! Applications which already have a rayon thread pool can use it instead,
+//! with the `rayon` feature, see `in_rayon_pool`.
+
+use std::{
+    any::Any,
+    cell::Cell,
+    io, mem,
+    num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, PoisonError},
+    thread::{self, available_parallelism, JoinHandle},
+};
+
+pub struct Threads {
+    n_threads: NonZeroUsize,
+    background: bool,
+    /// Spawned by the first [`Threads::in_parallel`], and kept for the later
+    /// ones.
+    pool: OnceLock<Pool>,
+}
+
+impl Threads {
+    pub fn new(n_threads: NonZeroUsize) -> Threads {
+        Threads { n_threads, background: false, pool: OnceLock::new() }
+    }
+
+    /// Runs the workers at the lowest OS priority, so that a long render
+    /// doesn't make the rest of the machine sluggish. The current thread still
+    /// does its share at its own priority, which couldn't be restored after.
+    pub fn background(self) -> Threads {
+        Threads { background: true, ..Threads::new(self.n_threads) }
+    }
+
+    /// One thread per core.
+    pub fn with_max_threads() -> io::Result<Threads> {
+        let n_threads = available_parallelism()?;
+        Ok(Threads::new(n_threads))
+    }
+
+    pub fn n_threads(&self) -> usize {
+        self.n_threads.get()
+    }
+
+    /// Calls `f` once on each thread, the current one included, and returns
+    /// when all the calls have returned. Work is shared by `f` itself, usually
+    /// by pulling items from a common queue until it's empty. The threads are
+    /// spawned by the first call, and wait for the next one in between.
+    ///
+    /// If any of the calls panics, the panic is resumed on the current thread
+    /// with its original payload, once the other calls are done. That needs
+    /// `panic = "unwind"`: with `panic = "abort"`, as the profiles of this
+    /// workspace build the `crt` binary, the panicking thread aborts the whole
+    /// process after printing the message, and nothing is caught.
+    ///
+    /// `f` can itself call `in_parallel` for a sub-job, like building the BVH
+    /// of a mesh while rendering. The nested call can't wait for the threads
+    /// busy with the outer job. It calls `f` on the current thread, and on the
+    /// workers which run out of outer work in the meantime, and returns once
+    /// those calls have returned.
+    pub fn in_parallel(&self, f: &(dyn Fn() + Sync)) {
+        let nested = IN_JOB.get();
+        let pool = self.pool.get_or_init(|| Pool::spawn(self.n_threads() - 1, self.background));
+        // SAFETY: the job leaves the pool, after the last call to `f` returns,
+        // before this function does. Nothing in between unwinds.
+        let f = unsafe { mem::transmute::<&(dyn Fn() + Sync), &'static (dyn Fn() + Sync)>(f) };
+        let id = pool.submit(f);
+        let mut panic = run(f).err();
+        if let Some(payload) = pool.join(id, nested) {
+            panic.get_or_insert(payload);
+        }
+        if let Some(payload) = panic {
+            panic::resume_unwind(payload)
+        }
+    }
+}
+
+/// [`Threads::in_parallel`] on the threads of the current rayon pool, the
+/// global one unless called from inside [`rayon::ThreadPool::install`].
+/// Broadcasting satisfies the same contract, as each call loops until the
+/// shared work runs out, and `broadcast` also resumes a panic on the calling
+/// thread after all the calls return.
+#[cfg(feature = "rayon")]
+pub fn in_rayon_pool(f: &(dyn Fn() + Sync)) {
+    rayon::broadcast(|_| f());
+}
+
+/// The worker threads, which wait for jobs between the calls to
+/// [`Threads::in_parallel`].
+struct Pool {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Notified when a job is submitted, or when the workers should exit.
+    submitted: Condvar,
+    /// Notified when a worker returns from a job.
+    left: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    jobs: Vec<Job>,
+    next_id: u64,
+    shutdown: bool,
+}
+
+struct Job {
+    id: u64,
+    f: &'static (dyn Fn() + Sync),
+    /// Which workers have called `f`, each calls it once.
+    joined: Vec<bool>,
+    /// Whether the workers which haven't joined yet shouldn't, once the
+    /// submitter of a nested job is done with its own call.
+    closed: bool,
+    /// How many workers are still in `f`.
+    running: usize,
+    /// The first panic of the workers, see [`Threads::in_parallel`].
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+impl Pool {
+    fn spawn(n_workers: usize, background: bool) -> Pool {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            submitted: Condvar::new(),
+            left: Condvar::new(),
+        });
+        let workers = (0..n_workers)
+            .map(|i| {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || {
+                    if background {
+                        lower_priority();
+                    }
+                    shared.work(i)
+                })
+            })
+            .collect();
+        Pool { shared, workers }
+    }
+
+    fn submit(&self, f: &'static (dyn Fn() + Sync)) -> u64 {
+        let mut state = self.shared.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        let joined = vec![false; self.workers.len()];
+        state.jobs.push(Job { id, f, joined, closed: false, running: 0, panic: None });
+        self.shared.submitted.notify_all();
+        id
+    }
+
+    /// Waits for every worker to be done with the job `id`, or, for a
+    /// `nested` one, for those which joined it, and removes it. Returns the
+    /// first panic of the workers.
+    fn join(&self, id: u64, nested: bool) -> Option<Box<dyn Any + Send>> {
+        let mut state = self.shared.lock();
+        loop {
+            let idx = state.jobs.iter().position(|it| it.id == id).unwrap();
+            let job = &mut state.jobs[idx];
+            job.closed |= nested;
+            if job.running == 0 && (job.closed || job.joined.iter().all(|&it| it)) {
+                return state.jobs.remove(idx).panic;
+            }
+            state = self.shared.left.wait(state).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+impl Shared {
+    /// The lock is never held across user code, so a panic can't leave the
+    /// state half-updated.
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The loop of the `i`-th worker.
+    fn work(&self, i: usize) {
+        let mut state = self.lock();
+        loop {
+            if state.shutdown {
+                return;
+            }
+            // Nested jobs come last, and their submitters are blocked on them.
+            let Some(job) = state.jobs.iter_mut().rev().find(|it| !it.closed && !it.joined[i])
+            else {
+                state = self.submitted.wait(state).unwrap_or_else(PoisonError::into_inner);
+                continue;
+            };
+            job.joined[i] = true;
+            job.running += 1;
+            let (id, f) = (job.id, job.f);
+            drop(state);
+            let res = run(f);
+            state = self.lock();
+            let job = state.jobs.iter_mut().find(|it| it.id == id).unwrap();
+            job.running -= 1;
+            if let Err(payload) = res {
+                job.panic.get_or_insert(payload);
+            }
+            self.left.notify_all();
+        }
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.shared.lock().shutdown = true;
+        self.shared.submitted.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Calls `f` as a part of a job, catching its panic.
+fn run(f: &(dyn Fn() + Sync)) -> thread::Result<()> {
+    IN_JOB.set(true);
+    let res = panic::catch_unwind(AssertUnwindSafe(f));
+    IN_JOB.set(false);
+    res
+}
+
+/// Lowers the priority of the current thread, and of no other. Where that
+/// isn't supported, or fails, the work just competes with everything else as
+/// usual.
+fn lower_priority() {
+    // On Linux, the niceness of a process id is that of the single thread.
+    #[cfg(target_os = "linux")]
+    unsafe {
+        sys::setpriority(sys::PRIO_PROCESS, sys::gettid() as u32, 19);
+    }
+    #[cfg(target_os = "macos")]
+    unsafe {
+        sys::pthread_set_qos_class_self_np(sys::QOS_CLASS_BACKGROUND, 0);
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    pub(super) const PRIO_PROCESS: i32 = 0;
+
+    extern "C" {
+        pub(super) fn gettid() -> i32;
+        pub(super) fn setpriority(which: i32, who: u32, prio: i32) -> i32;
+        #[cfg(test)]
+        pub(super) fn getpriority(which: i32, who: u32) -> i32;
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod sys {
+    pub(super) const QOS_CLASS_BACKGROUND: u32 = 0x09;
+
+    extern "C" {
+        pub(super) fn pthread_set_qos_class_self_np(qos_class: u32, relative_priority: i32) -> i32;
+    }
+}
+
+thread_local! {
+    /// Whether the current thread is running a job of [`Threads::in_parallel`].
+    static IN_JOB: Cell<bool> = const { Cell::new(false) };
+}
+
+#[test]
+fn test_nested() {
+    use std::{
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst},
+        time::{Duration, Instant},
+    };
+
+    let threads = Threads::new(NonZeroUsize::new(4).unwrap());
+    let (first, calls) = (AtomicBool::new(true), AtomicUsize::new(0));
+    let deadline = Instant::now() + Duration::from_secs(10);
+    threads.in_parallel(&|| {
+        if first.swap(false, SeqCst) {
+            // The sub-job waits for the workers done with the outer job, at
+            // least two besides the current thread, whether or not it's the
+            // calling one.
+            threads.in_parallel(&|| {
+                calls.fetch_add(1, SeqCst);
+                while calls.load(SeqCst) < 3 && Instant::now() < deadline {
+                    thread::yield_now();
+                }
+            })
+        }
+    });
+    assert!(calls.into_inner() >= 3);
+}
+
+#[test]
+fn test_panic() {
+    let threads = Threads::new(NonZeroUsize::new(4).unwrap());
+    let calls = std::sync::atomic::AtomicUsize::new(0);
+    let payload = panic::catch_unwind(AssertUnwindSafe(|| {
+        threads.in_parallel(&|| {
+            if calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 2 {
+                panic!("boom")
+            }
+        })
+    }))
+    .unwrap_err();
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+    assert_eq!(calls.into_inner(), 4);
+}
+
+#[test]
+fn test_reuse() {
+    let threads = Threads::new(NonZeroUsize::new(4).unwrap());
+    let ids = || {
+        let ids = Mutex::new(Vec::new());
+        threads.in_parallel(&|| ids.lock().unwrap().push(thread::current().id()));
+        let mut ids = ids.into_inner().unwrap();
+        ids.sort_by_key(|it| format!("{it:?}"));
+        ids
+    };
+    let first = ids();
+    assert_eq!(first.len(), 4);
+    assert_eq!(first, ids());
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_rayon() {
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+    let calls = AtomicUsize::new(0);
+    pool.install(|| {
+        in_rayon_pool(&|| {
+            calls.fetch_add(1, SeqCst);
+        })
+    });
+    assert_eq!(calls.into_inner(), 4);
+
+    let payload = panic::catch_unwind(AssertUnwindSafe(|| {
+        pool.install(|| in_rayon_pool(&|| panic!("boom")))
+    }))
+    .unwrap_err();
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_background() {
+    let threads = Threads::new(NonZeroUsize::new(4).unwrap()).background();
+    let nice = || unsafe {
+        (thread::current().id(), sys::getpriority(sys::PRIO_PROCESS, sys::gettid() as u32))
+    };
+    let current = nice();
+    let seen = Mutex::new(Vec::new());
+    threads.in_parallel(&|| seen.lock().unwrap().push(nice()));
+    let seen = seen.into_inner().unwrap();
+    assert_eq!(seen.len(), 4);
+    for (id, nice) in seen {
+        assert_eq!(nice, if id == current.0 { current.1 } else { 19 });
+    }
+    assert_eq!(nice(), current);
+}
@@ -0,0 +1,72 @@
+//! Events from the `no_std` crates, which have nowhere to print them
+//! themselves. The host sets a sink once, and maps the events to stderr, a
+//! server log, or whatever it has.
+#![no_std]
+
+use core::{
+    fmt, mem, ptr,
+    sync::atomic::{AtomicPtr, Ordering::SeqCst},
+};
+
+pub enum Event<'a> {
+    Start(Phase),
+    End(Phase),
+    /// Something suspicious in the scene, which still renders. The message
+    /// includes the line.
+    Warning {
+        line: Option<u32>,
+        message: &'a dyn fmt::Display,
+    },
+    /// An allocation of `requested` bytes failed with only `free` left.
+    Oom {
+        requested: usize,
+        free: usize,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Phase {
+    Parse,
+    BvhBuild,
+    Render,
+}
+
+static SINK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Sends all events to `sink`. Returns `false` if a sink is already set.
+pub fn set_sink(sink: fn(&Event<'_>)) -> bool {
+    SINK.compare_exchange(ptr::null_mut(), sink as *mut (), SeqCst, SeqCst).is_ok()
+}
+
+/// Passes `event` to the sink, if there is one.
+pub fn emit(event: Event<'_>) {
+    let sink = SINK.load(SeqCst);
+    if !sink.is_null() {
+        // SAFETY: only `set_sink` stores non-null values, which are `fn`s.
+        let sink = unsafe { mem::transmute::<*mut (), fn(&Event<'_>)>(sink) };
+        sink(&event)
+    }
+}
+
+impl fmt::Display for Event<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Start(phase) => write!(f, "{phase} started"),
+            Event::End(phase) => write!(f, "{phase} finished"),
+            Event::Warning { message, .. } => write!(f, "warning: {message}"),
+            Event::Oom { requested, free } => {
+                write!(f, "out of memory: {requested} bytes requested, {free} free")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Parse => "parsing",
+            Phase::BvhBuild => "building BVHs",
+            Phase::Render => "rendering",
+        })
+    }
+}
@@ -0,0 +1,80 @@
+//! Renderer for the browser, built with
+//! `cargo build -p crt-wasm --target wasm32-unknown-unknown --release` and
+//! bound to JS with `wasm-bindgen --target web`:
+//!
+//! ```js
+//! import init, { renderRgba } from "./crt_wasm.js";
+//!
+//! await init();
+//! const pixels = renderRgba(text, width, height);
+//! context.putImageData(new ImageData(pixels, width), 0, 0);
+//! ```
+//!
+//! Rendering runs on the calling thread, [`render::serial`], as there are no
+//! threads in `wasm32-unknown-unknown`. Run the module in a worker to keep the
+//! page responsive, or in several, each with its own instance, rendering its
+//! share of the tiles with `renderTileRgba`:
+//!
+//! ```js
+//! // In worker `index` of `count`, the other pixels are transparent.
+//! const tile = renderTileRgba(text, width, height, index, count);
+//! postMessage(tile, [tile.buffer]);
+//!
+//! // On the page, for each tile as it arrives.
+//! for (let i = 3; i < pixels.length; i += 4) {
+//!     if (tile[i] !== 0) pixels.set(tile.subarray(i - 3, i + 1), i - 3);
+//! }
+//! ```
+
+use render::rgb;
+use wasm_bindgen::{prelude::*, Clamped};
+
+/// Renders the scene `crt` into `width * height` pixels in the layout of
+/// `ImageData`, which the JS side gets as a `Uint8ClampedArray`. Errors are
+/// thrown as JS `Error`s with the message of the Rust one.
+#[wasm_bindgen(js_name = renderRgba)]
+pub fn render_rgba(crt: &str, width: u32, height: u32) -> Result<Clamped<Vec<u8>>, JsError> {
+    render_tiles(crt, [width, height], None)
+}
+
+/// Like [`render_rgba`], but renders only the pixels of the `index`-th of
+/// `count` sets of tiles, see [`render::Tiles`], leaving the others
+/// transparent.
+#[wasm_bindgen(js_name = renderTileRgba)]
+pub fn render_tile_rgba(
+    crt: &str,
+    width: u32,
+    height: u32,
+    index: u32,
+    count: u32,
+) -> Result<Clamped<Vec<u8>>, JsError> {
+    if index >= count {
+        return Err(JsError::new("tile index out of range"));
+    }
+    render_tiles(crt, [width, height], Some(render::Tiles { index, count }))
+}
+
+fn render_tiles(
+    crt: &str,
+    dim @ [width, height]: rgb::Idx,
+    tiles: Option<render::Tiles>,
+) -> Result<Clamped<Vec<u8>>, JsError> {
+    let n_pixels = width.checked_mul(height).filter(|&it| it > 0);
+    let n_pixels = n_pixels.ok_or_else(|| JsError::new("invalid image size"))?;
+
+    let estimate = render::mem_estimate(crt);
+    let mut mem = vec![0; estimate + estimate / 8 + 1024];
+    let mut pixels = vec![rgb::Color::default(); n_pixels as usize];
+    let mut buf = rgb::Buf::new(dim, &mut pixels);
+    let opts = render::Options { tiles, ..render::Options::default() };
+    render::render(crt, &mut mem, &opts, &render::serial, &mut buf)
+        .map_err(|err| JsError::new(&err.to_string()))?;
+
+    let mut res = Vec::with_capacity(pixels.len() * 4);
+    for (i, pixel) in pixels.iter().enumerate() {
+        let idx = [i as u32 % width, i as u32 / width];
+        let alpha = if tiles.is_none_or(|it| it.contains(width, idx)) { 255 } else { 0 };
+        res.extend_from_slice(&[pixel.r, pixel.g, pixel.b, alpha]);
+    }
+    Ok(Clamped(res))
+}